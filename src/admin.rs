@@ -0,0 +1,350 @@
+use crate::config::AdminConfig;
+use crate::engine::Input;
+use altusd::Exchange;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{watch, Mutex};
+
+/// The address of the admin server. It's only reachable from the host, since it has no
+/// authentication.
+const ADDR: &str = "127.0.0.1:9091";
+
+/// The maximum size of the head or of the body of a request.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// This type represents the set of the exchanges that were disabled by an operator.
+pub type DisabledExchanges = BTreeSet<Exchange>;
+
+/// This struct represents the state of the admin server, which is shared by the connections so
+/// that the changes are applied and audited one at a time.
+struct Admin {
+    audit_log: String,
+    disabled: DisabledExchanges,
+    disabled_tx: watch::Sender<DisabledExchanges>,
+    mpsc_tx: Sender<Input>,
+}
+
+/// This struct represents a line of the audit log. The times are in milliseconds since epoch.
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    time_ms: u64,
+    client: String,
+    exchange: &'static str,
+    enabled: bool,
+    /// Whether the exchange was enabled before the change, which is the same for a no-op.
+    was_enabled: bool,
+    /// The reason given by the operator in the body of the request, if any.
+    reason: Option<String>,
+}
+
+/// This enum represents the requests served by the admin server.
+#[derive(Debug, PartialEq)]
+enum Request {
+    /// `GET /exchanges`: get whether each exchange is enabled.
+    Status,
+    /// `POST /exchanges/{name}/enable` or `POST /exchanges/{name}/disable`.
+    Enable(Exchange, bool),
+}
+
+/// This function is responsible for running the admin server, through which an operator enables
+/// or disables the exchanges at runtime, e.g. during an incident. A disabled exchange is excluded
+/// from the index by the core engine right away, and its feed is disconnected. Every change is
+/// recorded in the audit log before it's applied.
+pub async fn run(
+    config: AdminConfig,
+    disabled_tx: watch::Sender<DisabledExchanges>,
+    mpsc_tx: Sender<Input>,
+) {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    tracing::info!("admin server started: {}", ADDR);
+    serve(listener, config, disabled_tx, mpsc_tx).await;
+}
+
+/// This function is responsible for accepting the connections of the admin server.
+async fn serve(
+    listener: TcpListener,
+    config: AdminConfig,
+    disabled_tx: watch::Sender<DisabledExchanges>,
+    mpsc_tx: Sender<Input>,
+) {
+    let disabled = disabled_tx.borrow().clone();
+    let admin = Arc::new(Mutex::new(Admin {
+        audit_log: config.audit_log,
+        disabled,
+        disabled_tx,
+        mpsc_tx,
+    }));
+
+    // Accept tcp connections in a loop. Each connection is handled in its owned spawned task.
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok((stream, addr)) => (stream, addr),
+            Err(error) => {
+                tracing::error!("failed to accept tcp connection: {}", error);
+                continue;
+            }
+        };
+        tokio::spawn(handle_connection(stream, addr, admin.clone()));
+    }
+}
+
+/// This function is responsible for handling a single HTTP connection, which carries a single
+/// request.
+async fn handle_connection(mut stream: TcpStream, addr: SocketAddr, admin: Arc<Mutex<Admin>>) {
+    let (head, body) = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(error) => {
+            tracing::warn!("failed to read http request: {}: {}", addr, error);
+            return;
+        }
+    };
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let (status, body) = match parse_request(method, path) {
+        Ok(Request::Status) => ("200 OK", admin.lock().await.status()),
+        Ok(Request::Enable(exchange, enabled)) => {
+            let reason = Some(body.trim().to_string()).filter(|reason| !reason.is_empty());
+            let mut admin = admin.lock().await;
+            match admin.enable(exchange, enabled, addr, reason).await {
+                Ok(()) => ("200 OK", admin.status()),
+                Err(error) => {
+                    tracing::error!("failed to write audit log: {}", error);
+                    let body = format!("failed to write audit log: {}\n", error);
+                    ("500 Internal Server Error", body)
+                }
+            }
+        }
+        Err(status) => (status, format!("{}\n", status)),
+    };
+
+    // Write the response and close the connection.
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    if let Err(error) = stream.write_all(response.as_bytes()).await {
+        tracing::warn!("failed to write http response: {}", error);
+    }
+}
+
+impl Admin {
+    /// Render whether each exchange is enabled as a JSON object, e.g. `{"binance":true,...}`.
+    fn status(&self) -> String {
+        let status: BTreeMap<_, _> = Exchange::ALL
+            .into_iter()
+            .map(|exchange| (exchange.name(), !self.disabled.contains(&exchange)))
+            .collect();
+        // Safe unwrap: a map of strings to booleans always serializes.
+        let mut status = serde_json::to_string(&status).unwrap();
+        status.push('\n');
+        status
+    }
+
+    /// Enable or disable an exchange, once the change is recorded in the audit log. The engine
+    /// is notified first, so that the prices still in flight from a disabled feed are ignored.
+    async fn enable(
+        &mut self,
+        exchange: Exchange,
+        enabled: bool,
+        client: SocketAddr,
+        reason: Option<String>,
+    ) -> std::io::Result<()> {
+        let record = AuditRecord {
+            time_ms: crate::engine::unix_millis().unwrap_or_default(),
+            client: client.to_string(),
+            exchange: exchange.name(),
+            enabled,
+            was_enabled: !self.disabled.contains(&exchange),
+            reason,
+        };
+        self.audit(&record)?;
+        let action = match enabled {
+            true => "enabled",
+            false => "disabled",
+        };
+        tracing::warn!(
+            "exchange {} by operator: {:?}: {}",
+            action,
+            exchange,
+            client
+        );
+
+        match enabled {
+            true => self.disabled.remove(&exchange),
+            false => self.disabled.insert(exchange),
+        };
+        if let Err(error) = self.mpsc_tx.send(Input::enable(exchange, enabled)).await {
+            tracing::error!("failed to send message in mpsc channel: {}", error);
+        }
+        // The feeds aren't running in replay mode, which is fine.
+        let _ = self.disabled_tx.send(self.disabled.clone());
+        Ok(())
+    }
+
+    /// Append a record to the audit log.
+    fn audit(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_log)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+/// This function is a helper to read the head and the body of an HTTP request. The body is read
+/// up to its `Content-Length`, if any.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<(String, String)> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let head_end = loop {
+        if let Some(index) = find(&request, b"\r\n\r\n") {
+            break index;
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        match stream.read(&mut buffer).await? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            read => request.extend_from_slice(&buffer[..read]),
+        }
+    };
+    let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST_SIZE {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    let mut body = request.split_off(head_end + 4);
+    while body.len() < content_length {
+        match stream.read(&mut buffer).await? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            read => body.extend_from_slice(&buffer[..read]),
+        }
+    }
+    body.truncate(content_length);
+    Ok((head, String::from_utf8_lossy(&body).to_string()))
+}
+
+/// This function is a helper to find the position of a sequence of bytes in a buffer.
+fn find(buffer: &[u8], bytes: &[u8]) -> Option<usize> {
+    buffer
+        .windows(bytes.len())
+        .position(|window| window == bytes)
+}
+
+/// This function is a helper to route a request from its method and path. It returns the status
+/// of the response if the request isn't served.
+fn parse_request(method: &str, path: &str) -> Result<Request, &'static str> {
+    let parts: Vec<_> = path.trim_matches('/').split('/').collect();
+    let request = match parts.as_slice() {
+        ["exchanges"] => (Request::Status, "GET"),
+        ["exchanges", name, action @ ("enable" | "disable")] => {
+            let exchange = match Exchange::try_from(name.to_string()) {
+                Ok(exchange) => exchange,
+                Err(_) => return Err("404 Not Found"),
+            };
+            (Request::Enable(exchange, *action == "enable"), "POST")
+        }
+        _ => return Err("404 Not Found"),
+    };
+    match request {
+        (request, expected) if method == expected => Ok(request),
+        _ => Err("405 Method Not Allowed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn admin_routes() {
+        assert_eq!(parse_request("GET", "/exchanges"), Ok(Request::Status));
+        assert_eq!(
+            parse_request("POST", "/exchanges/okx/disable"),
+            Ok(Request::Enable(Exchange::Okx, false))
+        );
+        assert_eq!(
+            parse_request("POST", "/exchanges/htx/enable/"),
+            Ok(Request::Enable(Exchange::Htx, true))
+        );
+        assert_eq!(
+            parse_request("GET", "/exchanges/okx/disable"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(
+            parse_request("POST", "/exchanges/ftx/disable"),
+            Err("404 Not Found")
+        );
+        assert_eq!(parse_request("GET", "/metrics"), Err("404 Not Found"));
+    }
+
+    #[tokio::test]
+    async fn disable_exchange() {
+        let audit_log = std::env::temp_dir().join(format!("altusd-audit-{}", std::process::id()));
+        let config = AdminConfig {
+            audit_log: audit_log.display().to_string(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (disabled_tx, mut disabled_rx) = watch::channel(DisabledExchanges::new());
+        let (mpsc_tx, mut mpsc_rx) = tokio::sync::mpsc::channel(10);
+        tokio::spawn(serve(listener, config, disabled_tx, mpsc_tx));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("http://{}/exchanges/okx/disable", addr))
+            .body("stale prices")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let status: HashMap<String, bool> = response.json().await.unwrap();
+        assert_eq!(status.len(), Exchange::ALL.len());
+        assert!(!status["okx"]);
+        assert!(status["htx"]);
+
+        // Both the engine and the feeds are notified.
+        match mpsc_rx.recv().await {
+            Some(Input::Enable(Exchange::Okx, false)) => {}
+            input => panic!("unexpected input: {:?}", input),
+        }
+        disabled_rx.changed().await.unwrap();
+        assert!(disabled_rx.borrow().contains(&Exchange::Okx));
+
+        let url = format!("http://{}/exchanges/ftx/disable", addr);
+        let response = client.post(url).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        let audit = std::fs::read_to_string(&audit_log).unwrap();
+        std::fs::remove_file(&audit_log).unwrap();
+        let records: Vec<serde_json::Value> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["exchange"], "okx");
+        assert_eq!(records[0]["enabled"], false);
+        assert_eq!(records[0]["was_enabled"], true);
+        assert_eq!(records[0]["reason"], "stale prices");
+    }
+}
@@ -0,0 +1,116 @@
+use super::{Protocol, Registry, Session, Tick};
+use altusd::Exchange;
+use serde_json::{json, Value};
+
+/// The Binance protocol. The streams are subscribed by the URL of the combined stream, and the
+/// depth streams are synchronized with the REST book snapshots. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#websocket-market-streams
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// This handler subscribes the streams of the URL, e.g. `ethusdt@trade`.
+fn connect(session: &mut Session) -> Vec<Value> {
+    let streams = super::query_param(&session.path, "streams").unwrap_or_default();
+    let streams: Vec<String> = streams.split('/').map(str::to_string).collect();
+    for stream in streams {
+        let subscribed = match stream.split_once('@') {
+            Some((symbol, channel)) => session.subscribe(channel, &symbol.to_uppercase()),
+            None => None,
+        };
+        if subscribed.is_none() {
+            tracing::warn!("unexpected binance stream: {}", stream);
+        }
+    }
+    Vec::new()
+}
+
+/// This handler ignores the messages, since the streams are subscribed by the URL.
+fn request(_: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    tracing::warn!("unexpected binance request: {}", message);
+    Vec::new()
+}
+
+/// This handler sends the depth updates, book tickers, and trades of the subscribed streams.
+/// The tick number is the update ID of the books and the ID of the trades.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let mut messages = Vec::new();
+    for subscription in session.subscribed() {
+        let market = match tick.markets.get(&subscription.coin) {
+            Some(market) => market,
+            None => continue,
+        };
+        let symbol = subscription.symbol;
+        let stream = format!("{}@{}", symbol.to_lowercase(), subscription.channel);
+        let data = match subscription.channel.as_str() {
+            "depth@100ms" => {
+                let mut bids = Vec::new();
+                let mut asks = Vec::new();
+                if let Some((bid, ask)) = session.update_book(&symbol, market) {
+                    bids.push(json!([bid, "0.00000000"]));
+                    asks.push(json!([ask, "0.00000000"]));
+                }
+                bids.push(json!([market.bid, market.quantity]));
+                asks.push(json!([market.ask, market.quantity]));
+                json!({
+                    "e": "depthUpdate",
+                    "E": tick.time,
+                    "s": symbol,
+                    "U": tick.number,
+                    "u": tick.number,
+                    "b": bids,
+                    "a": asks,
+                })
+            }
+            "bookTicker" => json!({
+                "u": tick.number,
+                "s": symbol,
+                "b": market.bid,
+                "B": market.quantity,
+                "a": market.ask,
+                "A": market.quantity,
+            }),
+            "trade" => json!({
+                "e": "trade",
+                "E": tick.time,
+                "s": symbol,
+                "t": tick.number,
+                "p": market.price,
+                "q": market.trade_quantity,
+                "T": tick.time,
+                "m": false,
+            }),
+            _ => continue,
+        };
+        messages.push(json!({ "stream": stream, "data": data }));
+    }
+    messages
+}
+
+/// This handler serves the book snapshots and the 24-hour tickers.
+fn rest(registry: &Registry, tick: &Tick, path: &str) -> Option<Value> {
+    let symbol = super::query_param(path, "symbol")?;
+    let market = tick
+        .markets
+        .get(&registry.coin(Exchange::Binance, symbol)?)?;
+    if path.starts_with("/api/v3/depth?") {
+        Some(json!({
+            "lastUpdateId": tick.number,
+            "bids": [[market.bid, market.quantity]],
+            "asks": [[market.ask, market.quantity]],
+        }))
+    } else if path.starts_with("/api/v3/ticker/24hr?") {
+        Some(json!({
+            "symbol": symbol,
+            "lastPrice": market.price,
+            "bidPrice": market.bid,
+            "askPrice": market.ask,
+        }))
+    } else {
+        None
+    }
+}
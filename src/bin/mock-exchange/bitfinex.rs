@@ -0,0 +1,80 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The Bitfinex protocol. Each symbol is subscribed separately, and its ticker is sent on the
+/// channel ID assigned in the response. See this link for reference:
+/// https://docs.bitfinex.com/reference/ws-public-ticker
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// This struct represents a subscribe request.
+#[derive(Deserialize)]
+struct Request<'a> {
+    event: &'a str,
+    channel: &'a str,
+    symbol: &'a str,
+}
+
+/// This handler sends the info message that starts every connection.
+fn connect(_: &mut Session) -> Vec<Value> {
+    vec![json!({ "event": "info", "version": 2, "platform": { "status": 1 } })]
+}
+
+/// This handler subscribes a ticker, and assigns it a channel ID.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) if request.event == "subscribe" => request,
+        _ => {
+            tracing::warn!("unexpected bitfinex request: {}", message);
+            return Vec::new();
+        }
+    };
+    match session.subscribe(request.channel, request.symbol) {
+        Some(id) => vec![json!({
+            "event": "subscribed",
+            "channel": request.channel,
+            "chanId": id,
+            "symbol": request.symbol,
+            "pair": request.symbol.trim_start_matches('t'),
+        })],
+        None => vec![json!({ "event": "error", "msg": "symbol: invalid", "code": 10300 })],
+    }
+}
+
+/// This handler sends the tickers, whose fields are positional.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let subscriptions = session.subscriptions.iter();
+    subscriptions
+        .filter_map(|subscription| {
+            let market = tick.markets.get(&subscription.coin)?;
+            let price = super::number(&market.price);
+            let quantity = super::number(&market.quantity);
+            Some(json!([
+                subscription.id,
+                [
+                    super::number(&market.bid),
+                    quantity,
+                    super::number(&market.ask),
+                    quantity,
+                    0.0,
+                    0.0,
+                    price,
+                    super::number(&market.trade_quantity),
+                    price,
+                    price,
+                ],
+            ]))
+        })
+        .collect()
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
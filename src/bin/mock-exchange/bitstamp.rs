@@ -0,0 +1,106 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The channels of a currency pair, whose names are followed by the pair.
+const CHANNELS: [&str; 2] = ["live_trades", "order_book"];
+
+/// The Bitstamp protocol. Each channel of each currency pair is subscribed separately.
+/// See this link for reference: https://www.bitstamp.net/websocket/v2/
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// These structs represent a subscribe request.
+#[derive(Deserialize)]
+struct Request<'a> {
+    event: &'a str,
+    #[serde(borrow)]
+    data: RequestData<'a>,
+}
+
+#[derive(Deserialize)]
+struct RequestData<'a> {
+    channel: &'a str,
+}
+
+/// This handler sends nothing, since Bitstamp waits for the subscriptions.
+fn connect(_: &mut Session) -> Vec<Value> {
+    Vec::new()
+}
+
+/// This handler subscribes a channel, e.g. `live_trades_ethusd`.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) if request.event == "bts:subscribe" => request,
+        _ => {
+            tracing::warn!("unexpected bitstamp request: {}", message);
+            return Vec::new();
+        }
+    };
+    let channel = request.data.channel;
+    let subscribed = CHANNELS.iter().find_map(|name| {
+        let pair = channel.strip_prefix(name)?.strip_prefix('_')?;
+        session.subscribe(name, pair)
+    });
+    match subscribed {
+        Some(_) => vec![json!({
+            "event": "bts:subscription_succeeded",
+            "channel": channel,
+            "data": {},
+        })],
+        None => vec![json!({
+            "event": "bts:error",
+            "channel": "",
+            "data": { "code": null, "message": format!("Incorrect channel: {}", channel) },
+        })],
+    }
+}
+
+/// This handler sends the trades and the top of the order books.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let microtimestamp = (tick.time * 1000).to_string();
+    let subscriptions = session.subscriptions.iter();
+    subscriptions
+        .filter_map(|subscription| {
+            let market = tick.markets.get(&subscription.coin)?;
+            let channel = format!("{}_{}", subscription.channel, subscription.symbol);
+            match subscription.channel.as_str() {
+                "live_trades" => Some(json!({
+                    "event": "trade",
+                    "channel": channel,
+                    "data": {
+                        "id": tick.number,
+                        "timestamp": (tick.time / 1000).to_string(),
+                        "amount": super::number(&market.trade_quantity),
+                        "amount_str": market.trade_quantity,
+                        "price": super::number(&market.price),
+                        "price_str": market.price,
+                        "type": 0,
+                        "microtimestamp": microtimestamp,
+                    },
+                })),
+                "order_book" => Some(json!({
+                    "event": "data",
+                    "channel": channel,
+                    "data": {
+                        "timestamp": (tick.time / 1000).to_string(),
+                        "microtimestamp": microtimestamp,
+                        "bids": [[market.bid, market.quantity]],
+                        "asks": [[market.ask, market.quantity]],
+                    },
+                })),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
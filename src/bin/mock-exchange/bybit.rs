@@ -0,0 +1,116 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The Bybit protocol. All the topics are subscribed in a single request, which is rejected if
+/// any of them is invalid. See this link for reference:
+/// https://bybit-exchange.github.io/docs/v5/ws/connect
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// This struct represents a request, e.g. `subscribe` or `ping`.
+#[derive(Deserialize)]
+struct Request<'a> {
+    op: &'a str,
+    #[serde(borrow, default)]
+    args: Vec<&'a str>,
+}
+
+/// This handler sends nothing, since Bybit waits for the subscriptions.
+fn connect(_: &mut Session) -> Vec<Value> {
+    Vec::new()
+}
+
+/// This handler subscribes the topics, e.g. `tickers.ETHUSDT` and `orderbook.1.ETHUSDT`, or
+/// answers a ping.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) => request,
+        Err(_) => {
+            tracing::warn!("unexpected bybit request: {}", message);
+            return Vec::new();
+        }
+    };
+    match request.op {
+        "ping" => {
+            vec![json!({ "success": true, "ret_msg": "pong", "conn_id": "mock", "op": "pong" })]
+        }
+        "subscribe" => {
+            let invalid: Vec<&str> = request
+                .args
+                .iter()
+                .filter(|topic| {
+                    let subscribed = match topic.rsplit_once('.') {
+                        Some((channel, symbol)) => session.subscribe(channel, symbol),
+                        None => None,
+                    };
+                    subscribed.is_none()
+                })
+                .copied()
+                .collect();
+            let ret_msg = match invalid.is_empty() {
+                true => String::new(),
+                false => format!("Invalid symbol :{:?}", invalid),
+            };
+            vec![json!({
+                "success": invalid.is_empty(),
+                "ret_msg": ret_msg,
+                "conn_id": "mock",
+                "op": "subscribe",
+            })]
+        }
+        _ => {
+            tracing::warn!("unexpected bybit request: {}", message);
+            Vec::new()
+        }
+    }
+}
+
+/// This handler sends the tickers and the top of the order books.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let subscriptions = session.subscriptions.iter();
+    subscriptions
+        .filter_map(|subscription| {
+            let market = tick.markets.get(&subscription.coin)?;
+            let topic = format!("{}.{}", subscription.channel, subscription.symbol);
+            match subscription.channel.as_str() {
+                "tickers" => Some(json!({
+                    "topic": topic,
+                    "ts": tick.time,
+                    "type": "snapshot",
+                    "cs": tick.number,
+                    "data": {
+                        "symbol": subscription.symbol,
+                        "lastPrice": market.price,
+                        "highPrice24h": market.price,
+                        "lowPrice24h": market.price,
+                    },
+                })),
+                "orderbook.1" => Some(json!({
+                    "topic": topic,
+                    "ts": tick.time,
+                    "type": "snapshot",
+                    "data": {
+                        "s": subscription.symbol,
+                        "b": [[market.bid, market.quantity]],
+                        "a": [[market.ask, market.quantity]],
+                        "u": tick.number,
+                        "seq": tick.number,
+                    },
+                    "cts": tick.time,
+                })),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
@@ -0,0 +1,125 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The Coinbase protocol. All the channels of all the products are subscribed in a single
+/// request, which is rejected if any product is invalid. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-overview#subscribe
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// This struct represents a subscribe request.
+#[derive(Deserialize)]
+struct Request<'a> {
+    r#type: &'a str,
+    #[serde(borrow)]
+    product_ids: Vec<&'a str>,
+    #[serde(borrow)]
+    channels: Vec<&'a str>,
+}
+
+/// This handler sends nothing, since Coinbase waits for the subscriptions.
+fn connect(_: &mut Session) -> Vec<Value> {
+    Vec::new()
+}
+
+/// This handler subscribes the channels of the products, and lists them in the response.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) if request.r#type == "subscribe" => request,
+        _ => {
+            tracing::warn!("unexpected coinbase request: {}", message);
+            return Vec::new();
+        }
+    };
+    let exchange = session.exchange;
+    let invalid = request
+        .product_ids
+        .iter()
+        .find(|product_id| session.registry.coin(exchange, product_id).is_none());
+    if let Some(product_id) = invalid {
+        return vec![json!({
+            "type": "error",
+            "message": "Failed to subscribe",
+            "reason": format!("{} is not a valid product", product_id),
+        })];
+    }
+    for channel in &request.channels {
+        for product_id in &request.product_ids {
+            session.subscribe(channel, product_id);
+        }
+    }
+    let channels: Vec<Value> = request
+        .channels
+        .iter()
+        .map(|channel| json!({ "name": channel, "product_ids": request.product_ids }))
+        .collect();
+    vec![json!({ "type": "subscriptions", "channels": channels })]
+}
+
+/// This handler sends the changes of the order books, the trades, and the heartbeats.
+/// Each order book starts with a snapshot.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let time = super::rfc3339(tick.time);
+    let mut messages = Vec::new();
+    for subscription in session.subscribed() {
+        let market = match tick.markets.get(&subscription.coin) {
+            Some(market) => market,
+            None => continue,
+        };
+        let product_id = subscription.symbol;
+        let message = match subscription.channel.as_str() {
+            "level2_batch" => match session.update_book(&product_id, market) {
+                Some((bid, ask)) => json!({
+                    "type": "l2update",
+                    "product_id": product_id,
+                    "changes": [
+                        ["buy", bid, "0.00000000"],
+                        ["buy", market.bid, market.quantity],
+                        ["sell", ask, "0.00000000"],
+                        ["sell", market.ask, market.quantity],
+                    ],
+                    "time": time,
+                }),
+                None => json!({
+                    "type": "snapshot",
+                    "product_id": product_id,
+                    "bids": [[market.bid, market.quantity]],
+                    "asks": [[market.ask, market.quantity]],
+                }),
+            },
+            "matches" => json!({
+                "type": "match",
+                "trade_id": tick.number,
+                "maker_order_id": "mock",
+                "taker_order_id": "mock",
+                "side": "sell",
+                "size": market.trade_quantity,
+                "price": market.price,
+                "product_id": product_id,
+                "sequence": tick.number,
+                "time": time,
+            }),
+            "heartbeat" => json!({
+                "type": "heartbeat",
+                "last_trade_id": tick.number,
+                "product_id": product_id,
+                "sequence": tick.number,
+                "time": time,
+            }),
+            _ => continue,
+        };
+        messages.push(message);
+    }
+    messages
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
@@ -0,0 +1,80 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde_json::{json, Value};
+
+/// The Gemini protocol. Each symbol has its own connection, whose URL subscribes the top of the
+/// book and the trades. See this link for reference:
+/// https://docs.gemini.com/websocket-api/#market-data
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// This handler subscribes the symbol at the end of the path, e.g. `/v1/marketdata/ETHUSD`.
+fn connect(session: &mut Session) -> Vec<Value> {
+    let path = session.path.split('?').next().unwrap_or_default();
+    let symbol = path.rsplit('/').next().unwrap_or_default().to_string();
+    if session.subscribe("marketdata", &symbol).is_none() {
+        tracing::warn!("unexpected gemini symbol: {}", symbol);
+    }
+    Vec::new()
+}
+
+/// This handler ignores the messages, since the symbol is subscribed by the URL.
+fn request(_: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    tracing::warn!("unexpected gemini request: {}", message);
+    Vec::new()
+}
+
+/// This handler sends an update with a trade and the top of the book. The messages of the
+/// connection are numbered by their socket sequence, from 0.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let market = match session.subscriptions.first() {
+        Some(subscription) => match tick.markets.get(&subscription.coin) {
+            Some(market) => market,
+            None => return Vec::new(),
+        },
+        None => return Vec::new(),
+    };
+    let message = json!({
+        "type": "update",
+        "eventId": tick.number,
+        "socket_sequence": session.sequence,
+        "timestamp": tick.time / 1000,
+        "timestampms": tick.time,
+        "events": [
+            {
+                "type": "trade",
+                "tid": tick.number,
+                "price": market.price,
+                "amount": market.trade_quantity,
+                "makerSide": "ask",
+            },
+            {
+                "type": "change",
+                "side": "bid",
+                "price": market.bid,
+                "remaining": market.quantity,
+                "delta": market.quantity,
+                "reason": "top-of-book",
+            },
+            {
+                "type": "change",
+                "side": "ask",
+                "price": market.ask,
+                "remaining": market.quantity,
+                "delta": market.quantity,
+                "reason": "top-of-book",
+            },
+        ],
+    });
+    session.sequence += 1;
+    vec![message]
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
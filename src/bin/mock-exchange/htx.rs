@@ -0,0 +1,89 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The HTX protocol. Each ticker is subscribed separately, and all the messages are compressed.
+/// See this link for reference:
+/// https://www.htx.com/en-us/opend/newApiPages/?id=7ec4a4da-7773-11ed-9966-0242ac110003
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: true,
+};
+
+/// This struct represents a request, i.e. a subscription or a pong.
+#[derive(Deserialize)]
+struct Request<'a> {
+    sub: Option<&'a str>,
+    id: Option<&'a str>,
+    pong: Option<u64>,
+}
+
+/// This handler sends nothing, since HTX waits for the subscriptions.
+fn connect(_: &mut Session) -> Vec<Value> {
+    Vec::new()
+}
+
+/// This handler subscribes a ticker, e.g. `market.ethusdt.ticker`.
+fn request(session: &mut Session, tick: &Tick, message: &str) -> Vec<Value> {
+    let (topic, id) = match serde_json::from_str::<Request>(message) {
+        Ok(Request { pong: Some(_), .. }) => return Vec::new(),
+        Ok(Request {
+            sub: Some(topic),
+            id,
+            ..
+        }) => (topic, id),
+        _ => {
+            tracing::warn!("unexpected htx request: {}", message);
+            return Vec::new();
+        }
+    };
+    let symbol = topic
+        .strip_prefix("market.")
+        .and_then(|topic| topic.strip_suffix(".ticker"));
+    match symbol.and_then(|symbol| session.subscribe("ticker", symbol)) {
+        Some(_) => vec![json!({ "id": id, "status": "ok", "subbed": topic, "ts": tick.time })],
+        None => vec![json!({
+            "id": id,
+            "status": "error",
+            "err-code": "bad-request",
+            "err-msg": format!("invalid topic {}", topic),
+            "ts": tick.time,
+        })],
+    }
+}
+
+/// This handler sends the tickers, whose prices are numbers.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let subscriptions = session.subscriptions.iter();
+    subscriptions
+        .filter_map(|subscription| {
+            let market = tick.markets.get(&subscription.coin)?;
+            let price = super::number(&market.price);
+            let quantity = super::number(&market.quantity);
+            Some(json!({
+                "ch": format!("market.{}.ticker", subscription.symbol),
+                "ts": tick.time,
+                "tick": {
+                    "open": price,
+                    "high": price,
+                    "low": price,
+                    "close": price,
+                    "bid": super::number(&market.bid),
+                    "bidSize": quantity,
+                    "ask": super::number(&market.ask),
+                    "askSize": quantity,
+                    "lastPrice": price,
+                    "lastSize": super::number(&market.trade_quantity),
+                },
+            }))
+        })
+        .collect()
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
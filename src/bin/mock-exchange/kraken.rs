@@ -0,0 +1,320 @@
+use super::{Market, Protocol, Registry, Session, Tick};
+use altusd::Exchange;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The legacy Kraken protocol, whose messages are positional arrays. Each pair of a subscribe
+/// request is confirmed separately. See this link for reference:
+/// https://docs.kraken.com/websockets/
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// The v2 Kraken protocol, whose messages have named fields. The levels of the order books are
+/// numbers, formatted with the precisions of the `instrument` channel for their checksums.
+/// See this link for reference: https://docs.kraken.com/api/docs/websocket-v2/
+pub const V2_PROTOCOL: Protocol = Protocol {
+    connect: v2_connect,
+    request: v2_request,
+    tick: v2_tick,
+    rest,
+    gzip: false,
+};
+
+/// These structs represent a v1 request, e.g. `subscribe` or `ping`.
+#[derive(Deserialize)]
+struct Request<'a> {
+    event: &'a str,
+    #[serde(borrow, default)]
+    pair: Vec<&'a str>,
+    subscription: Option<Value>,
+}
+
+/// These structs represent a v2 request, e.g. `subscribe` or `ping`.
+#[derive(Deserialize)]
+struct V2Request<'a> {
+    method: &'a str,
+    #[serde(borrow)]
+    params: Option<V2RequestParams<'a>>,
+}
+
+#[derive(Deserialize)]
+struct V2RequestParams<'a> {
+    channel: &'a str,
+    #[serde(borrow, default)]
+    symbol: Vec<&'a str>,
+    depth: Option<u64>,
+}
+
+/// This handler sends the system status that starts every v1 connection.
+fn connect(_: &mut Session) -> Vec<Value> {
+    vec![json!({
+        "connectionID": 1,
+        "event": "systemStatus",
+        "status": "online",
+        "version": "1.9.0",
+    })]
+}
+
+/// This handler subscribes the `book` or `trade` channel of the pairs, or answers a ping.
+/// The name of a book channel includes its depth, e.g. `book-100`.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) => request,
+        Err(_) => {
+            tracing::warn!("unexpected kraken request: {}", message);
+            return Vec::new();
+        }
+    };
+    let subscription = request.subscription.unwrap_or_default();
+    let channel = match (request.event, subscription["name"].as_str()) {
+        ("ping", _) => return vec![json!({ "event": "pong" })],
+        ("subscribe", Some("book")) => {
+            format!("book-{}", subscription["depth"].as_u64().unwrap_or(10))
+        }
+        ("subscribe", Some("trade")) => "trade".to_string(),
+        _ => {
+            tracing::warn!("unexpected kraken request: {}", message);
+            return Vec::new();
+        }
+    };
+    let pairs = request.pair.iter();
+    pairs
+        .map(|pair| match session.subscribe(&channel, pair) {
+            Some(id) => json!({
+                "channelID": id,
+                "channelName": channel,
+                "event": "subscriptionStatus",
+                "pair": pair,
+                "status": "subscribed",
+                "subscription": subscription,
+            }),
+            None => json!({
+                "errorMessage": format!("Currency pair not supported {}", pair),
+                "event": "subscriptionStatus",
+                "pair": pair,
+                "status": "error",
+                "subscription": subscription,
+            }),
+        })
+        .collect()
+}
+
+/// This handler sends the changes of the order books with their checksums, and the trades.
+/// Each order book starts with a snapshot.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let time = format!("{}.{:03}000", tick.time / 1000, tick.time % 1000);
+    let mut messages = Vec::new();
+    for subscription in session.subscribed() {
+        let market = match tick.markets.get(&subscription.coin) {
+            Some(market) => market,
+            None => continue,
+        };
+        let (id, channel, pair) = (subscription.id, subscription.channel, subscription.symbol);
+        let ask = [market.ask.as_str(), market.quantity.as_str(), time.as_str()];
+        let bid = [market.bid.as_str(), market.quantity.as_str(), time.as_str()];
+        let message = if channel == "trade" {
+            let trade = [&market.price, &market.trade_quantity, &time, "b", "m", ""];
+            json!([id, [trade], channel, pair])
+        } else {
+            match session.update_book(&pair, market) {
+                Some((previous_bid, previous_ask)) => {
+                    let asks = [[&previous_ask, "0.00000000", &time], ask];
+                    let bids = [[&previous_bid, "0.00000000", &time], bid];
+                    let checksum = checksum(market).to_string();
+                    json!([id, { "a": asks }, { "b": bids, "c": checksum }, channel, pair])
+                }
+                None => json!([id, { "as": [ask], "bs": [bid] }, channel, pair]),
+            }
+        };
+        messages.push(message);
+    }
+    messages
+}
+
+/// This handler sends the status that starts every v2 connection.
+fn v2_connect(_: &mut Session) -> Vec<Value> {
+    vec![json!({
+        "channel": "status",
+        "type": "update",
+        "data": [{ "system": "online", "api_version": "v2", "version": "2.0.0" }],
+    })]
+}
+
+/// This handler subscribes the `instrument`, `book`, or `trade` channel, or answers a ping.
+/// The `instrument` channel is confirmed without symbol, and its snapshot lists the precisions
+/// of all our pairs.
+fn v2_request(session: &mut Session, tick: &Tick, message: &str) -> Vec<Value> {
+    let time = super::rfc3339(tick.time);
+    let (method, params) = match serde_json::from_str::<V2Request>(message) {
+        Ok(V2Request { method: "ping", .. }) => {
+            return vec![json!({ "method": "pong", "time_in": time, "time_out": time })]
+        }
+        Ok(V2Request {
+            method: method @ "subscribe",
+            params: Some(params),
+        }) => (method, params),
+        _ => {
+            tracing::warn!("unexpected kraken request: {}", message);
+            return Vec::new();
+        }
+    };
+
+    if params.channel == "instrument" {
+        let registry = &session.registry;
+        let pairs: Vec<Value> = registry
+            .symbols(Exchange::Kraken)
+            .filter_map(|(coin, symbol)| {
+                let market = tick.markets.get(&coin)?;
+                Some(json!({
+                    "symbol": symbol,
+                    "quote": "USD",
+                    "status": "online",
+                    "price_precision": market.decimals,
+                    "qty_precision": super::QUANTITY_DECIMALS,
+                }))
+            })
+            .collect();
+        return vec![
+            json!({
+                "method": method,
+                "result": { "channel": "instrument", "snapshot": true },
+                "success": true,
+                "time_in": time,
+                "time_out": time,
+            }),
+            json!({
+                "channel": "instrument",
+                "type": "snapshot",
+                "data": { "assets": [], "pairs": pairs },
+            }),
+        ];
+    }
+
+    let symbols = params.symbol.iter();
+    symbols
+        .map(|symbol| match session.subscribe(params.channel, symbol) {
+            Some(_) => json!({
+                "method": method,
+                "result": {
+                    "channel": params.channel,
+                    "symbol": symbol,
+                    "depth": params.depth,
+                    "snapshot": true,
+                },
+                "success": true,
+                "time_in": time,
+                "time_out": time,
+            }),
+            None => json!({
+                "error": "Currency pair not supported",
+                "method": method,
+                "success": false,
+                "symbol": symbol,
+                "time_in": time,
+                "time_out": time,
+            }),
+        })
+        .collect()
+}
+
+/// This handler sends the changes of the order books with their checksums, and the trades.
+/// Each order book starts with a snapshot.
+fn v2_tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let time = super::rfc3339(tick.time);
+    let mut messages = Vec::new();
+    for subscription in session.subscribed() {
+        let market = match tick.markets.get(&subscription.coin) {
+            Some(market) => market,
+            None => continue,
+        };
+        let symbol = subscription.symbol;
+        let quantity = super::number(&market.quantity);
+        let message = match subscription.channel.as_str() {
+            "trade" => json!({
+                "channel": "trade",
+                "type": "update",
+                "data": [{
+                    "symbol": symbol,
+                    "side": "buy",
+                    "price": super::number(&market.price),
+                    "qty": super::number(&market.trade_quantity),
+                    "ord_type": "market",
+                    "trade_id": tick.number,
+                    "timestamp": time,
+                }],
+            }),
+            "book" => {
+                let mut asks = Vec::new();
+                let mut bids = Vec::new();
+                let previous = session.update_book(&symbol, market);
+                if let Some((previous_bid, previous_ask)) = &previous {
+                    asks.push(json!({ "price": super::number(previous_ask), "qty": 0.0 }));
+                    bids.push(json!({ "price": super::number(previous_bid), "qty": 0.0 }));
+                }
+                asks.push(json!({ "price": super::number(&market.ask), "qty": quantity }));
+                bids.push(json!({ "price": super::number(&market.bid), "qty": quantity }));
+                let r#type = match previous {
+                    Some(_) => "update",
+                    None => "snapshot",
+                };
+                json!({
+                    "channel": "book",
+                    "type": r#type,
+                    "data": [{
+                        "symbol": symbol,
+                        "bids": bids,
+                        "asks": asks,
+                        "checksum": checksum(market),
+                        "timestamp": time,
+                    }],
+                })
+            }
+            _ => continue,
+        };
+        messages.push(message);
+    }
+    messages
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
+
+/// This function is a helper to compute the CRC32 checksum of an order book with a single level
+/// on each side, whose numbers are formatted without decimal point and leading zeros.
+/// See this link for reference: https://docs.kraken.com/websockets/#book-checksum
+fn checksum(market: &Market) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for number in [&market.ask, &market.quantity, &market.bid, &market.quantity] {
+        let digits = number.replace('.', "");
+        hasher.update(digits.trim_start_matches('0').as_bytes());
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_checksum() {
+        // The ask comes first, and each number is stripped of its decimal point and leading zeros.
+        let market = Market {
+            price: "3000.15".to_string(),
+            bid: "3000.10".to_string(),
+            ask: "3000.20".to_string(),
+            quantity: "1.00000000".to_string(),
+            trade_quantity: "0.00100000".to_string(),
+            decimals: 2,
+        };
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(b"300020100000000300010100000000");
+        assert_eq!(checksum(&market), hasher.finalize());
+    }
+}
@@ -0,0 +1,673 @@
+//! A mock exchange server to develop and test altusd offline.
+//!
+//! It speaks the websocket protocol of every supported exchange on localhost, one port per
+//! exchange, and streams the same scripted or random-walk prices on all of them. It also serves
+//! the REST endpoints needed by the feeds, i.e. the Binance book snapshots and tickers, and the
+//! circulating supplies.
+
+mod binance;
+mod bitfinex;
+mod bitstamp;
+mod bybit;
+mod coinbase;
+mod gemini;
+mod htx;
+mod kraken;
+mod okx;
+
+use altusd::{Coin, Exchange};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The environment variable that contains the path of the config file.
+/// If it isn't set, the default config is used.
+const CONFIG_PATH_VAR: &str = "MOCK_EXCHANGE_CONFIG";
+
+/// The instrument registry of altusd, which gives the symbols of the altcoins on each exchange.
+const INSTRUMENTS: &str = include_str!("../../instruments.toml");
+
+/// The path of the Coinbase supply API, which is served on the base port.
+const SUPPLY_PATH: &str = "/api/v2/assets/search";
+
+/// The path of the CoinGecko supply API, which is served on the base port too.
+const COINGECKO_SUPPLY_PATH: &str = "/api/v3/coins/markets";
+
+/// The distance between the mid price and the best bid or ask, relative to the mid price.
+const HALF_SPREAD: f64 = 0.0005;
+
+/// The notional in USD of each level of the order books, which covers the depth-weighted mid.
+const LEVEL_NOTIONAL: f64 = 1_000_000.0;
+
+/// The number of decimals of the quantities.
+pub const QUANTITY_DECIMALS: usize = 8;
+
+/// This struct represents the config of the mock exchange, which is loaded from a TOML file.
+/// Every field is optional and falls back to its default value.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    /// The address on which the servers listen.
+    host: String,
+    /// The port of the supply API. Each exchange listens on one of the following ports, in the
+    /// order of `Exchange::ALL`.
+    base_port: u16,
+    /// The interval between two price updates.
+    tick_interval_ms: u64,
+    /// The maximum change of a random-walk price at every tick, relative to the price.
+    volatility: f64,
+    /// The initial price, the circulating supply, and the optional script of each altcoin.
+    coins: BTreeMap<Coin, CoinConfig>,
+}
+
+/// This struct represents the config of an altcoin. If a script is set, its prices are streamed
+/// in a loop, one per tick. Otherwise, the price follows a random walk from the initial price.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CoinConfig {
+    price: f64,
+    supply: f64,
+    #[serde(default)]
+    script: Vec<f64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let coins = [
+            (Coin::ADA, 0.35, 35_000_000_000.0),
+            (Coin::DOGE, 0.08, 140_000_000_000.0),
+            (Coin::DOT, 6.0, 1_300_000_000.0),
+            (Coin::ETH, 3000.0, 120_000_000.0),
+            (Coin::SOL, 20.0, 400_000_000.0),
+        ];
+        let coins = coins.into_iter().map(|(coin, price, supply)| {
+            let script = Vec::new();
+            (
+                coin,
+                CoinConfig {
+                    price,
+                    supply,
+                    script,
+                },
+            )
+        });
+        Self {
+            host: "127.0.0.1".to_string(),
+            base_port: 9000,
+            tick_interval_ms: 500,
+            volatility: 0.001,
+            coins: coins.collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from the file pointed to by the `MOCK_EXCHANGE_CONFIG` environment variable.
+    pub fn load() -> Result<Self, String> {
+        let path = match std::env::var(CONFIG_PATH_VAR) {
+            Ok(path) => path,
+            Err(_) => return Ok(Self::default()),
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|error| format!("failed to read {}: {}", path, error))?;
+        let config: Self = toml::from_str(&content)
+            .map_err(|error| format!("failed to parse {}: {}", path, error))?;
+        match Coin::ALL
+            .iter()
+            .find(|coin| !config.coins.contains_key(coin))
+        {
+            Some(coin) => Err(format!("missing coin in {}: {:?}", path, coin)),
+            None => Ok(config),
+        }
+    }
+}
+
+/// This struct represents the entry of an altcoin in the instrument registry. The aliases are
+/// ignored, since the mock always answers with the symbols of the subscriptions.
+#[derive(Debug, Deserialize)]
+struct Instrument {
+    /// The identifier of the altcoin at each supply provider.
+    supply: BTreeMap<String, String>,
+    symbols: BTreeMap<Exchange, String>,
+}
+
+/// This struct maps the symbols of each exchange to the altcoins, and vice versa.
+#[derive(Debug)]
+pub struct Registry {
+    instruments: BTreeMap<Coin, Instrument>,
+    coins: HashMap<(Exchange, String), Coin>,
+}
+
+impl Registry {
+    /// Constructor from the instrument registry of altusd.
+    pub fn load() -> Result<Self, String> {
+        let instruments: BTreeMap<Coin, Instrument> = toml::from_str(INSTRUMENTS)
+            .map_err(|error| format!("failed to parse instruments: {}", error))?;
+        let coins = instruments.iter().flat_map(|(coin, instrument)| {
+            let symbols = instrument.symbols.iter();
+            symbols.map(move |(exchange, symbol)| ((*exchange, symbol.clone()), *coin))
+        });
+        let coins = coins.collect();
+        Ok(Self { instruments, coins })
+    }
+
+    /// Get the altcoin of a symbol on an exchange.
+    pub fn coin(&self, exchange: Exchange, symbol: &str) -> Option<Coin> {
+        self.coins.get(&(exchange, symbol.to_string())).copied()
+    }
+
+    /// Get the symbols of the altcoins on an exchange.
+    pub fn symbols(&self, exchange: Exchange) -> impl Iterator<Item = (Coin, &str)> {
+        let instruments = self.instruments.iter();
+        instruments.filter_map(move |(coin, instrument)| {
+            Some((*coin, instrument.symbols.get(&exchange)?.as_str()))
+        })
+    }
+}
+
+/// This struct represents the market of an altcoin at a given tick, which is the same on every
+/// exchange. The order book has a single level on each side, whose quantity is fixed.
+/// The prices and quantities are formatted with a fixed number of decimals.
+#[derive(Clone, Debug)]
+pub struct Market {
+    pub price: String,
+    pub bid: String,
+    pub ask: String,
+    pub quantity: String,
+    pub trade_quantity: String,
+    pub decimals: usize,
+}
+
+/// This struct represents a tick, i.e. an update of the markets of all the altcoins.
+/// The tick number serves as the update ID and trade ID on every exchange.
+#[derive(Clone, Debug, Default)]
+pub struct Tick {
+    pub number: u64,
+    pub time: u64,
+    pub markets: BTreeMap<Coin, Market>,
+}
+
+/// This struct represents a channel subscribed on a connection, e.g. the trades of ETH.
+/// The ID is assigned by the exchanges that map their messages to channel IDs.
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    pub channel: String,
+    pub coin: Coin,
+    pub symbol: String,
+    pub id: u64,
+}
+
+/// This struct represents the context of a websocket connection that is passed to the handlers
+/// of an exchange protocol.
+pub struct Session {
+    pub exchange: Exchange,
+    /// The path of the websocket handshake, including its query.
+    pub path: String,
+    pub registry: Arc<Registry>,
+    pub subscriptions: Vec<Subscription>,
+    /// The best bid and ask last sent for each symbol, which are removed by the next update of
+    /// its order book. A symbol without levels gets a snapshot.
+    pub books: HashMap<String, (String, String)>,
+    /// The number of messages sent, for exchanges that number them.
+    pub sequence: u64,
+}
+
+impl Session {
+    /// Subscribe a channel of the given symbol, if it belongs to one of our altcoins.
+    /// The ID of the subscription is its position, starting from 1.
+    pub fn subscribe(&mut self, channel: &str, symbol: &str) -> Option<u64> {
+        let coin = self.registry.coin(self.exchange, symbol)?;
+        let id = self.subscriptions.len() as u64 + 1;
+        self.subscriptions.push(Subscription {
+            channel: channel.to_string(),
+            coin,
+            symbol: symbol.to_string(),
+            id,
+        });
+        Some(id)
+    }
+
+    /// Get a copy of the subscriptions, so that the session can be updated while iterating.
+    pub fn subscribed(&self) -> Vec<Subscription> {
+        self.subscriptions.clone()
+    }
+
+    /// Record the levels of an order book sent for a tick, and get the previous ones if any.
+    pub fn update_book(&mut self, symbol: &str, market: &Market) -> Option<(String, String)> {
+        let levels = (market.bid.clone(), market.ask.clone());
+        self.books.insert(symbol.to_string(), levels)
+    }
+}
+
+/// This struct represents the protocol of an exchange, as a set of handlers that build the
+/// messages sent to a connection. The handlers can't fail: an invalid request gets the error
+/// message of the exchange.
+pub struct Protocol {
+    /// The messages sent right after the websocket handshake.
+    pub connect: fn(&mut Session) -> Vec<Value>,
+    /// The replies to a message of the client, e.g. a subscribe request or a ping.
+    pub request: fn(&mut Session, &Tick, &str) -> Vec<Value>,
+    /// The messages of the subscribed channels at every tick.
+    pub tick: fn(&mut Session, &Tick) -> Vec<Value>,
+    /// The response to a REST request given its path and query, if the exchange serves it.
+    pub rest: fn(&Registry, &Tick, &str) -> Option<Value>,
+    /// Whether the messages are sent as gzip-compressed binary frames.
+    pub gzip: bool,
+}
+
+/// This function is a helper to get the protocol spoken on a websocket path of an exchange.
+pub fn protocol(exchange: Exchange, path: &str) -> &'static Protocol {
+    match exchange {
+        Exchange::Binance => &binance::PROTOCOL,
+        Exchange::Bitfinex => &bitfinex::PROTOCOL,
+        Exchange::Bitstamp => &bitstamp::PROTOCOL,
+        Exchange::Bybit => &bybit::PROTOCOL,
+        Exchange::Coinbase => &coinbase::PROTOCOL,
+        Exchange::Gemini => &gemini::PROTOCOL,
+        Exchange::Htx => &htx::PROTOCOL,
+        Exchange::Kraken if path.starts_with("/v2") => &kraken::V2_PROTOCOL,
+        Exchange::Kraken => &kraken::PROTOCOL,
+        Exchange::Okx => &okx::PROTOCOL,
+    }
+}
+
+/// This struct holds the state shared by all the connections.
+struct Context {
+    config: Config,
+    registry: Arc<Registry>,
+    tick_rx: watch::Receiver<Tick>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            tracing::error!("failed to load config: {}", error);
+            return;
+        }
+    };
+    let registry = match Registry::load() {
+        Ok(registry) => Arc::new(registry),
+        Err(error) => {
+            tracing::error!("failed to load registry: {}", error);
+            return;
+        }
+    };
+
+    // This watch channel is used to broadcast the ticks to all the connections.
+    let (tick_tx, tick_rx) = watch::channel(Tick::default());
+    let context = Arc::new(Context {
+        config,
+        registry,
+        tick_rx,
+    });
+    tokio::spawn(run_ticker(context.clone(), tick_tx));
+
+    // The supply API listens on the base port, and each exchange on the following ones.
+    let mut servers = vec![tokio::spawn(run_server(None, context.clone()))];
+    for exchange in Exchange::ALL {
+        servers.push(tokio::spawn(run_server(Some(exchange), context.clone())));
+    }
+    futures::future::join_all(servers).await;
+}
+
+/// This function is responsible for updating the markets of all the altcoins at every tick.
+/// The number of decimals and the quantity of each altcoin are set by its initial price.
+async fn run_ticker(context: Arc<Context>, tick_tx: watch::Sender<Tick>) {
+    let config = &context.config;
+    let mut interval = tokio::time::interval(Duration::from_millis(config.tick_interval_ms));
+    let mut prices: BTreeMap<Coin, f64> = config
+        .coins
+        .iter()
+        .map(|(coin, coin_config)| (*coin, coin_config.price))
+        .collect();
+    for number in 1.. {
+        interval.tick().await;
+        let mut markets = BTreeMap::new();
+        for (coin, coin_config) in &config.coins {
+            let price = prices.entry(*coin).or_insert(coin_config.price);
+            if !coin_config.script.is_empty() {
+                let index = (number - 1) as usize % coin_config.script.len();
+                *price = coin_config.script[index];
+            } else if number > 1 {
+                let change = rand::thread_rng().gen_range(-1.0..=1.0) * config.volatility;
+                *price *= 1.0 + change;
+            }
+            markets.insert(*coin, market(*price, coin_config.price));
+        }
+        let tick = Tick {
+            number,
+            time: unix_millis(),
+            markets,
+        };
+        if tick_tx.send(tick).is_err() {
+            tracing::error!("failed to send tick in watch channel");
+            return;
+        }
+    }
+}
+
+/// This function is a helper to build the market of an altcoin around a mid price.
+pub fn market(price: f64, initial_price: f64) -> Market {
+    let magnitude = initial_price.max(f64::MIN_POSITIVE).log10().floor() as i64;
+    let decimals = (5 - magnitude).clamp(2, 8) as usize;
+    let quantity = LEVEL_NOTIONAL / initial_price;
+    Market {
+        price: format!("{:.*}", decimals, price),
+        bid: format!("{:.*}", decimals, price * (1.0 - HALF_SPREAD)),
+        ask: format!("{:.*}", decimals, price * (1.0 + HALF_SPREAD)),
+        quantity: format!("{:.*}", QUANTITY_DECIMALS, quantity),
+        trade_quantity: format!("{:.*}", QUANTITY_DECIMALS, quantity / 1000.0),
+        decimals,
+    }
+}
+
+/// This function is responsible for running the server of an exchange, or the supply API.
+async fn run_server(exchange: Option<Exchange>, context: Arc<Context>) {
+    let offset = exchange.and_then(|exchange| Exchange::ALL.iter().position(|e| *e == exchange));
+    let port = context.config.base_port + offset.map_or(0, |offset| offset as u16 + 1);
+    let addr = format!("{}:{}", context.config.host, port);
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            tracing::error!("failed to bind {}: {}", addr, error);
+            return;
+        }
+    };
+    let name = exchange.map_or("supply", |exchange| exchange.name());
+    tracing::info!("mock server started: {}: {}", name, addr);
+
+    // Accept tcp connections in a loop. Each connection is handled in its owned spawned task.
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::error!("failed to accept tcp connection: {}", error);
+                continue;
+            }
+        };
+        tokio::spawn(handle_connection(exchange, stream, context.clone()));
+    }
+}
+
+/// This function is responsible for handling a single connection, which is either a websocket
+/// connection or a REST request, depending on its headers.
+async fn handle_connection(exchange: Option<Exchange>, stream: TcpStream, context: Arc<Context>) {
+    let mut buffer = [0; 4096];
+    let head = match stream.peek(&mut buffer).await {
+        Ok(read) => String::from_utf8_lossy(&buffer[..read]).to_lowercase(),
+        Err(error) => {
+            tracing::warn!("failed to read request: {}", error);
+            return;
+        }
+    };
+    match exchange {
+        Some(exchange) if head.contains("upgrade: websocket") => {
+            handle_websocket(exchange, stream, context).await
+        }
+        _ => handle_http(exchange, stream, &context).await,
+    }
+}
+
+/// This function is responsible for answering a REST request, and closing the connection.
+async fn handle_http(exchange: Option<Exchange>, mut stream: TcpStream, context: &Context) {
+    let mut buffer = [0; 4096];
+    let read = match stream.read(&mut buffer).await {
+        Ok(read) => read,
+        Err(error) => {
+            tracing::warn!("failed to read http request: {}", error);
+            return;
+        }
+    };
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let tick = context.tick_rx.borrow().clone();
+    let body = match exchange {
+        Some(exchange) => (protocol(exchange, path).rest)(&context.registry, &tick, path),
+        None if path.starts_with(SUPPLY_PATH) => Some(supply_response(context)),
+        None if path.starts_with(COINGECKO_SUPPLY_PATH) => Some(coingecko_response(context)),
+        None => None,
+    };
+
+    let response = match body {
+        Some(body) => {
+            let body = body.to_string();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        None => {
+            tracing::warn!("unexpected http request: {:?}: {}", exchange, path);
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+    };
+    if let Err(error) = stream.write_all(response.as_bytes()).await {
+        tracing::warn!("failed to write http response: {}", error);
+    }
+}
+
+/// This function is a helper to build the response of the supply API, in the Coinbase format.
+fn supply_response(context: &Context) -> Value {
+    let instruments = context.registry.instruments.iter();
+    let data: Vec<Value> = instruments
+        .filter_map(|(coin, instrument)| {
+            let supply = context.config.coins.get(coin)?.supply;
+            Some(json!({
+                "symbol": instrument.supply.get("coinbase")?,
+                "circulating_supply": supply.to_string(),
+            }))
+        })
+        .collect();
+    json!({ "data": data })
+}
+
+/// This function is a helper to build the response of the supply API, in the CoinGecko format.
+/// The markets of all the altcoins are served, whatever the requested IDs.
+fn coingecko_response(context: &Context) -> Value {
+    let instruments = context.registry.instruments.iter();
+    let markets: Vec<Value> = instruments
+        .filter_map(|(coin, instrument)| {
+            let supply = context.config.coins.get(coin)?.supply;
+            Some(json!({
+                "id": instrument.supply.get("coingecko")?,
+                "symbol": format!("{:?}", coin).to_lowercase(),
+                "circulating_supply": supply,
+            }))
+        })
+        .collect();
+    Value::Array(markets)
+}
+
+/// This function is responsible for handling a single websocket connection to an exchange.
+/// The protocol of the exchange answers the messages of the client, and streams the markets of
+/// the subscribed altcoins at every tick.
+async fn handle_websocket(exchange: Exchange, stream: TcpStream, context: Arc<Context>) {
+    // Upgrade the tcp connection, and keep the path of the handshake.
+    let mut path = String::new();
+    // The error type is imposed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        path = request.uri().to_string();
+        Ok(response)
+    };
+    let mut websocket_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(websocket_stream) => websocket_stream,
+        Err(error) => {
+            tracing::warn!("failed to upgrade tcp connection: {}", error);
+            return;
+        }
+    };
+    tracing::info!("websocket client connected: {:?}: {}", exchange, path);
+
+    let protocol = protocol(exchange, &path);
+    let mut session = Session {
+        exchange,
+        path,
+        registry: context.registry.clone(),
+        subscriptions: Vec::new(),
+        books: HashMap::new(),
+        sequence: 0,
+    };
+    let mut tick_rx = context.tick_rx.clone();
+    let mut messages = (protocol.connect)(&mut session);
+    loop {
+        for message in messages.drain(..) {
+            if let Err(error) = websocket_stream.send(encode(protocol, message)).await {
+                tracing::info!("websocket client disconnected: {:?}: {}", exchange, error);
+                return;
+            }
+        }
+        tokio::select! {
+            message = websocket_stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let tick = tick_rx.borrow().clone();
+                    messages = (protocol.request)(&mut session, &tick, &text);
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // The pings are answered by tungstenite.
+                Some(Ok(_)) => {}
+            },
+            changed = tick_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let tick = tick_rx.borrow().clone();
+                messages = (protocol.tick)(&mut session, &tick);
+            }
+        }
+    }
+    tracing::info!(
+        "websocket client disconnected: {:?}: {}",
+        exchange,
+        session.path
+    );
+}
+
+/// This function is a helper to encode a message for the websocket.
+fn encode(protocol: &Protocol, message: Value) -> Message {
+    let text = text(message);
+    if !protocol.gzip {
+        return Message::Text(text);
+    }
+    // Safe unwraps: writing to a `Vec` never fails.
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes()).unwrap();
+    Message::Binary(encoder.finish().unwrap())
+}
+
+/// This function is a helper to get the text of a message. A string is sent as is, e.g. a bare
+/// `pong`, whereas other values are serialized to JSON.
+pub fn text(message: Value) -> String {
+    match message {
+        Value::String(text) => text,
+        message => message.to_string(),
+    }
+}
+
+/// This function is a helper to parse a formatted price or quantity into a JSON number, for
+/// exchanges that don't send them as strings.
+pub fn number(value: &str) -> Value {
+    json!(value.parse::<f64>().unwrap_or_default())
+}
+
+/// This function is a helper to get the number of milliseconds since the Unix epoch.
+pub fn unix_millis() -> u64 {
+    let elapsed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    elapsed.map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// This function is a helper to format a time in milliseconds since the Unix epoch as an
+/// RFC 3339 timestamp with microseconds, e.g. `2023-01-16T07:22:26.151000Z`.
+pub fn rfc3339(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (days, seconds_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Convert the days since the epoch to a date, with the years starting in March so that the
+    // leap day is the last day of the year. See this link for reference:
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}000Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        millis % 1000
+    )
+}
+
+/// This function is a helper to get the value of a parameter in the query of a path.
+pub fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = path.split_once('?')?;
+    let mut params = query.split('&').filter_map(|param| param.split_once('='));
+    params.find(|(key, _)| *key == name).map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_rfc3339() {
+        assert_eq!(rfc3339(1673853746151), "2023-01-16T07:22:26.151000Z");
+        assert_eq!(rfc3339(951782400000), "2000-02-29T00:00:00.000000Z");
+    }
+
+    #[test]
+    fn market_levels() {
+        let market = market(3000.0, 3000.0);
+        assert_eq!(market.price, "3000.00");
+        assert_eq!(market.bid, "2998.50");
+        assert_eq!(market.ask, "3001.50");
+        assert_eq!(market.quantity, "333.33333333");
+        let market = super::market(0.08, 0.08);
+        assert_eq!((market.bid.as_str(), market.decimals), ("0.0799600", 7));
+    }
+
+    #[test]
+    fn registry_symbols() {
+        let registry = Registry::load().unwrap();
+        assert_eq!(
+            registry.coin(Exchange::Kraken, "DOGE/USD"),
+            Some(Coin::DOGE)
+        );
+        assert_eq!(registry.coin(Exchange::Kraken, "BTC/USD"), None);
+        assert_eq!(registry.symbols(Exchange::Okx).count(), Coin::ALL.len());
+        assert_eq!(
+            query_param("/api/v3/depth?symbol=ETHUSDT&limit=1000", "symbol"),
+            Some("ETHUSDT")
+        );
+    }
+}
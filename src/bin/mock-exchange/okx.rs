@@ -0,0 +1,92 @@
+use super::{Protocol, Registry, Session, Tick};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// The OKX protocol. All the tickers are subscribed in a single request, and each one is
+/// confirmed separately. See this link for reference:
+/// https://www.okx.com/docs-v5/en/#websocket-api-subscribe
+pub const PROTOCOL: Protocol = Protocol {
+    connect,
+    request,
+    tick,
+    rest,
+    gzip: false,
+};
+
+/// These structs represent a subscribe request.
+#[derive(Deserialize)]
+struct Request<'a> {
+    op: &'a str,
+    #[serde(borrow)]
+    args: Vec<RequestArg<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestArg<'a> {
+    channel: &'a str,
+    inst_id: &'a str,
+}
+
+/// This handler sends nothing, since OKX waits for the subscriptions.
+fn connect(_: &mut Session) -> Vec<Value> {
+    Vec::new()
+}
+
+/// This handler subscribes the tickers, or answers a bare `ping`.
+fn request(session: &mut Session, _: &Tick, message: &str) -> Vec<Value> {
+    if message == "ping" {
+        return vec![json!("pong")];
+    }
+    let request = match serde_json::from_str::<Request>(message) {
+        Ok(request) if request.op == "subscribe" => request,
+        _ => {
+            tracing::warn!("unexpected okx request: {}", message);
+            return Vec::new();
+        }
+    };
+    let args = request.args.iter();
+    args.map(|arg| match session.subscribe(arg.channel, arg.inst_id) {
+        Some(_) => json!({
+            "event": "subscribe",
+            "arg": { "channel": arg.channel, "instId": arg.inst_id },
+            "connId": "mock",
+        }),
+        None => json!({
+            "event": "error",
+            "code": "60018",
+            "msg": format!("Wrong URL or channel:{},instId:{} doesn't exist.", arg.channel, arg.inst_id),
+            "connId": "mock",
+        }),
+    })
+    .collect()
+}
+
+/// This handler sends the tickers.
+fn tick(session: &mut Session, tick: &Tick) -> Vec<Value> {
+    let subscriptions = session.subscriptions.iter();
+    subscriptions
+        .filter_map(|subscription| {
+            let market = tick.markets.get(&subscription.coin)?;
+            Some(json!({
+                "arg": { "channel": subscription.channel, "instId": subscription.symbol },
+                "data": [{
+                    "instType": "SPOT",
+                    "instId": subscription.symbol,
+                    "last": market.price,
+                    "lastSz": market.trade_quantity,
+                    "askPx": market.ask,
+                    "askSz": market.quantity,
+                    "bidPx": market.bid,
+                    "bidSz": market.quantity,
+                    "ts": tick.time.to_string(),
+                }],
+            }))
+        })
+        .collect()
+}
+
+/// This handler serves nothing, since the REST tickers aren't mocked.
+fn rest(_: &Registry, _: &Tick, _: &str) -> Option<Value> {
+    None
+}
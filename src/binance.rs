@@ -1,79 +1,514 @@
+use crate::admin::DisabledExchanges;
+use crate::book::{OrderBook, Side};
+use crate::config::{BinanceTopOfBook, FeedConfig};
 use crate::engine::Input;
-use crate::price::WebSocketPriceFeed;
+use crate::instruments::Symbols;
+use crate::price::{
+    Connection, Event, Poll, Quote, Sequence, Session, Snapshot, Trade, WebSocketPriceFeed,
+};
 use altusd::{Coin, Exchange};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
-use serde_json::json;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The endpoint of the combined streams, whose names are listed in the URL.
+const ENDPOINT: &str = "wss://stream.binance.com:9443/stream";
+
+/// The REST endpoint that serves the snapshots of the order books.
+const SNAPSHOT_ENDPOINT: &str = "https://api.binance.com/api/v3/depth";
+
+/// The number of levels on each side of the order book snapshots.
+const SNAPSHOT_DEPTH: u64 = 1000;
+
+/// The streams subscribed for each symbol. Either the depth streams maintain the order books, or
+/// the book ticker streams push the best bid and ask in real time. The trade streams push the last
+/// price and feed the VWAP.
+const DEPTH_STREAMS: [&str; 2] = ["depth@100ms", "trade"];
+const BOOK_TICKER_STREAMS: [&str; 2] = ["bookTicker", "trade"];
+
+/// The REST endpoint that serves the 24-hour tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.binance.com/api/v3/ticker/24hr";
 
 /// This function is responsible to subscribe to the Binance websocket price feed.
 ///
 /// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
-pub async fn run(mpsc_tx: Sender<Input>) {
-    let websocket_price_feed = WebSocketPriceFeed {
-        endpoint: "wss://stream.binance.com:9443/ws",
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(&config, symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Binance websocket price feed, which is also used to replay its
+/// captures.
+pub fn feed(config: &FeedConfig, symbols: Symbols) -> WebSocketPriceFeed {
+    // The stream names are made of the lowercase symbol and the stream type.
+    let streams = match config.binance_top_of_book {
+        BinanceTopOfBook::Depth => DEPTH_STREAMS,
+        BinanceTopOfBook::BookTicker => BOOK_TICKER_STREAMS,
+    };
+    let streams: Vec<String> = streams
+        .iter()
+        .flat_map(|stream| {
+            let symbols = symbols.iter();
+            symbols.map(move |(_, symbol)| format!("{}@{}", symbol.to_lowercase(), stream))
+        })
+        .collect();
+    WebSocketPriceFeed {
         exchange: Exchange::Binance,
-        subscribe: json!({
-            "method": "SUBSCRIBE",
-            "params": [
-                "adausdt@ticker",
-                "dogeusdt@ticker",
-                "dotusdt@ticker",
-                "ethusdt@ticker",
-                "solusdt@ticker",
-            ],
-            "id": 1,
+        // The streams are subscribed by the URL, so there is no request to send.
+        connections: vec![Connection::all_coins(
+            &format!("{}?streams={}", ENDPOINT, streams.join("/")),
+            Vec::new(),
+        )],
+        symbols,
+        // Binance has no heartbeat channel, but the depth and book ticker streams are pushed at
+        // least every 100ms.
+        idle_timeout: Duration::from_secs(30),
+        keepalive: None,
+        scheduled: Vec::new(),
+        // The depth streams only send the changes, so each book starts from a REST snapshot.
+        snapshot: Some(Snapshot {
+            endpoint: SNAPSHOT_ENDPOINT.to_string(),
+            path: snapshot_path,
+            handler: snapshot_handler,
+        }),
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
         }),
+        // Binance serves the same streams on port 443, and the market data only on its own host.
+        mirrors: vec![
+            "wss://stream.binance.com:443",
+            "wss://data-stream.binance.vision",
+        ],
         message_handler,
-    };
-    websocket_price_feed.run(mpsc_tx).await;
+    }
+}
+
+/// This struct represents the envelope of a combined stream message, which contains the name of
+/// its stream and its data. The stream name is used to dispatch it. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#websocket-market-streams
+#[derive(Deserialize)]
+struct Envelope<'a, T> {
+    stream: &'a str,
+    data: T,
 }
 
-/// This struct represents a message from the `ticker` channel. See this link for reference:
-/// https://binance-docs.github.io/apidocs/spot/en/#individual-symbol-ticker-streams
+/// This struct represents a message from the diff depth stream, which contains the changed
+/// levels between the update IDs `U` and `u` at the event time `E`. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#diff-depth-stream
 #[derive(Deserialize)]
-struct Message<'a> {
-    e: &'a str,
+#[allow(non_snake_case)]
+struct DepthMessage<'a> {
+    E: u64,
+    s: &'a str,
+    U: u64,
+    u: u64,
+    #[serde(borrow)]
+    b: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    a: Vec<(&'a str, &'a str)>,
+}
+
+/// This struct represents a message from the book ticker stream, which is pushed whenever the best
+/// bid or ask changes. Its update ID is shared with the depth stream, but it has no event time.
+/// See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#individual-symbol-book-ticker-streams
+#[derive(Deserialize)]
+struct BookTickerMessage<'a> {
+    u: u64,
     s: &'a str,
-    c: &'a str,
     b: &'a str,
     a: &'a str,
 }
 
-/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+/// This struct represents a message from the trade stream. The trade IDs of a symbol increase by
+/// one for every trade. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#trade-streams
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct TradeMessage<'a> {
+    E: u64,
+    s: &'a str,
+    t: u64,
+    p: &'a str,
+    q: &'a str,
+}
+
+/// This struct represents a snapshot of an order book. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#order-book
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotResponse<'a> {
+    last_update_id: u64,
+    #[serde(borrow)]
+    bids: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    asks: Vec<(&'a str, &'a str)>,
+}
+
+/// This message handler dispatches a message on the type of its stream, e.g. `trade` in
+/// `ethusdt@trade`.
 ///
 /// Effectively, this is a callback used for every websocket received from the exchange.
-fn message_handler(message: String) -> Option<(Coin, [f64; 3])> {
-    // Deserialize.
-    let message = match serde_json::from_str::<Message>(&message) {
-        Ok(message) => message,
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    // Deserialize the envelope.
+    let envelope = match serde_json::from_str::<Envelope<IgnoredAny>>(&message) {
+        Ok(envelope) => envelope,
         Err(_) => {
             tracing::warn!("discarded message: {}", message);
-            return None;
+            return Vec::new();
+        }
+    };
+    let (symbol, stream) = match envelope.stream.split_once('@') {
+        Some((symbol, stream)) => (symbol.to_uppercase(), stream),
+        None => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
         }
     };
 
-    // Validate message type.
-    if message.e != "24hrTicker" {
-        tracing::error!("unexpected message type: {}", message.e);
-        return None;
+    // Binance doesn't acknowledge the streams of the URL, so the first message of an altcoin
+    // confirms its subscription.
+    let mut events = Vec::new();
+    if let Some(coin) = session.symbols.coin(&symbol) {
+        if session.confirmed.insert(coin) {
+            events.push(Event::Subscribed(vec![coin]));
+        }
     }
 
+    // Dispatch on the stream type.
+    match stream {
+        "depth@100ms" => events.extend(depth_handler(session, message)),
+        "bookTicker" => events.extend(book_ticker_handler(session, &message)),
+        "trade" => events.extend(trade_handler(session, &message)),
+        _ => tracing::error!("unexpected message type: {}", stream),
+    }
+    events
+}
+
+/// This message handler tries to apply the changes of a depth update to the order book of an
+/// altcoin. The update is buffered if the book isn't synchronized with a snapshot yet, or if an
+/// update was missed, in which case a new snapshot is requested. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#how-to-manage-a-local-order-book-correctly
+fn depth_handler(session: &mut Session, message: String) -> Vec<Event> {
+    // Deserialize.
+    let update = match serde_json::from_str::<Envelope<DepthMessage>>(&message) {
+        Ok(envelope) => envelope.data,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
+        }
+    };
+
     // Extract coin.
-    let coin = match message.s {
-        "ADAUSDT" => Coin::ADA,
-        "DOGEUSDT" => Coin::DOGE,
-        "DOTUSDT" => Coin::DOT,
-        "ETHUSDT" => Coin::ETH,
-        "SOLUSDT" => Coin::SOL,
-        _ => {
-            tracing::error!("unexpected message coin: {}", message.s);
+    let coin = match session.symbols.coin(update.s) {
+        Some(coin) => coin,
+        None => return Vec::new(),
+    };
+
+    // Discard the book if an update was missed.
+    if let Some(book) = session.books.get(&coin) {
+        if update.U > book.update_id + 1 {
+            tracing::warn!("missed book update: {:?}: {}", coin, book.update_id);
+            session.books.remove(&coin);
+        }
+    }
+
+    // Buffer the update until the book is synchronized with a snapshot, which is requested once.
+    let book = match session.books.get_mut(&coin) {
+        Some(book) => book,
+        None => {
+            let buffered = session.buffered.entry(coin).or_default();
+            buffered.push(message);
+            if buffered.len() > 1 {
+                return Vec::new();
+            }
+            return vec![Event::SnapshotRequired(coin)];
+        }
+    };
+
+    // Discard the update if it's already included in the book.
+    if update.u <= book.update_id {
+        return Vec::new();
+    }
+
+    // Apply the changes.
+    apply_levels(book, &update.b, &update.a);
+    book.update_id = update.u;
+    vec![Event::Book(coin, Some(update.E))]
+}
+
+/// This message handler tries to parse the best bid and best ask for an altcoin.
+fn book_ticker_handler(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let ticker = match serde_json::from_str::<Envelope<BookTickerMessage>>(message) {
+        Ok(envelope) => envelope.data,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, best bid, best ask, and sequence.
+    let coin = session.symbols.coin(ticker.s)?;
+    let best_bid = crate::price::str_to_f64(ticker.b)?;
+    let best_ask = crate::price::str_to_f64(ticker.a)?;
+    Some(Event::Quote(Quote {
+        coin,
+        best_bid: Some(best_bid),
+        best_ask: Some(best_ask),
+        sequence: Some(Sequence::UpdateId(ticker.u)),
+        time: None,
+    }))
+}
+
+/// This message handler tries to parse the price and quantity of a trade of an altcoin.
+fn trade_handler(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let trade = match serde_json::from_str::<Envelope<TradeMessage>>(message) {
+        Ok(envelope) => envelope.data,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
             return None;
         }
     };
 
-    // Extract last price, best bid, and best ask.
-    let last_price = crate::price::str_to_f64(message.c)?;
-    let best_bid = crate::price::str_to_f64(message.b)?;
-    let best_ask = crate::price::str_to_f64(message.a)?;
-    Some((coin, [last_price, best_bid, best_ask]))
+    // Extract coin, price, and quantity.
+    let coin = session.symbols.coin(trade.s)?;
+    let price = crate::price::str_to_f64(trade.p)?;
+    let quantity = crate::price::str_to_f64(trade.q)?;
+    Some(Event::Trade(Trade {
+        coin,
+        price,
+        quantity: Some(quantity),
+        sequence: Some(Sequence::Number(trade.t)),
+        time: Some(trade.E),
+    }))
+}
+
+/// This function is a helper to build the path of the order book snapshot of a symbol.
+fn snapshot_path(symbol: &str) -> String {
+    format!("?symbol={}&limit={}", symbol, SNAPSHOT_DEPTH)
+}
+
+/// This snapshot handler replaces the order book of an altcoin, and applies the updates that
+/// were buffered while waiting for it. If the snapshot is older than these updates, a new one is
+/// requested.
+fn snapshot_handler(session: &mut Session, coin: Coin, response: String) -> Vec<Event> {
+    // Deserialize.
+    let snapshot = match serde_json::from_str::<SnapshotResponse>(&response) {
+        Ok(snapshot) => snapshot,
+        Err(_) => {
+            tracing::warn!("discarded snapshot: {}", response);
+            return vec![Event::Reconnect("invalid book snapshot".to_string())];
+        }
+    };
+
+    // Replace the book.
+    let mut book = OrderBook::default();
+    book.update_id = snapshot.last_update_id;
+    apply_levels(&mut book, &snapshot.bids, &snapshot.asks);
+    session.books.insert(coin, book);
+
+    // Apply the buffered updates.
+    let mut events = vec![Event::Book(coin, None)];
+    for message in session.buffered.remove(&coin).unwrap_or_default() {
+        events.extend(depth_handler(session, message));
+    }
+    events
+}
+
+/// This function is a helper to apply the changed levels to an order book.
+/// A level with a zero quantity is removed.
+fn apply_levels(book: &mut OrderBook, bids: &[(&str, &str)], asks: &[(&str, &str)]) {
+    for (price, quantity) in bids {
+        book.update(Side::Bid, price, quantity);
+    }
+    for (price, quantity) in asks {
+        book.update(Side::Ask, price, quantity);
+    }
+}
+
+/// This struct represents a 24-hour ticker. See this link for reference:
+/// https://binance-docs.github.io/apidocs/spot/en/#24hr-ticker-price-change-statistics
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TickerResponse<'a> {
+    last_price: &'a str,
+    bid_price: &'a str,
+    ask_price: &'a str,
+}
+
+/// This function is a helper to build the path of the REST ticker of a symbol.
+fn poll_path(symbol: &str) -> String {
+    format!("?symbol={}", symbol)
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let ticker = serde_json::from_str::<TickerResponse>(response).ok()?;
+    Some([
+        crate::price::str_to_f64(ticker.last_price)?,
+        crate::price::str_to_f64(ticker.bid_price)?,
+        crate::price::str_to_f64(ticker.ask_price)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Binance)
+    }
+
+    /// This function is a helper to build a depth update of ETH.
+    fn update(first_id: u64, last_id: u64, bid: &str, ask: &str) -> String {
+        format!(
+            r#"{{"stream":"ethusdt@depth@100ms","data":{{"e":"depthUpdate","E":1672515782136,"s":"ETHUSDT","U":{},"u":{},"b":[{}],"a":[{}]}}}}"#,
+            first_id, last_id, bid, ask
+        )
+    }
+
+    #[test]
+    fn snapshot_and_diff_sync() {
+        let mut session = session();
+
+        // The updates are buffered until the snapshot is received, which is requested once.
+        let message = update(100, 105, r#"["3000.10","2.0"]"#, "");
+        let expected = vec![
+            Event::Subscribed(vec![Coin::ETH]),
+            Event::SnapshotRequired(Coin::ETH),
+        ];
+        assert_eq!(message_handler(&mut session, message), expected);
+        let message = update(106, 110, r#"["3000.20","1.5"]"#, r#"["3000.40","0"]"#);
+        assert_eq!(message_handler(&mut session, message), vec![]);
+
+        // The snapshot is applied, and then the buffered updates that aren't included in it.
+        let response = r#"{"lastUpdateId":103,"bids":[["3000.10","1.0"],["3000.00","4.0"]],"asks":[["3000.30","3.0"],["3000.40","5.0"]]}"#;
+        let events = snapshot_handler(&mut session, Coin::ETH, response.to_string());
+        let expected = vec![
+            Event::Book(Coin::ETH, None),
+            Event::Book(Coin::ETH, Some(1672515782136)),
+            Event::Book(Coin::ETH, Some(1672515782136)),
+        ];
+        assert_eq!(events, expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.update_id, 110);
+        let bids: Vec<&str> = book.bids().map(|level| level.quantity.as_str()).collect();
+        assert_eq!(bids, ["1.5", "2.0", "4.0"]);
+        assert_eq!(book.asks().count(), 1);
+
+        // An update that is already included in the book is discarded.
+        let message = update(108, 110, r#"["3000.10","9.0"]"#, "");
+        assert_eq!(message_handler(&mut session, message), vec![]);
+        let message = update(111, 111, "", r#"["3000.50","1.0"]"#);
+        assert_eq!(
+            message_handler(&mut session, message),
+            vec![Event::Book(Coin::ETH, Some(1672515782136))]
+        );
+    }
+
+    #[test]
+    fn missed_update() {
+        let mut session = session();
+        session.confirmed.insert(Coin::ETH);
+        let mut book = OrderBook::default();
+        book.update_id = 110;
+        session.books.insert(Coin::ETH, book);
+
+        // A gap between the updates discards the book and requests a new snapshot.
+        let message = update(115, 120, r#"["3000.10","2.0"]"#, "");
+        let expected = vec![Event::SnapshotRequired(Coin::ETH)];
+        assert_eq!(message_handler(&mut session, message), expected);
+        assert!(session.books.is_empty());
+
+        // A snapshot older than the buffered updates is requested again.
+        let response =
+            r#"{"lastUpdateId":112,"bids":[["3000.10","1.0"]],"asks":[["3000.30","3.0"]]}"#;
+        let events = snapshot_handler(&mut session, Coin::ETH, response.to_string());
+        let expected = vec![
+            Event::Book(Coin::ETH, None),
+            Event::SnapshotRequired(Coin::ETH),
+        ];
+        assert_eq!(events, expected);
+        assert_eq!(session.buffered[&Coin::ETH].len(), 1);
+    }
+
+    #[test]
+    fn trade_message() {
+        let message = r#"{"stream":"ethusdt@trade","data":{"e":"trade","E":1672515782136,"s":"ETHUSDT","t":1142163207,"p":"3000.25000000","q":"0.41020000","b":13205327563,"a":13205327592,"T":1672515782134,"m":true,"M":true}}"#;
+        let expected = vec![
+            Event::Subscribed(vec![Coin::ETH]),
+            Event::Trade(Trade {
+                coin: Coin::ETH,
+                price: 3000.25,
+                quantity: Some(0.4102),
+                sequence: Some(Sequence::Number(1142163207)),
+                time: Some(1672515782136),
+            }),
+        ];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn book_ticker_messages() {
+        let mut session = session();
+        let message = r#"{"stream":"dogeusdt@bookTicker","data":{"u":400900217,"s":"DOGEUSDT","b":"0.08170000","B":"31021.00000000","a":"0.08171000","A":"40660.00000000"}}"#;
+        let quote = Event::Quote(Quote {
+            coin: Coin::DOGE,
+            best_bid: Some(0.0817),
+            best_ask: Some(0.08171),
+            sequence: Some(Sequence::UpdateId(400900217)),
+            time: None,
+        });
+        let expected = vec![Event::Subscribed(vec![Coin::DOGE]), quote];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+
+        // The subscription is only confirmed by the first message of an altcoin. The update IDs
+        // skip the order book updates that don't change the top of the book.
+        let message = r#"{"stream":"dogeusdt@bookTicker","data":{"u":400900231,"s":"DOGEUSDT","b":"0.08171000","B":"1200.00000000","a":"0.08172000","A":"40660.00000000"}}"#;
+        let expected = vec![Event::Quote(Quote {
+            coin: Coin::DOGE,
+            best_bid: Some(0.08171),
+            best_ask: Some(0.08172),
+            sequence: Some(Sequence::UpdateId(400900231)),
+            time: None,
+        })];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+    }
+
+    #[test]
+    fn subscribe_reply() {
+        // The streams are subscribed by the URL, so a reply to a subscribe request confirms
+        // nothing, and the altcoin is only confirmed by its first message.
+        let mut session = session();
+        let message = r#"{"result":null,"id":1}"#;
+        assert_eq!(message_handler(&mut session, message.to_string()), vec![]);
+        assert!(session.confirmed.is_empty());
+    }
+
+    #[test]
+    fn unexpected_message() {
+        let message = r#"{"code":2,"msg":"Invalid request"}"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"symbol":"ETHUSDT","priceChange":"12.10000000","lastPrice":"3000.25000000","bidPrice":"3000.24000000","bidQty":"5.10000000","askPrice":"3000.26000000","askQty":"2.00000000","volume":"250103.10000000"}"#;
+        assert_eq!(poll_handler(response), Some([3000.25, 3000.24, 3000.26]));
+        assert_eq!(poll_path("ETHUSDT"), "?symbol=ETHUSDT");
+    }
 }
@@ -0,0 +1,267 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Session, Ticker, WebSocketPriceFeed};
+use altusd::Exchange;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The info code sent before the websocket server stops or restarts.
+const RESTART_CODE: u64 = 20051;
+
+/// The REST endpoint that serves the tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api-pub.bitfinex.com/v2/ticker/";
+
+/// This function is responsible to subscribe to the Bitfinex websocket price feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Bitfinex websocket price feed, which is also used to replay its
+/// captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    WebSocketPriceFeed {
+        exchange: Exchange::Bitfinex,
+        // Bitfinex only accepts one symbol per subscribe request.
+        connections: vec![Connection::all_coins(
+            "wss://api-pub.bitfinex.com/ws/2",
+            symbols
+                .iter()
+                .map(|(_, symbol)| {
+                    json!({
+                        "event": "subscribe",
+                        "channel": "ticker",
+                        "symbol": symbol,
+                    })
+                })
+                .collect(),
+        )],
+        symbols,
+        // Bitfinex sends a heartbeat every 15 seconds on a channel without updates.
+        idle_timeout: Duration::from_secs(30),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        mirrors: Vec::new(),
+        message_handler,
+    }
+}
+
+/// These structs represent a message from a channel, which starts with the channel ID assigned
+/// in the subscribe response. See this link for reference:
+/// https://docs.bitfinex.com/reference/ws-public-ticker
+///
+/// The ticker is a positional array whose fields are the best bid, its size, the best ask, its
+/// size, the daily change, the relative daily change, the last price, the volume, the high, and
+/// the low. The messages carry no symbol and no sequence number.
+#[derive(Deserialize)]
+struct ChannelMessage<'a>(u64, #[serde(borrow)] ChannelData<'a>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChannelData<'a> {
+    Heartbeat(&'a str),
+    Ticker(TickerData),
+}
+
+#[derive(Deserialize)]
+struct TickerData(
+    f64,
+    IgnoredAny,
+    f64,
+    IgnoredAny,
+    IgnoredAny,
+    IgnoredAny,
+    f64,
+    IgnoredAny,
+    IgnoredAny,
+    IgnoredAny,
+);
+
+/// This struct represents an event, e.g. `info`, `subscribed`, or `error`.
+/// See this link for reference: https://docs.bitfinex.com/docs/ws-general
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventMessage<'a> {
+    event: &'a str,
+    chan_id: Option<u64>,
+    symbol: Option<&'a str>,
+    code: Option<u64>,
+    msg: Option<String>,
+}
+
+/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+/// The altcoin is found from the channel ID, which is mapped when the subscription is confirmed.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    // Deserialize.
+    let channel_message = match serde_json::from_str::<ChannelMessage>(&message) {
+        Ok(channel_message) => channel_message,
+        Err(_) => return event_handler(session, &message).into_iter().collect(),
+    };
+
+    // Extract coin.
+    let coin = match session.channels.get(&channel_message.0) {
+        Some(coin) => *coin,
+        None => {
+            tracing::warn!("unexpected channel: {}", message);
+            return Vec::new();
+        }
+    };
+
+    // Extract last price, best bid, and best ask.
+    match channel_message.1 {
+        ChannelData::Heartbeat("hb") => vec![Event::Heartbeat],
+        ChannelData::Heartbeat(_) => {
+            tracing::warn!("discarded message: {}", message);
+            Vec::new()
+        }
+        ChannelData::Ticker(ticker) => vec![Event::Ticker(Ticker {
+            coin,
+            prices: [ticker.6, ticker.0, ticker.2],
+            sequence: None,
+            time: None,
+        })],
+    }
+}
+
+/// This message handler tries to parse an event. Bitfinex confirms each symbol separately and
+/// assigns it a channel ID, which is kept for the lifetime of the connection.
+fn event_handler(session: &mut Session, message: &str) -> Option<Event> {
+    let event = match serde_json::from_str::<EventMessage>(message) {
+        Ok(event) => event,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    match (event.event, event.code) {
+        ("subscribed", _) => {
+            let coin = session.symbols.coin(event.symbol?)?;
+            session.channels.insert(event.chan_id?, coin);
+            Some(Event::Subscribed(vec![coin]))
+        }
+        ("error", code) => Some(Event::Rejected(format!(
+            "{}: {}",
+            code.unwrap_or_default(),
+            event.msg.unwrap_or_default()
+        ))),
+        // The channel IDs are lost on restart, so we subscribe again on a new connection.
+        ("info", Some(RESTART_CODE)) => Some(Event::Reconnect(format!(
+            "{}: {}",
+            RESTART_CODE,
+            event.msg.unwrap_or_default()
+        ))),
+        ("info", code) => {
+            tracing::info!("bitfinex info: {:?}: {:?}", code, event.msg);
+            None
+        }
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This function is a helper to build the path of the REST ticker of a symbol, i.e. the symbol.
+fn poll_path(symbol: &str) -> String {
+    symbol.to_string()
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker,
+/// which has the same fields as the ticker messages. See this link for reference:
+/// https://docs.bitfinex.com/reference/rest-public-ticker
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let ticker = serde_json::from_str::<TickerData>(response).ok()?;
+    Some([ticker.6, ticker.0, ticker.2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use altusd::Coin;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Bitfinex)
+    }
+
+    #[test]
+    fn ticker_messages() {
+        let mut session = session();
+        let message = r#"{"event":"subscribed","channel":"ticker","chanId":224555,"symbol":"tETHUSD","pair":"ETHUSD"}"#;
+        let expected = vec![Event::Subscribed(vec![Coin::ETH])];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+
+        let message =
+            r#"[224555,[3011.1,52.47,3011.2,31.75,-24.3,-0.008,3011.15,18433.07,3060.5,2990.2]]"#;
+        let expected = vec![Event::Ticker(Ticker {
+            coin: Coin::ETH,
+            prices: [3011.15, 3011.1, 3011.2],
+            sequence: None,
+            time: None,
+        })];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+
+        let message = r#"[224555,"hb"]"#;
+        let expected = vec![Event::Heartbeat];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+    }
+
+    #[test]
+    fn unknown_channel_message() {
+        let message =
+            r#"[17082,[0.0817,150000,0.08171,98000,0.0002,0.0025,0.08172,32000000,0.0831,0.0802]]"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+    }
+
+    #[test]
+    fn error_message() {
+        let message = r#"{"event":"error","msg":"symbol: invalid","code":10300,"pair":"FOOUSD"}"#;
+        let expected = vec![Event::Rejected("10300: symbol: invalid".to_string())];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn info_messages() {
+        let message = r#"{"event":"info","version":2,"serverId":"7ad6b4b4-2a05-4f5f-a6a1-7f3b1f5e1f4c","platform":{"status":1}}"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+
+        let message = r#"{"event":"info","code":20051,"msg":"Stopping. Please try to reconnect"}"#;
+        let expected = vec![Event::Reconnect(
+            "20051: Stopping. Please try to reconnect".to_string(),
+        )];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = "[3000.1,12.5,3000.2,8.1,-12.3,-0.0041,3000.15,15203.2,3050,2980.5]";
+        assert_eq!(poll_handler(response), Some([3000.15, 3000.1, 3000.2]));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Quote, Sequence, Session, Trade, WebSocketPriceFeed};
+use altusd::{Coin, Exchange};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The channels subscribed for each currency pair. Bitstamp has no ticker channel, so the last
+/// price comes from the `live_trades` channel and the best bid and ask from the `order_book` one.
+const CHANNELS: [&str; 2] = ["live_trades", "order_book"];
+
+/// The REST endpoint that serves the tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://www.bitstamp.net/api/v2/ticker/";
+
+/// This function is responsible to subscribe to the Bitstamp websocket price feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Bitstamp websocket price feed, which is also used to replay its
+/// captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    WebSocketPriceFeed {
+        exchange: Exchange::Bitstamp,
+        // Bitstamp only accepts one channel per subscribe request.
+        connections: vec![Connection::all_coins(
+            "wss://ws.bitstamp.net",
+            symbols
+                .iter()
+                .flat_map(|(_, pair)| {
+                    CHANNELS.iter().map(move |channel| {
+                        json!({
+                            "event": "bts:subscribe",
+                            "data": {
+                                "channel": format!("{}_{}", channel, pair),
+                            },
+                        })
+                    })
+                })
+                .collect(),
+        )],
+        symbols,
+        // Bitstamp has no heartbeat channel, but the order books of our pairs change constantly.
+        idle_timeout: Duration::from_secs(30),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        mirrors: Vec::new(),
+        message_handler,
+    }
+}
+
+/// This struct represents the envelope of any message, which is used to dispatch it.
+/// See this link for reference: https://www.bitstamp.net/websocket/v2/
+#[derive(Deserialize)]
+struct Header<'a> {
+    event: &'a str,
+    channel: &'a str,
+}
+
+/// This struct represents a message from the `live_trades` channel. The price, the amount, and
+/// the microsecond timestamp are sent as strings to avoid any loss of precision.
+#[derive(Deserialize)]
+struct TradeMessage<'a> {
+    #[serde(borrow)]
+    data: TradeData<'a>,
+}
+
+#[derive(Deserialize)]
+struct TradeData<'a> {
+    price_str: &'a str,
+    amount_str: &'a str,
+    microtimestamp: &'a str,
+}
+
+/// This struct represents a message from the `order_book` channel, which contains the top 100
+/// levels on each side of the book. The first level of each side is the best bid or ask.
+#[derive(Deserialize)]
+struct OrderBookMessage<'a> {
+    #[serde(borrow)]
+    data: OrderBookData<'a>,
+}
+
+#[derive(Deserialize)]
+struct OrderBookData<'a> {
+    microtimestamp: &'a str,
+    #[serde(borrow)]
+    bids: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    asks: Vec<(&'a str, &'a str)>,
+}
+
+/// This struct represents a `bts:error` message, e.g. for an invalid channel.
+#[derive(Deserialize)]
+struct ErrorMessage {
+    data: ErrorData,
+}
+
+#[derive(Deserialize)]
+struct ErrorData {
+    message: String,
+}
+
+/// This message handler dispatches a message on its event.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    parse_message(session, &message).into_iter().collect()
+}
+
+/// This function is a helper to parse a message, which carries at most one event.
+fn parse_message(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize the header.
+    let header = match serde_json::from_str::<Header>(message) {
+        Ok(header) => header,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Dispatch on the event.
+    match header.event {
+        "trade" => trade_handler(session, message, header.channel),
+        "data" => order_book_handler(session, message, header.channel),
+        "bts:subscription_succeeded" => {
+            let coin = channel_to_coin(session, header.channel)?;
+            Some(Event::Subscribed(vec![coin]))
+        }
+        "bts:error" => match serde_json::from_str::<ErrorMessage>(message) {
+            Ok(error) => Some(Event::Rejected(error.data.message)),
+            Err(_) => {
+                tracing::warn!("discarded message: {}", message);
+                None
+            }
+        },
+        "bts:request_reconnect" => Some(Event::Reconnect(header.event.to_string())),
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This message handler tries to parse the price and quantity of a trade of an altcoin.
+fn trade_handler(session: &Session, message: &str, channel: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<TradeMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, last price, quantity, and time, which is also the sequence.
+    let coin = channel_to_coin(session, channel)?;
+    let price = crate::price::str_to_f64(message.data.price_str)?;
+    let quantity = crate::price::str_to_f64(message.data.amount_str)?;
+    let time = microtimestamp_to_millis(message.data.microtimestamp)?;
+    Some(Event::Trade(Trade {
+        coin,
+        price,
+        quantity: Some(quantity),
+        sequence: Some(Sequence::Time(time)),
+        time: Some(time),
+    }))
+}
+
+/// This message handler tries to parse the best bid and best ask for an altcoin.
+fn order_book_handler(session: &Session, message: &str, channel: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<OrderBookMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, best bid, best ask, and time, which is also the sequence.
+    let coin = channel_to_coin(session, channel)?;
+    let (best_bid, _) = message.data.bids.first()?;
+    let (best_ask, _) = message.data.asks.first()?;
+    let best_bid = crate::price::str_to_f64(best_bid)?;
+    let best_ask = crate::price::str_to_f64(best_ask)?;
+    let time = microtimestamp_to_millis(message.data.microtimestamp)?;
+    Some(Event::Quote(Quote {
+        coin,
+        best_bid: Some(best_bid),
+        best_ask: Some(best_ask),
+        sequence: Some(Sequence::Time(time)),
+        time: Some(time),
+    }))
+}
+
+/// This function is a helper to map a Bitstamp channel (e.g. `live_trades_ethusd`) to an altcoin.
+fn channel_to_coin(session: &Session, channel: &str) -> Option<Coin> {
+    let pair = CHANNELS
+        .iter()
+        .find_map(|prefix| channel.strip_prefix(prefix)?.strip_prefix('_'));
+    session.symbols.coin(pair.unwrap_or(channel))
+}
+
+/// This function is a helper to convert a timestamp in microseconds to milliseconds.
+fn microtimestamp_to_millis(microtimestamp: &str) -> Option<u64> {
+    match microtimestamp.parse::<u64>() {
+        Ok(microtimestamp) => Some(microtimestamp / 1000),
+        Err(error) => {
+            tracing::error!("failed to parse microtimestamp: {}", error);
+            None
+        }
+    }
+}
+
+/// This struct represents a ticker. See this link for reference:
+/// https://www.bitstamp.net/api/#ticker
+#[derive(Deserialize)]
+struct TickerResponse<'a> {
+    last: &'a str,
+    bid: &'a str,
+    ask: &'a str,
+}
+
+/// This function is a helper to build the path of the REST ticker of a currency pair.
+fn poll_path(pair: &str) -> String {
+    format!("{}/", pair)
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let ticker = serde_json::from_str::<TickerResponse>(response).ok()?;
+    Some([
+        crate::price::str_to_f64(ticker.last)?,
+        crate::price::str_to_f64(ticker.bid)?,
+        crate::price::str_to_f64(ticker.ask)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Bitstamp)
+    }
+
+    #[test]
+    fn trade_message() {
+        let message = r#"{"data": {"id": 223478016, "timestamp": "1645113600", "amount": 0.5, "amount_str": "0.50000000", "price": 2901.12, "price_str": "2901.12", "type": 0, "microtimestamp": "1645113600123456", "buy_order_id": 1459026486697985, "sell_order_id": 1459026481840128}, "channel": "live_trades_ethusd", "event": "trade"}"#;
+        let expected = Event::Trade(Trade {
+            coin: Coin::ETH,
+            price: 2901.12,
+            quantity: Some(0.5),
+            sequence: Some(Sequence::Time(1645113600123)),
+            time: Some(1645113600123),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn order_book_message() {
+        let message = r#"{"data": {"timestamp": "1645113600", "microtimestamp": "1645113600234567", "bids": [["0.98540", "1520.00000000"], ["0.98530", "6000.00000000"]], "asks": [["0.98610", "870.50000000"], ["0.98640", "2500.00000000"]]}, "channel": "order_book_adausd", "event": "data"}"#;
+        let expected = Event::Quote(Quote {
+            coin: Coin::ADA,
+            best_bid: Some(0.9854),
+            best_ask: Some(0.9861),
+            sequence: Some(Sequence::Time(1645113600234)),
+            time: Some(1645113600234),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn subscription_succeeded_message() {
+        let message = r#"{"event": "bts:subscription_succeeded", "channel": "order_book_dogeusd", "data": {}}"#;
+        let expected = Event::Subscribed(vec![Coin::DOGE]);
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn error_message() {
+        let message = r#"{"event": "bts:error", "channel": "", "data": {"code": null, "message": "Bad subscription string."}}"#;
+        let expected = Event::Rejected("Bad subscription string.".to_string());
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn empty_order_book_message() {
+        let message = r#"{"data": {"timestamp": "1645113600", "microtimestamp": "1645113600234567", "bids": [], "asks": []}, "channel": "order_book_solusd", "event": "data"}"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"timestamp": "1645113600", "open": "2950.00", "high": "2990.10", "low": "2880.55", "last": "2901.12", "volume": "5321.12345678", "vwap": "2930.21", "bid": "2901.01", "ask": "2901.50", "open_24": "2960.00", "percent_change_24": "-1.99"}"#;
+        assert_eq!(poll_handler(response), Some([2901.12, 2901.01, 2901.5]));
+    }
+}
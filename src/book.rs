@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// This enum represents a side of an order book.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// This struct represents a local copy of the order book of a market, which is maintained from
+/// the snapshots and incremental updates sent by an exchange.
+///
+/// The levels keep the strings sent by the exchange, e.g. to verify a checksum.
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    bids: BTreeMap<Price, Level>,
+    asks: BTreeMap<Price, Level>,
+    /// The ID of the last update applied to the book, for exchanges that number them.
+    pub update_id: u64,
+}
+
+/// This struct represents a price level of an order book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub price: String,
+    pub quantity: String,
+}
+
+/// This struct is a helper to sort the levels of an order book by price.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Price(f64);
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl OrderBook {
+    /// Set the quantity of a price level, or remove the level if the quantity is zero.
+    /// It returns `None` if the price or the quantity can't be parsed.
+    pub fn update(&mut self, side: Side, price: &str, quantity: &str) -> Option<()> {
+        let key = Price(crate::price::str_to_f64(price)?);
+        let levels = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        if crate::price::str_to_f64(quantity)? == 0.0 {
+            levels.remove(&key);
+        } else {
+            let level = Level {
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+            };
+            levels.insert(key, level);
+        }
+        Some(())
+    }
+
+    /// Keep only the best `depth` levels of each side, for exchanges that stream a book of a
+    /// fixed depth and don't send the removal of the levels that fall out of it.
+    pub fn truncate(&mut self, depth: usize) {
+        while self.bids.len() > depth {
+            self.bids.pop_first();
+        }
+        while self.asks.len() > depth {
+            self.asks.pop_last();
+        }
+    }
+
+    /// Iterate over the bids from the best one, i.e. the highest.
+    pub fn bids(&self) -> impl Iterator<Item = &Level> {
+        self.bids.values().rev()
+    }
+
+    /// Iterate over the asks from the best one, i.e. the lowest.
+    pub fn asks(&self) -> impl Iterator<Item = &Level> {
+        self.asks.values()
+    }
+
+    /// Compute the depth-weighted mid price, i.e. the mean of the average prices at which a
+    /// `notional` in USD would be sold and bought by sweeping the book. Unlike the best bid and
+    /// ask, it can't be moved by tiny orders at the top of the book.
+    ///
+    /// It returns `None` if either side of the book doesn't hold enough liquidity.
+    pub fn depth_weighted_mid(&self, notional: f64) -> Option<f64> {
+        let bid = sweep(self.bids(), notional)?;
+        let ask = sweep(self.asks(), notional)?;
+        Some((bid + ask) / 2.0)
+    }
+}
+
+/// This function is a helper to compute the average price at which a `notional` is filled by
+/// sweeping the given levels in order. A zero notional is filled at the best price.
+fn sweep<'a>(levels: impl Iterator<Item = &'a Level>, notional: f64) -> Option<f64> {
+    let mut remaining = notional;
+    let mut quantity = 0.0;
+    for level in levels {
+        let price = crate::price::str_to_f64(&level.price)?;
+        let level_quantity = crate::price::str_to_f64(&level.quantity)?;
+        if price * level_quantity >= remaining {
+            quantity += remaining / price;
+            if quantity == 0.0 {
+                return Some(price);
+            }
+            return Some(notional / quantity);
+        }
+        remaining -= price * level_quantity;
+        quantity += level_quantity;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> OrderBook {
+        let mut book = OrderBook::default();
+        for (side, price, quantity) in [
+            (Side::Bid, "99", "1"),
+            (Side::Bid, "100", "0.01"),
+            (Side::Bid, "98", "10"),
+            (Side::Ask, "101", "0.01"),
+            (Side::Ask, "102", "1"),
+            (Side::Ask, "103", "10"),
+        ] {
+            book.update(side, price, quantity).unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn update_levels() {
+        let mut book = book();
+        book.update(Side::Bid, "99", "0").unwrap();
+        book.update(Side::Ask, "101", "0.02").unwrap();
+        let bids: Vec<&str> = book.bids().map(|level| level.price.as_str()).collect();
+        assert_eq!(bids, ["100", "98"]);
+        assert_eq!(book.asks().next().unwrap().quantity, "0.02");
+
+        book.truncate(1);
+        assert_eq!(book.bids().count(), 1);
+        assert_eq!(book.asks().last().unwrap().price, "101");
+        assert_eq!(book.update(Side::Ask, "foo", "1"), None);
+    }
+
+    #[test]
+    fn depth_weighted_mid() {
+        let book = book();
+        // Tiny orders at the top of the book barely move the depth-weighted mid.
+        assert_eq!(book.depth_weighted_mid(0.0), Some(100.5));
+        let mid = book.depth_weighted_mid(500.0).unwrap();
+        let bid = 500.0 / (0.01 + 1.0 + (500.0 - 1.0 - 99.0) / 98.0);
+        let ask = 500.0 / (0.01 + 1.0 + (500.0 - 1.01 - 102.0) / 103.0);
+        assert!((mid - (bid + ask) / 2.0).abs() < 1e-9);
+        assert_eq!(book.depth_weighted_mid(10_000.0), None);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{
+    Connection, Event, Poll, Quote, ScheduledMessage, Sequence, Session, Trade, WebSocketPriceFeed,
+};
+use altusd::{Coin, Exchange};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The REST endpoint that serves the spot tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.bybit.com/v5/market/tickers?category=spot&symbol=";
+
+/// This function is responsible to subscribe to the Bybit websocket price feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Bybit websocket price feed, which is also used to replay its captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    let tickers = symbols
+        .iter()
+        .map(|(_, symbol)| format!("tickers.{}", symbol));
+    let order_books = symbols
+        .iter()
+        .map(|(_, symbol)| format!("orderbook.1.{}", symbol));
+    let args: Vec<String> = tickers.chain(order_books).collect();
+    WebSocketPriceFeed {
+        exchange: Exchange::Bybit,
+        // The spot `tickers` topic has no best bid and ask, so they come from the level 1 book.
+        connections: vec![Connection::all_coins(
+            "wss://stream.bybit.com/v5/public/spot",
+            vec![json!({
+                "op": "subscribe",
+                "args": args,
+            })],
+        )],
+        symbols,
+        idle_timeout: Duration::from_secs(30),
+        keepalive: None,
+        // Bybit recommends sending a `ping` every 20 seconds to keep the connection alive.
+        scheduled: vec![ScheduledMessage {
+            interval: Duration::from_secs(20),
+            message: json!({ "op": "ping" }),
+        }],
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        mirrors: Vec::new(),
+        message_handler,
+    }
+}
+
+/// This struct represents the envelope of any message, which is used to dispatch it.
+/// Topic messages have a `topic`, whereas responses to our requests have an `op`.
+#[derive(Deserialize)]
+struct Header<'a> {
+    topic: Option<&'a str>,
+    op: Option<&'a str>,
+}
+
+/// These structs represent a message from the `tickers` topic. See this link for reference:
+/// https://bybit-exchange.github.io/docs/v5/websocket/public/ticker
+#[derive(Deserialize)]
+struct TickerMessage<'a> {
+    ts: u64,
+    #[serde(borrow)]
+    data: TickerData<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TickerData<'a> {
+    symbol: &'a str,
+    last_price: &'a str,
+}
+
+/// These structs represent a message from the `orderbook.1` topic. See this link for reference:
+/// https://bybit-exchange.github.io/docs/v5/websocket/public/orderbook
+#[derive(Deserialize)]
+struct OrderBookMessage<'a> {
+    ts: u64,
+    #[serde(borrow)]
+    data: OrderBookData<'a>,
+}
+
+#[derive(Deserialize)]
+struct OrderBookData<'a> {
+    s: &'a str,
+    #[serde(borrow)]
+    b: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    a: Vec<(&'a str, &'a str)>,
+}
+
+/// This struct represents the response to a request, e.g. `subscribe` or `ping`.
+/// See this link for reference: https://bybit-exchange.github.io/docs/v5/ws/connect
+#[derive(Deserialize)]
+struct Response {
+    success: bool,
+    ret_msg: String,
+}
+
+/// This message handler dispatches a message on its topic or operation.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    parse_message(session, &message).into_iter().collect()
+}
+
+/// This function is a helper to parse a message, which carries at most one event.
+fn parse_message(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize the header.
+    let header = match serde_json::from_str::<Header>(message) {
+        Ok(header) => header,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Dispatch on the topic or operation.
+    match (header.topic, header.op) {
+        (Some(topic), _) if topic.starts_with("tickers.") => ticker_handler(session, message),
+        (Some(topic), _) if topic.starts_with("orderbook.1.") => {
+            order_book_handler(session, message)
+        }
+        (None, Some("subscribe")) => response_handler(message),
+        (None, Some("ping" | "pong")) => Some(Event::Heartbeat),
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This message handler tries to parse the last price for an altcoin.
+fn ticker_handler(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<TickerMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin and last price.
+    let coin = session.symbols.coin(message.data.symbol)?;
+    let price = crate::price::str_to_f64(message.data.last_price)?;
+    Some(Event::Trade(Trade {
+        coin,
+        price,
+        quantity: None,
+        sequence: Some(Sequence::Time(message.ts)),
+        time: Some(message.ts),
+    }))
+}
+
+/// This message handler tries to parse the best bid and best ask for an altcoin.
+fn order_book_handler(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<OrderBookMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, best bid, and best ask. A delta omits the side of the book that didn't
+    // change.
+    let coin = session.symbols.coin(message.data.s)?;
+    let best_bid = match message.data.b.first() {
+        Some((best_bid, _)) => Some(crate::price::str_to_f64(best_bid)?),
+        None => None,
+    };
+    let best_ask = match message.data.a.first() {
+        Some((best_ask, _)) => Some(crate::price::str_to_f64(best_ask)?),
+        None => None,
+    };
+    if best_bid.is_none() && best_ask.is_none() {
+        return None;
+    }
+    Some(Event::Quote(Quote {
+        coin,
+        best_bid,
+        best_ask,
+        sequence: Some(Sequence::Time(message.ts)),
+        time: Some(message.ts),
+    }))
+}
+
+/// This message handler tries to parse the response to the subscribe request.
+/// A successful response confirms the subscription of all the altcoins at once.
+fn response_handler(message: &str) -> Option<Event> {
+    match serde_json::from_str::<Response>(message) {
+        Ok(response) if response.success => Some(Event::Subscribed(Coin::ALL.to_vec())),
+        Ok(response) => Some(Event::Rejected(response.ret_msg)),
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// These structs represent the response of the REST tickers. See this link for reference:
+/// https://bybit-exchange.github.io/docs/v5/market/tickers
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TickerResponse<'a> {
+    ret_code: i64,
+    #[serde(borrow)]
+    result: TickerResult<'a>,
+}
+
+#[derive(Deserialize)]
+struct TickerResult<'a> {
+    #[serde(borrow)]
+    list: Vec<TickerResultData<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TickerResultData<'a> {
+    last_price: &'a str,
+    bid1_price: &'a str,
+    ask1_price: &'a str,
+}
+
+/// This function is a helper to build the path of the REST ticker of a symbol, i.e. the symbol.
+fn poll_path(symbol: &str) -> String {
+    symbol.to_string()
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let response = serde_json::from_str::<TickerResponse>(response).ok()?;
+    if response.ret_code != 0 {
+        return None;
+    }
+    let ticker = response.result.list.first()?;
+    Some([
+        crate::price::str_to_f64(ticker.last_price)?,
+        crate::price::str_to_f64(ticker.bid1_price)?,
+        crate::price::str_to_f64(ticker.ask1_price)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Bybit)
+    }
+
+    #[test]
+    fn ticker_message() {
+        let message = r#"{"topic":"tickers.ETHUSDT","ts":1673853746003,"type":"snapshot","cs":2588407389,"data":{"symbol":"ETHUSDT","lastPrice":"1551.52","highPrice24h":"1580.00","lowPrice24h":"1530.12","prevPrice24h":"1540.10","volume24h":"125023.1","turnover24h":"193999999.1","price24hPcnt":"0.0074","usdIndexPrice":"1551.30"}}"#;
+        let expected = Event::Trade(Trade {
+            coin: Coin::ETH,
+            price: 1551.52,
+            quantity: None,
+            sequence: Some(Sequence::Time(1673853746003)),
+            time: Some(1673853746003),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn order_book_message() {
+        let message = r#"{"topic":"orderbook.1.DOGEUSDT","ts":1673853746012,"type":"snapshot","data":{"s":"DOGEUSDT","b":[["0.08412","120553"]],"a":[["0.08413","98012"]],"u":3061935,"seq":24519203488},"cts":1673853746009}"#;
+        let expected = Event::Quote(Quote {
+            coin: Coin::DOGE,
+            best_bid: Some(0.08412),
+            best_ask: Some(0.08413),
+            sequence: Some(Sequence::Time(1673853746012)),
+            time: Some(1673853746012),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+
+        // The side of the book that didn't change is kept.
+        let message = r#"{"topic":"orderbook.1.DOGEUSDT","ts":1673853746052,"type":"delta","data":{"s":"DOGEUSDT","b":[],"a":[["0.08414","51000"]],"u":3061936,"seq":24519203512},"cts":1673853746049}"#;
+        let expected = Event::Quote(Quote {
+            coin: Coin::DOGE,
+            best_bid: None,
+            best_ask: Some(0.08414),
+            sequence: Some(Sequence::Time(1673853746052)),
+            time: Some(1673853746052),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn subscribe_messages() {
+        let message = r#"{"success":true,"ret_msg":"subscribe","conn_id":"2324d924-aa4d-45b0-a858-7b8be29ab52b","req_id":"","op":"subscribe"}"#;
+        let expected = Event::Subscribed(Coin::ALL.to_vec());
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+
+        let message = r#"{"success":false,"ret_msg":"Invalid topic :[tickers.FOOUSDT]","conn_id":"2324d924-aa4d-45b0-a858-7b8be29ab52b","req_id":"","op":"subscribe"}"#;
+        let expected = Event::Rejected("Invalid topic :[tickers.FOOUSDT]".to_string());
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn pong_message() {
+        let message = r#"{"success":true,"ret_msg":"pong","conn_id":"0970e817-426e-429a-a679-ff7f55e0b16a","op":"ping"}"#;
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![Event::Heartbeat]
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"retCode":0,"retMsg":"OK","result":{"category":"spot","list":[{"symbol":"ETHUSDT","bid1Price":"1551.50","bid1Size":"2.1","ask1Price":"1551.53","ask1Size":"0.5","lastPrice":"1551.52","prevPrice24h":"1540.10","price24hPcnt":"0.0074","highPrice24h":"1580.00","lowPrice24h":"1530.12","turnover24h":"193999999.1","volume24h":"125023.1"}]},"retExtInfo":{},"time":1673853746003}"#;
+        assert_eq!(poll_handler(response), Some([1551.52, 1551.5, 1551.53]));
+
+        let response = r#"{"retCode":10001,"retMsg":"Not supported symbols","result":{},"retExtInfo":{},"time":1673853746003}"#;
+        assert_eq!(poll_handler(response), None);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::config::CaptureConfig;
+use crate::metrics::METRICS;
+use altusd::{Coin, Exchange};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The interval at which the buffered frames of an exchange are flushed to the capture file, so
+/// that it can be read while it's being written. The exchanges send messages at least every few
+/// seconds, whereas the supply API is only polled every minute, so its responses are flushed
+/// right away.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The name of the capture files of the supply API.
+pub const SUPPLY: &str = "supply";
+
+/// This struct represents a line of a capture file, i.e. a frame received from an exchange along
+/// with its receive time in ms since epoch and the ID of the connection it was received on.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    pub received: u64,
+    pub connection: usize,
+    pub frame: Frame,
+}
+
+/// This enum represents a captured frame. Binary frames are hex-encoded as they were received,
+/// i.e. usually compressed. The REST snapshots of the order books are captured too, since the
+/// depth updates can't be replayed without them, and so are the responses of the supply API.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frame {
+    /// The connection was (re)established, so the frames that follow belong to a new session.
+    Connected,
+    Text(String),
+    Binary(String),
+    Snapshot(Coin, String),
+    Supply(String),
+}
+
+/// This struct writes the frames of an exchange to rotating gzip-compressed files of JSON lines,
+/// named after the exchange, their creation time in ms since epoch, and their index, e.g.
+/// `binance-1673853746151-0.jsonl.gz`. The connections of the exchange share the same file.
+/// The responses of the supply API are captured the same way, in `supply-*.jsonl.gz` files.
+pub struct Capture {
+    name: &'static str,
+    /// The exchange whose capture errors are counted in the metrics, if any.
+    exchange: Option<Exchange>,
+    directory: PathBuf,
+    rotate_bytes: u64,
+    rotate_interval: Duration,
+    flush_interval: Duration,
+    file: Mutex<CaptureState>,
+}
+
+/// This struct represents the state of a capture, which is shared by the connections.
+#[derive(Default)]
+struct CaptureState {
+    current: Option<CaptureFile>,
+    /// The number of files created so far, which disambiguates the ones created in the same ms.
+    files: u64,
+}
+
+/// This struct represents the capture file being written.
+struct CaptureFile {
+    encoder: GzEncoder<BufWriter<File>>,
+    /// The number of bytes written before compression.
+    written: u64,
+    created_at: Instant,
+    flushed_at: Instant,
+}
+
+impl Capture {
+    /// Create the capture of an exchange, unless the capture is disabled. The first file is only
+    /// created once the first frame is received.
+    pub fn new(exchange: Exchange, config: &CaptureConfig) -> Option<Self> {
+        Self::named(exchange.name(), Some(exchange), FLUSH_INTERVAL, config)
+    }
+
+    /// Create the capture of the supply API, unless the capture is disabled.
+    pub fn supply(config: &CaptureConfig) -> Option<Self> {
+        Self::named(SUPPLY, None, Duration::ZERO, config)
+    }
+
+    fn named(
+        name: &'static str,
+        exchange: Option<Exchange>,
+        flush_interval: Duration,
+        config: &CaptureConfig,
+    ) -> Option<Self> {
+        let directory = config.directory.as_ref()?;
+        Some(Self {
+            name,
+            exchange,
+            directory: PathBuf::from(directory),
+            rotate_bytes: config.rotate_bytes,
+            rotate_interval: config.rotate_interval(),
+            flush_interval,
+            file: Mutex::new(CaptureState::default()),
+        })
+    }
+
+    /// Append a frame to the capture. Errors are logged and counted, and the failed file is
+    /// abandoned so that the next frame goes to a new one.
+    pub fn record(&self, connection: usize, received: u64, frame: Frame) {
+        let record = Record {
+            received,
+            connection,
+            frame,
+        };
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(error) => {
+                tracing::error!("failed to serialize capture: {}: {}", self.name, error);
+                self.failed();
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut state = match self.file.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(error) = self.write(&mut state, line.as_bytes()) {
+            tracing::error!("failed to write capture: {}: {}", self.name, error);
+            self.failed();
+            state.current = None;
+        }
+    }
+
+    /// Count a capture error in the metrics, which are labeled by exchange.
+    fn failed(&self) {
+        if let Some(exchange) = self.exchange {
+            METRICS.increment("capture_errors_total", exchange);
+        }
+    }
+
+    /// Write a line to the current file, after rotating it if it's full or too old.
+    fn write(&self, state: &mut CaptureState, line: &[u8]) -> std::io::Result<()> {
+        if let Some(file) = &state.current {
+            if file.written >= self.rotate_bytes
+                || file.created_at.elapsed() >= self.rotate_interval
+            {
+                if let Some(file) = state.current.take() {
+                    file.encoder.finish()?.flush()?;
+                }
+            }
+        }
+        let file = match &mut state.current {
+            Some(file) => file,
+            None => {
+                let file = self.create(state.files)?;
+                state.files += 1;
+                state.current.insert(file)
+            }
+        };
+
+        file.encoder.write_all(line)?;
+        file.written += line.len() as u64;
+        if file.flushed_at.elapsed() >= self.flush_interval {
+            file.encoder.flush()?;
+            file.flushed_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Create a new capture file in the capture directory.
+    fn create(&self, index: u64) -> std::io::Result<CaptureFile> {
+        std::fs::create_dir_all(&self.directory)?;
+        let created = crate::engine::unix_millis().unwrap_or_default();
+        let name = format!("{}-{}-{}.jsonl.gz", self.name, created, index);
+        let path = self.directory.join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        tracing::info!("capturing frames: {}: {}", self.name, path.display());
+        Ok(CaptureFile {
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            written: 0,
+            created_at: Instant::now(),
+            flushed_at: Instant::now(),
+        })
+    }
+}
+
+/// This function is a helper to encode binary frames in lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// This function is a helper to decode a hex-encoded binary frame. It returns `None` if the
+/// string isn't valid hexadecimal.
+pub fn unhex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 && pair.bytes().all(|byte| byte.is_ascii_hexdigit()) => {
+                u8::from_str_radix(pair, 16).ok()
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn capture_rotation() {
+        let directory = std::env::temp_dir().join(format!("altusd-capture-{}", std::process::id()));
+        let config = CaptureConfig {
+            directory: Some(directory.display().to_string()),
+            rotate_bytes: 100,
+            rotate_secs: 3600,
+        };
+        let capture = Capture::new(Exchange::Htx, &config).unwrap();
+        capture.record(0, 1673853746151, Frame::Connected);
+        capture.record(0, 1673853746152, Frame::Binary(hex(&[0x1f, 0x8b, 0x08])));
+        capture.record(1, 1673853746153, Frame::Text(r#"{"ping":1}"#.to_string()));
+        capture.record(
+            1,
+            1673853746154,
+            Frame::Snapshot(Coin::ETH, "{}".to_string()),
+        );
+        drop(capture);
+
+        // The first file is rotated once it exceeds 100 bytes, and the last one is finished when
+        // the capture is dropped.
+        let mut paths: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path().display().to_string())
+            .collect();
+        paths.sort_by_key(|path| path.rsplit('-').next().map(String::from));
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].ends_with("-0.jsonl.gz"));
+        assert!(paths[1].ends_with("-1.jsonl.gz"));
+        let lengths: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let reader = BufReader::new(GzDecoder::new(File::open(path).unwrap()));
+                reader.lines().count()
+            })
+            .collect();
+        assert_eq!(lengths, vec![2, 2]);
+        let records: Vec<Record> = paths
+            .iter()
+            .flat_map(|path| BufReader::new(GzDecoder::new(File::open(path).unwrap())).lines())
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let expected = vec![
+            Record {
+                received: 1673853746151,
+                connection: 0,
+                frame: Frame::Connected,
+            },
+            Record {
+                received: 1673853746152,
+                connection: 0,
+                frame: Frame::Binary("1f8b08".to_string()),
+            },
+            Record {
+                received: 1673853746153,
+                connection: 1,
+                frame: Frame::Text(r#"{"ping":1}"#.to_string()),
+            },
+            Record {
+                received: 1673853746154,
+                connection: 1,
+                frame: Frame::Snapshot(Coin::ETH, "{}".to_string()),
+            },
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn hex_frames() {
+        let frame = [0x1f, 0x8b, 0x08, 0x00, 0xff];
+        assert_eq!(hex(&frame), "1f8b0800ff");
+        assert_eq!(unhex("1f8b0800ff"), Some(frame.to_vec()));
+        assert_eq!(unhex("1f8b0"), None);
+        assert_eq!(unhex("1g"), None);
+        assert_eq!(unhex("+1"), None);
+    }
+}
@@ -1,50 +1,162 @@
+use crate::admin::DisabledExchanges;
+use crate::book::{OrderBook, Side};
+use crate::config::FeedConfig;
 use crate::engine::Input;
-use crate::price::WebSocketPriceFeed;
-use altusd::{Coin, Exchange};
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Sequence, Session, Trade, WebSocketPriceFeed};
+use altusd::Exchange;
 use serde::Deserialize;
 use serde_json::json;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The REST endpoint that serves the product tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.exchange.coinbase.com/products/";
 
 /// This function is responsible to subscribe to the Coinbase websocket price feed.
 ///
 /// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
-pub async fn run(mpsc_tx: Sender<Input>) {
-    let websocket_price_feed = WebSocketPriceFeed {
-        endpoint: "wss://ws-feed.exchange.coinbase.com",
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Coinbase websocket price feed, which is also used to replay its
+/// captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    let product_ids: Vec<&str> = symbols.iter().map(|(_, product_id)| product_id).collect();
+    WebSocketPriceFeed {
         exchange: Exchange::Coinbase,
-        subscribe: json!({
-            "type": "subscribe",
-            "product_ids": [
-                "ADA-USD",
-                "DOGE-USD",
-                "DOT-USD",
-                "ETH-USD",
-                "SOL-USD",
-            ],
-            "channels": ["ticker"],
+        connections: vec![Connection::all_coins(
+            "wss://ws-feed.exchange.coinbase.com",
+            vec![json!({
+                "type": "subscribe",
+                "product_ids": product_ids,
+                "channels": ["heartbeat", "level2_batch", "matches"],
+            })],
+        )],
+        symbols,
+        // The `heartbeat` channel sends a message every second for each product.
+        idle_timeout: Duration::from_secs(10),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
         }),
+        mirrors: Vec::new(),
         message_handler,
-    };
-    websocket_price_feed.run(mpsc_tx).await;
+    }
 }
 
-/// This struct represents a message from the `ticker` channel. See this link for reference:
-/// https://docs.cloud.coinbase.com/exchange/docs/websocket-channels#the-ticker-channel
+/// This struct represents the type of any message, which is used to dispatch it.
 #[derive(Deserialize)]
-struct Message<'a> {
+struct Header<'a> {
     r#type: &'a str,
+}
+
+/// These structs represent the messages from the `level2_batch` channel, i.e. a snapshot of the
+/// order book, and then the changed levels batched every 50ms. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-channels#level2-batch-channel
+#[derive(Deserialize)]
+struct SnapshotMessage<'a> {
+    product_id: &'a str,
+    #[serde(borrow)]
+    bids: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    asks: Vec<(&'a str, &'a str)>,
+}
+
+#[derive(Deserialize)]
+struct UpdateMessage<'a> {
+    product_id: &'a str,
+    time: &'a str,
+    #[serde(borrow)]
+    changes: Vec<(&'a str, &'a str, &'a str)>,
+}
+
+/// This struct represents a message from the `matches` channel, i.e. a trade. The trade IDs of a
+/// product increase by one for every trade. The last trade is also sent as a `last_match`
+/// message right after subscribing. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-channels#match
+#[derive(Deserialize)]
+struct MatchMessage<'a> {
+    trade_id: u64,
     product_id: &'a str,
+    time: &'a str,
+    size: &'a str,
     price: &'a str,
-    best_bid: &'a str,
-    best_ask: &'a str,
 }
 
-/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+/// These structs represent the `subscriptions` message, which lists the subscribed products
+/// of each channel. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-overview#subscribe
+#[derive(Deserialize)]
+struct SubscriptionsMessage<'a> {
+    #[serde(borrow)]
+    channels: Vec<SubscriptionsChannel<'a>>,
+}
+
+#[derive(Deserialize)]
+struct SubscriptionsChannel<'a> {
+    name: &'a str,
+    product_ids: Vec<&'a str>,
+}
+
+/// This struct represents an `error` message, e.g. for an invalid product. See this link:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-errors
+#[derive(Deserialize)]
+struct ErrorMessage {
+    message: String,
+    #[serde(default)]
+    reason: String,
+}
+
+/// This message handler dispatches a message on its type. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/docs/websocket-channels#the-heartbeat-channel
 ///
 /// Effectively, this is a callback used for every websocket received from the exchange.
-fn message_handler(message: String) -> Option<(Coin, [f64; 3])> {
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    parse_message(session, &message).into_iter().collect()
+}
+
+/// This function is a helper to parse a message, which carries at most one event.
+fn parse_message(session: &mut Session, message: &str) -> Option<Event> {
+    // Deserialize the header.
+    let header = match serde_json::from_str::<Header>(message) {
+        Ok(header) => header,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Dispatch on the message type.
+    match header.r#type {
+        "snapshot" => snapshot_handler(session, message),
+        "l2update" => update_handler(session, message),
+        "match" | "last_match" => match_handler(session, message),
+        "heartbeat" => Some(Event::Heartbeat),
+        "subscriptions" => subscriptions_handler(session, message),
+        "error" => error_handler(message),
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This message handler tries to replace the order book of an altcoin.
+fn snapshot_handler(session: &mut Session, message: &str) -> Option<Event> {
     // Deserialize.
-    let message = match serde_json::from_str::<Message>(&message) {
+    let message = match serde_json::from_str::<SnapshotMessage>(message) {
         Ok(message) => message,
         Err(_) => {
             tracing::warn!("discarded message: {}", message);
@@ -52,28 +164,211 @@ fn message_handler(message: String) -> Option<(Coin, [f64; 3])> {
         }
     };
 
-    // Validate message type.
-    if message.r#type != "ticker" {
-        tracing::error!("unexpected message type: {}", message.r#type);
-        return None;
+    // Extract coin, and replace the book.
+    let coin = session.symbols.coin(message.product_id)?;
+    let mut book = OrderBook::default();
+    for (price, size) in message.bids {
+        book.update(Side::Bid, price, size);
     }
+    for (price, size) in message.asks {
+        book.update(Side::Ask, price, size);
+    }
+    session.books.insert(coin, book);
+    Some(Event::Book(coin, None))
+}
 
-    // Extract coin.
-    let coin = match message.product_id {
-        "ADA-USD" => Coin::ADA,
-        "DOGE-USD" => Coin::DOGE,
-        "DOT-USD" => Coin::DOT,
-        "ETH-USD" => Coin::ETH,
-        "SOL-USD" => Coin::SOL,
-        _ => {
-            tracing::error!("unexpected message coin: {}", message.product_id);
+/// This message handler tries to apply the changed levels to the order book of an altcoin.
+fn update_handler(session: &mut Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<UpdateMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, and find its book. The snapshot is always sent first.
+    let coin = session.symbols.coin(message.product_id)?;
+    let book = match session.books.get_mut(&coin) {
+        Some(book) => book,
+        None => {
+            tracing::error!("unexpected update before snapshot: {:?}", coin);
             return None;
         }
     };
 
-    // Extract last price, best bid, and best ask.
-    let last_price = crate::price::str_to_f64(message.price)?;
-    let best_bid = crate::price::str_to_f64(message.best_bid)?;
-    let best_ask = crate::price::str_to_f64(message.best_ask)?;
-    Some((coin, [last_price, best_bid, best_ask]))
+    // Apply the changes.
+    for (side, price, size) in message.changes {
+        match side {
+            "buy" => book.update(Side::Bid, price, size),
+            "sell" => book.update(Side::Ask, price, size),
+            _ => {
+                tracing::error!("unexpected change side: {}", side);
+                None
+            }
+        };
+    }
+    let time = crate::price::rfc3339_to_millis(message.time);
+    Some(Event::Book(coin, time))
+}
+
+/// This message handler tries to parse the price and size of a trade of an altcoin.
+fn match_handler(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<MatchMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, price, size, and time.
+    let coin = session.symbols.coin(message.product_id)?;
+    let price = crate::price::str_to_f64(message.price)?;
+    let size = crate::price::str_to_f64(message.size)?;
+    Some(Event::Trade(Trade {
+        coin,
+        price,
+        quantity: Some(size),
+        sequence: Some(Sequence::Number(message.trade_id)),
+        time: crate::price::rfc3339_to_millis(message.time),
+    }))
+}
+
+/// This message handler tries to parse the products subscribed to the `level2_batch` channel.
+fn subscriptions_handler(session: &Session, message: &str) -> Option<Event> {
+    let message = match serde_json::from_str::<SubscriptionsMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    let coins = message
+        .channels
+        .iter()
+        .filter(|channel| channel.name == "level2_batch")
+        .flat_map(|channel| channel.product_ids.iter())
+        .filter_map(|product_id| session.symbols.coin(product_id))
+        .collect();
+    Some(Event::Subscribed(coins))
+}
+
+/// This message handler tries to parse an error. Coinbase rejects the whole subscribe request
+/// if any of its products is invalid, so the error is always treated as a rejection.
+fn error_handler(message: &str) -> Option<Event> {
+    match serde_json::from_str::<ErrorMessage>(message) {
+        Ok(error) => Some(Event::Rejected(format!(
+            "{}: {}",
+            error.message, error.reason
+        ))),
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This struct represents a product ticker. See this link for reference:
+/// https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductticker
+#[derive(Deserialize)]
+struct TickerResponse<'a> {
+    price: &'a str,
+    bid: &'a str,
+    ask: &'a str,
+}
+
+/// This function is a helper to build the path of the REST ticker of a product.
+fn poll_path(product_id: &str) -> String {
+    format!("{}/ticker", product_id)
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let ticker = serde_json::from_str::<TickerResponse>(response).ok()?;
+    Some([
+        crate::price::str_to_f64(ticker.price)?,
+        crate::price::str_to_f64(ticker.bid)?,
+        crate::price::str_to_f64(ticker.ask)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use altusd::Coin;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Coinbase)
+    }
+
+    #[test]
+    fn level2_messages() {
+        let mut session = session();
+        let message = r#"{"type":"snapshot","product_id":"ETH-USD","bids":[["3000.10","1.5"],["3000.00","4.0"]],"asks":[["3000.20","2.0"]]}"#;
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+
+        let message = r#"{"type":"l2update","product_id":"ETH-USD","changes":[["buy","3000.10","0.00000000"],["sell","3000.15","0.5"]],"time":"2023-01-16T07:22:26.151385Z"}"#;
+        let expected = vec![Event::Book(Coin::ETH, Some(1673853746151))];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.bids().next().unwrap().price, "3000.00");
+        assert_eq!(book.asks().next().unwrap().price, "3000.15");
+    }
+
+    #[test]
+    fn update_before_snapshot() {
+        let message = r#"{"type":"l2update","product_id":"SOL-USD","changes":[["buy","21.05","10.0"]],"time":"2023-01-16T07:22:26.151385Z"}"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+    }
+
+    #[test]
+    fn match_message() {
+        let message = r#"{"type":"match","trade_id":4127832,"maker_order_id":"ac928c66-ca53-498f-9c13-a110027a60e8","taker_order_id":"132fb6ae-456b-4654-b4e0-d681ac05cea1","side":"sell","size":"0.25","price":"3000.17","product_id":"ETH-USD","sequence":52897483510,"time":"2023-01-16T07:22:26.151385Z"}"#;
+        let expected = vec![Event::Trade(Trade {
+            coin: Coin::ETH,
+            price: 3000.17,
+            quantity: Some(0.25),
+            sequence: Some(Sequence::Number(4127832)),
+            time: Some(1673853746151),
+        })];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn subscriptions_message() {
+        let message = r#"{"type":"subscriptions","channels":[{"name":"heartbeat","product_ids":["ADA-USD","ETH-USD"]},{"name":"level2_batch","product_ids":["ADA-USD","ETH-USD"]}]}"#;
+        let expected = vec![Event::Subscribed(vec![Coin::ADA, Coin::ETH])];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn error_message() {
+        let message = r#"{"type":"error","message":"Failed to subscribe","reason":"FOO-USD is not a valid product"}"#;
+        let expected = vec![Event::Rejected(
+            "Failed to subscribe: FOO-USD is not a valid product".to_string(),
+        )];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"ask":"3000.20","bid":"3000.10","volume":"152031.12","trade_id":4127832,"price":"3000.17","size":"0.25","time":"2023-01-16T07:22:26.151385Z"}"#;
+        assert_eq!(poll_handler(response), Some([3000.17, 3000.1, 3000.2]));
+        assert_eq!(poll_path("ETH-USD"), "ETH-USD/ticker");
+    }
 }
@@ -1,3 +1,5 @@
+use crate::config::{EngineConfig, PriceSource};
+use crate::metrics::METRICS;
 use altusd::{Coin, Engine, Exchange};
 use serde::Serialize;
 use std::time::SystemTime;
@@ -5,30 +7,83 @@ use tokio::sync::mpsc::Receiver;
 use tokio::sync::watch::Sender;
 
 /// This struct represents the input of the core engine, which is received through a mpsc channel.
+/// Market prices are tagged with their source, since they may be polled while a websocket is down,
+/// and with their timestamps. Exchanges are enabled or disabled by an operator.
 #[derive(Debug)]
 pub enum Input {
-    Price(Coin, Exchange, f64),
+    Price(Coin, Exchange, f64, Source, Timestamps),
+    Vwap(Coin, Exchange, f64),
     Supply(Coin, f64),
+    Enable(Exchange, bool),
+}
+
+/// This enum represents where a market price comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// The websocket feed of the exchange.
+    Stream,
+    /// The REST ticker of the exchange, which is polled while its websocket is down.
+    Poll,
+}
+
+/// This struct represents the timestamps of a market price, in milliseconds since the Unix epoch:
+/// the event time of the exchange message it comes from, if any, and the time it was received.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timestamps {
+    pub event: Option<u64>,
+    pub received: u64,
+}
+
+impl Timestamps {
+    /// Constructor for a market price received just now.
+    pub fn received_now(event: Option<u64>) -> Self {
+        Self {
+            event,
+            received: unix_millis().unwrap_or_default(),
+        }
+    }
 }
 
 impl Input {
     /// Constructor for the `Price` variant.
-    pub fn price(coin: Coin, exchange: Exchange, price: f64) -> Self {
-        Self::Price(coin, exchange, price)
+    pub fn price(
+        coin: Coin,
+        exchange: Exchange,
+        price: f64,
+        source: Source,
+        timestamps: Timestamps,
+    ) -> Self {
+        Self::Price(coin, exchange, price, source, timestamps)
+    }
+
+    /// Constructor for the `Vwap` variant.
+    pub fn vwap(coin: Coin, exchange: Exchange, vwap: f64) -> Self {
+        Self::Vwap(coin, exchange, vwap)
     }
 
     /// Constructor for the `Supply` variant.
     pub fn supply(coin: Coin, supply: f64) -> Self {
         Self::Supply(coin, supply)
     }
+
+    /// Constructor for the `Enable` variant.
+    pub fn enable(exchange: Exchange, enabled: bool) -> Self {
+        Self::Enable(exchange, enabled)
+    }
 }
 
 /// This struct represents the output of the core engine, which is sent through a watch channel.
-/// Note that `f64::NAN` deserializes to null in JSON.
+/// Note that `f64::NAN` and `None` deserialize to null in JSON.
+///
+/// The epoch is the computation time in seconds, whereas the other times are in milliseconds.
+/// The event and receive times are the latest ones of the market prices fed to the engine.
 #[derive(Debug, Serialize)]
 pub struct Output {
     pub epoch: u64,
     pub index: f64,
+    pub event_time_ms: Option<u64>,
+    pub receive_time_ms: Option<u64>,
+    pub compute_time_ms: u64,
 }
 
 impl Output {
@@ -37,6 +92,9 @@ impl Output {
         Self {
             epoch: 0,
             index: f64::NAN,
+            event_time_ms: None,
+            receive_time_ms: None,
+            compute_time_ms: 0,
         }
     }
 }
@@ -44,34 +102,128 @@ impl Output {
 /// This function is responsible for running the core index engine.
 /// It's a thin wrapper around the library to receive input and send output from channels.
 /// However, it's the library itself that contains the core business logic of the engine.
-pub async fn run(mut mpsc_rx: Receiver<Input>, watch_tx: Sender<Output>) {
+///
+/// The VWAPs are only fed to the engine if they are the configured price source.
+pub async fn run(config: EngineConfig, mut mpsc_rx: Receiver<Input>, watch_tx: Sender<Output>) {
     let mut engine = Engine::init();
+    let mut event_time_ms = None;
+    let mut receive_time_ms = None;
 
     // Wait for input messages from the mpsc channel in a loop...
     while let Some(input) = mpsc_rx.recv().await {
         tracing::info!("input message = {:?}", input);
 
         // Process the input message (i.e. updated price or supply) in the engine.
-        let index = match input {
-            Input::Price(coin, exchange, price) => engine.update_price(coin, exchange, price),
-            Input::Supply(coin, supply) => engine.update_supply(coin, supply),
+        let times = (&mut event_time_ms, &mut receive_time_ms);
+        let index = match process_input(&mut engine, &config, input, times) {
+            Some(index) => index,
+            None => continue,
         };
 
-        // Timestamp the updated index price with Unix time, i.e. the number of seconds
+        // Timestamp the updated index price with Unix time, i.e. the number of milliseconds
         // that have elapsed since 00:00:00 UTC on 1 January 1970.
-        let epoch = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => duration.as_secs(),
-            Err(error) => {
-                tracing::error!("failed to get system time: {}", error);
-                continue;
-            }
+        let compute_time_ms = match unix_millis() {
+            Some(compute_time_ms) => compute_time_ms,
+            None => continue,
         };
 
-        // Send the output message (i.e. updated index price with timestamp) on the watch channel.
-        let output = Output { epoch, index };
+        // Send the output message (i.e. updated index price with timestamps) on the watch channel.
+        let output = Output {
+            epoch: compute_time_ms / 1000,
+            index,
+            event_time_ms,
+            receive_time_ms,
+            compute_time_ms,
+        };
         tracing::info!("output message = {:?}", output);
         if let Err(error) = watch_tx.send(output) {
             tracing::error!("failed to send message in watch channel: {}", error);
         }
     }
 }
+
+/// This function is responsible for processing an input message in the engine, and returns the
+/// updated index price, or `None` if the input is ignored.
+///
+/// The event and receive times of the output only advance with the market prices that are used in
+/// the median, so that a disabled exchange, or a market price replaced by a VWAP, doesn't
+/// timestamp an index it didn't change.
+fn process_input(
+    engine: &mut Engine,
+    config: &EngineConfig,
+    input: Input,
+    (event_time_ms, receive_time_ms): (&mut Option<u64>, &mut Option<u64>),
+) -> Option<f64> {
+    let index = match input {
+        Input::Price(coin, exchange, price, source, timestamps) => {
+            let name = match source {
+                Source::Stream => "streamed_prices_total",
+                Source::Poll => "polled_prices_total",
+            };
+            METRICS.increment(name, exchange);
+            if engine.is_price_used(coin, exchange) {
+                *event_time_ms = (*event_time_ms).max(timestamps.event);
+                *receive_time_ms = (*receive_time_ms).max(Some(timestamps.received));
+            }
+            engine.update_price(coin, exchange, price)
+        }
+        Input::Vwap(coin, exchange, vwap) => match config.price_source {
+            PriceSource::Vwap => engine.update_vwap(coin, exchange, vwap),
+            PriceSource::Market => return None,
+        },
+        Input::Supply(coin, supply) => engine.update_supply(coin, supply),
+        Input::Enable(exchange, enabled) => engine.set_enabled(exchange, enabled),
+    };
+    Some(index)
+}
+
+/// This function is a helper to get the current Unix time in milliseconds.
+pub fn unix_millis() -> Option<u64> {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => Some(duration.as_millis() as u64),
+        Err(error) => {
+            tracing::error!("failed to get system time: {}", error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_of_used_prices() {
+        let mut engine = Engine::init();
+        let config = EngineConfig {
+            price_source: PriceSource::Vwap,
+        };
+        let (mut event_time_ms, mut receive_time_ms) = (None, None);
+        let mut process = |engine: &mut Engine, input| {
+            let times = (&mut event_time_ms, &mut receive_time_ms);
+            process_input(engine, &config, input, times);
+            (event_time_ms, receive_time_ms)
+        };
+        let price = |exchange, event, received| {
+            let timestamps = Timestamps { event, received };
+            Input::price(Coin::ETH, exchange, 3000.1, Source::Stream, timestamps)
+        };
+
+        // A used price advances the timestamps.
+        let input = price(Exchange::Binance, Some(1000), 1010);
+        assert_eq!(process(&mut engine, input), (Some(1000), Some(1010)));
+
+        // The price of a disabled exchange leaves them unchanged, even if its clock is ahead.
+        process(&mut engine, Input::enable(Exchange::Okx, false));
+        let input = price(Exchange::Okx, Some(9000), 9010);
+        assert_eq!(process(&mut engine, input), (Some(1000), Some(1010)));
+
+        // So does a market price replaced by a VWAP.
+        process(
+            &mut engine,
+            Input::vwap(Coin::ETH, Exchange::Kraken, 3000.2),
+        );
+        let input = price(Exchange::Kraken, Some(2000), 2010);
+        assert_eq!(process(&mut engine, input), (Some(1000), Some(1010)));
+    }
+}
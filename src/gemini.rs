@@ -0,0 +1,261 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Quote, Sequence, Session, Trade, WebSocketPriceFeed};
+use altusd::{Coin, Exchange};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The endpoint of the market data feed, which streams a single symbol per connection.
+const ENDPOINT: &str = "wss://api.gemini.com/v1/marketdata";
+
+/// The REST endpoint that serves the tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.gemini.com/v1/pubticker/";
+
+/// This function is responsible to subscribe to the Gemini websocket price feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Gemini websocket price feed, which is also used to replay its captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    WebSocketPriceFeed {
+        exchange: Exchange::Gemini,
+        // The symbol is part of the URL, so there is one connection per altcoin and no request
+        // to send. Only the changes to the top of the book and the trades are streamed.
+        connections: symbols
+            .iter()
+            .map(|(coin, symbol)| Connection {
+                endpoint: format!(
+                    "{}/{}?heartbeat=true&top_of_book=true&trades=true",
+                    ENDPOINT, symbol
+                ),
+                subscribe: Vec::new(),
+                coins: vec![coin],
+            })
+            .collect(),
+        symbols,
+        // The `heartbeat` parameter makes Gemini send a heartbeat every 5 seconds.
+        idle_timeout: Duration::from_secs(10),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        mirrors: Vec::new(),
+        message_handler,
+    }
+}
+
+/// These structs represent a message of the market data feed. See this link for reference:
+/// https://docs.gemini.com/websocket-api/#market-data
+///
+/// The messages don't carry the symbol, which is known from the connection.
+#[derive(Deserialize)]
+struct Message<'a> {
+    r#type: &'a str,
+    socket_sequence: u64,
+    timestampms: Option<u64>,
+    #[serde(borrow, default)]
+    events: Vec<MessageEvent<'a>>,
+}
+
+#[derive(Deserialize)]
+struct MessageEvent<'a> {
+    r#type: &'a str,
+    price: Option<&'a str>,
+    amount: Option<&'a str>,
+    remaining: Option<&'a str>,
+    side: Option<&'a str>,
+}
+
+/// This message handler tries to parse the last price, best bid, and best ask for the altcoin of
+/// the connection. A single update message may carry both a trade and a change of the book.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    // Deserialize.
+    let parsed = match serde_json::from_str::<Message>(&message) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
+        }
+    };
+
+    // Extract coin.
+    let coin = match session.coins.first() {
+        Some(coin) => *coin,
+        None => {
+            tracing::error!("unexpected connection without coin");
+            return Vec::new();
+        }
+    };
+
+    // Gemini doesn't acknowledge the subscription, so the first message of the connection
+    // confirms it.
+    let mut events = Vec::new();
+    if parsed.socket_sequence == 0 {
+        events.push(Event::Subscribed(vec![coin]));
+    }
+
+    // Dispatch on the message type.
+    match parsed.r#type {
+        "update" => events.extend(update_handler(coin, &parsed)),
+        "heartbeat" => events.push(Event::Heartbeat),
+        _ => tracing::warn!("discarded message: {}", message),
+    }
+    events
+}
+
+/// This message handler tries to parse the trades and the changes of the top of the book.
+/// A removed price level is ignored, since the new best price is sent as another change.
+fn update_handler(coin: Coin, message: &Message) -> Vec<Event> {
+    let sequence = message.timestampms.map(Sequence::Time);
+    let mut best_bid = None;
+    let mut best_ask = None;
+    let mut events = Vec::new();
+    for event in &message.events {
+        let price = match event.price.and_then(crate::price::str_to_f64) {
+            Some(price) => price,
+            None => continue,
+        };
+        match (event.r#type, event.side) {
+            ("trade", _) => events.push(Event::Trade(Trade {
+                coin,
+                price,
+                quantity: event.amount.and_then(crate::price::str_to_f64),
+                sequence,
+                time: message.timestampms,
+            })),
+            ("change", side) if event.remaining != Some("0") => match side {
+                Some("bid") => best_bid = Some(f64::max(price, best_bid.unwrap_or(price))),
+                Some("ask") => best_ask = Some(f64::min(price, best_ask.unwrap_or(price))),
+                _ => tracing::warn!("unexpected change side: {:?}", side),
+            },
+            _ => {}
+        }
+    }
+    if best_bid.is_some() || best_ask.is_some() {
+        events.push(Event::Quote(Quote {
+            coin,
+            best_bid,
+            best_ask,
+            sequence,
+            time: message.timestampms,
+        }));
+    }
+    events
+}
+
+/// This struct represents a ticker. See this link for reference:
+/// https://docs.gemini.com/rest-api/#ticker
+#[derive(Deserialize)]
+struct TickerResponse<'a> {
+    last: &'a str,
+    bid: &'a str,
+    ask: &'a str,
+}
+
+/// This function is a helper to build the path of the REST ticker of a symbol, which is
+/// lowercase.
+fn poll_path(symbol: &str) -> String {
+    symbol.to_lowercase()
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let ticker = serde_json::from_str::<TickerResponse>(response).ok()?;
+    Some([
+        crate::price::str_to_f64(ticker.last)?,
+        crate::price::str_to_f64(ticker.bid)?,
+        crate::price::str_to_f64(ticker.ask)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session of a connection that streams ETH.
+    fn session() -> Session {
+        Session {
+            coins: vec![Coin::ETH],
+            ..Session::default()
+        }
+    }
+
+    #[test]
+    fn initial_message() {
+        let message = r#"{"type":"update","eventId":5375461993,"socket_sequence":0,"events":[{"type":"change","reason":"initial","price":"3641.61","delta":"0.83372051","remaining":"0.83372051","side":"bid"},{"type":"change","reason":"initial","price":"3641.62","delta":"4.072","remaining":"4.072","side":"ask"}]}"#;
+        let expected = vec![
+            Event::Subscribed(vec![Coin::ETH]),
+            Event::Quote(Quote {
+                coin: Coin::ETH,
+                best_bid: Some(3641.61),
+                best_ask: Some(3641.62),
+                sequence: None,
+                time: None,
+            }),
+        ];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn trade_message() {
+        let message = r#"{"type":"update","eventId":5375547515,"timestamp":1547760288,"timestampms":1547760288001,"socket_sequence":15,"events":[{"type":"trade","tid":5375547515,"price":"3632.54","amount":"0.1362819142","makerSide":"ask"},{"type":"change","side":"ask","price":"3632.54","remaining":"0","delta":"-0.1362819142","reason":"trade"},{"type":"change","side":"ask","price":"3632.60","remaining":"1.5","delta":"1.5","reason":"top-of-book"}]}"#;
+        let expected = vec![
+            Event::Trade(Trade {
+                coin: Coin::ETH,
+                price: 3632.54,
+                quantity: Some(0.1362819142),
+                sequence: Some(Sequence::Time(1547760288001)),
+                time: Some(1547760288001),
+            }),
+            Event::Quote(Quote {
+                coin: Coin::ETH,
+                best_bid: None,
+                best_ask: Some(3632.6),
+                sequence: Some(Sequence::Time(1547760288001)),
+                time: Some(1547760288001),
+            }),
+        ];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn heartbeat_message() {
+        let message = r#"{"type":"heartbeat","socket_sequence":30}"#;
+        let expected = vec![Event::Heartbeat];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"bid":"3641.61","ask":"3641.62","volume":{"ETH":"20612.5","USD":"75003011.3","timestamp":1547760288001},"last":"3641.60"}"#;
+        assert_eq!(poll_handler(response), Some([3641.6, 3641.61, 3641.62]));
+        assert_eq!(poll_path("ETHUSD"), "ethusd");
+    }
+}
@@ -0,0 +1,263 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Sequence, Session, Ticker, WebSocketPriceFeed};
+use altusd::{Coin, Exchange};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The REST endpoint that serves the merged tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.huobi.pro/market/detail/merged?symbol=";
+
+/// This function is responsible to subscribe to the HTX (formerly Huobi) websocket price feed.
+/// Every message is sent as a gzip-compressed binary frame, which is decompressed by the feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the HTX websocket price feed, which is also used to replay its captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    WebSocketPriceFeed {
+        exchange: Exchange::Htx,
+        // HTX only accepts one channel per subscribe request.
+        connections: vec![Connection::all_coins(
+            "wss://api.huobi.pro/ws",
+            symbols
+                .iter()
+                .map(|(_, symbol)| {
+                    let channel = format!("market.{}.ticker", symbol);
+                    json!({ "sub": channel, "id": channel })
+                })
+                .collect(),
+        )],
+        symbols,
+        // HTX sends a `ping` every 5 seconds, and closes the connection if it isn't answered.
+        idle_timeout: Duration::from_secs(20),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        // HTX serves the same API on AWS, which is closer to some regions.
+        mirrors: vec!["wss://api-aws.huobi.pro"],
+        message_handler,
+    }
+}
+
+/// This struct represents the envelope of any message, which is used to dispatch it.
+/// Channel messages have a `ch`, whereas responses to our requests have a `status`.
+#[derive(Deserialize)]
+struct Header<'a> {
+    ch: Option<&'a str>,
+    status: Option<&'a str>,
+    ping: Option<u64>,
+}
+
+/// These structs represent a message from the `ticker` channel. See this link for reference:
+/// https://www.htx.com/en-us/opend/newApiPages/?id=7ec53561-7773-11ed-9966-0242ac110003
+#[derive(Deserialize)]
+struct TickerMessage {
+    ts: u64,
+    tick: TickerData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TickerData {
+    last_price: f64,
+    bid: f64,
+    ask: f64,
+}
+
+/// This struct represents the response to a subscribe request, whose ID is the channel.
+#[derive(Deserialize)]
+struct Response<'a> {
+    id: Option<&'a str>,
+    #[serde(rename = "err-code")]
+    err_code: Option<&'a str>,
+    #[serde(rename = "err-msg")]
+    err_msg: Option<&'a str>,
+}
+
+/// This message handler dispatches a decompressed message on its channel or status.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    parse_message(session, &message).into_iter().collect()
+}
+
+/// This function is a helper to parse a message, which carries at most one event.
+fn parse_message(session: &Session, message: &str) -> Option<Event> {
+    // Deserialize the header.
+    let header = match serde_json::from_str::<Header>(message) {
+        Ok(header) => header,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Dispatch on the channel or status. The `ping` must be answered with a `pong`.
+    match (header.ch, header.status, header.ping) {
+        (_, _, Some(ping)) => Some(Event::Reply(json!({ "pong": ping }).to_string())),
+        (Some(channel), _, _) => ticker_handler(session, message, channel),
+        (None, Some("ok"), _) => response_handler(session, message).map(Event::Subscribed),
+        (None, Some("error"), _) => {
+            let response = serde_json::from_str::<Response>(message).ok()?;
+            Some(Event::Rejected(format!(
+                "{}: {}",
+                response.err_code.unwrap_or_default(),
+                response.err_msg.unwrap_or_default()
+            )))
+        }
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+fn ticker_handler(session: &Session, message: &str, channel: &str) -> Option<Event> {
+    // Deserialize.
+    let message = match serde_json::from_str::<TickerMessage>(message) {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    // Extract coin, last price, best bid, and best ask.
+    let coin = channel_to_coin(session, channel)?;
+    Some(Event::Ticker(Ticker {
+        coin,
+        prices: [message.tick.last_price, message.tick.bid, message.tick.ask],
+        sequence: Some(Sequence::Time(message.ts)),
+        time: Some(message.ts),
+    }))
+}
+
+/// This message handler tries to parse the altcoin confirmed by a successful subscribe response.
+fn response_handler(session: &Session, message: &str) -> Option<Vec<Coin>> {
+    match serde_json::from_str::<Response>(message) {
+        Ok(response) => Some(vec![channel_to_coin(session, response.id?)?]),
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This function is a helper to map an HTX channel (e.g. `market.ethusdt.ticker`) to an altcoin.
+fn channel_to_coin(session: &Session, channel: &str) -> Option<Coin> {
+    let symbol = channel
+        .strip_prefix("market.")
+        .and_then(|channel| channel.strip_suffix(".ticker"));
+    session.symbols.coin(symbol.unwrap_or(channel))
+}
+
+/// These structs represent a merged ticker, whose best bid and ask are made of a price and a size.
+/// See this link for reference: https://www.htx.com/en-us/opend/newApiPages/?id=7ec4a4da-7773-11ed-9966-0242ac110003
+#[derive(Deserialize)]
+struct TickerResponse<'a> {
+    status: &'a str,
+    tick: Option<MergedTickerData>,
+}
+
+#[derive(Deserialize)]
+struct MergedTickerData {
+    close: f64,
+    bid: (f64, f64),
+    ask: (f64, f64),
+}
+
+/// This function is a helper to build the path of the REST ticker of a symbol, i.e. the symbol.
+fn poll_path(symbol: &str) -> String {
+    symbol.to_string()
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let response = serde_json::from_str::<TickerResponse>(response).ok()?;
+    if response.status != "ok" {
+        return None;
+    }
+    let tick = response.tick?;
+    Some([tick.close, tick.bid.0, tick.ask.0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Htx)
+    }
+
+    #[test]
+    fn ticker_message() {
+        let message = r#"{"ch":"market.ethusdt.ticker","ts":1630982370526,"tick":{"open":3850.12,"high":3920.0,"low":3801.55,"close":3899.41,"amount":31254.2231,"vol":121049302.35,"count":245110,"bid":3899.4,"bidSize":2.31,"ask":3899.42,"askSize":0.583653,"lastPrice":3899.41,"lastSize":0.03}}"#;
+        let expected = Event::Ticker(Ticker {
+            coin: Coin::ETH,
+            prices: [3899.41, 3899.4, 3899.42],
+            sequence: Some(Sequence::Time(1630982370526)),
+            time: Some(1630982370526),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn ping_message() {
+        let message = r#"{"ping":1492420473027}"#;
+        let expected = Event::Reply(r#"{"pong":1492420473027}"#.to_string());
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn subscribe_messages() {
+        let message = r#"{"id":"market.dotusdt.ticker","status":"ok","subbed":"market.dotusdt.ticker","ts":1630982370526}"#;
+        let expected = Event::Subscribed(vec![Coin::DOT]);
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+
+        let message = r#"{"status":"error","ts":1630982370526,"id":"market.foousdt.ticker","err-code":"bad-request","err-msg":"invalid symbol foousdt"}"#;
+        let expected = Event::Rejected("bad-request: invalid symbol foousdt".to_string());
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"ch":"market.ethusdt.detail.merged","status":"ok","ts":1630982370526,"tick":{"id":311869842695,"version":311869842695,"open":3850.12,"close":3899.41,"low":3801.55,"high":3920.0,"amount":31254.2231,"vol":121049302.35,"count":245110,"bid":[3899.4,2.31],"ask":[3899.42,0.583653]}}"#;
+        assert_eq!(poll_handler(response), Some([3899.41, 3899.4, 3899.42]));
+
+        let response = r#"{"status":"error","err-code":"invalid-parameter","err-msg":"invalid symbol","ts":1630982370526}"#;
+        assert_eq!(poll_handler(response), None);
+    }
+}
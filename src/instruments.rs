@@ -0,0 +1,155 @@
+use crate::config::SupplyApi;
+use altusd::{Coin, Exchange};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// The default instrument registry, which is used unless the config overrides it.
+const DEFAULT_INSTRUMENTS: &str = include_str!("instruments.toml");
+
+/// This struct represents the central registry of our index's altcoins. It maps each altcoin to
+/// its identifier at each supply provider, and to its symbols on each exchange.
+///
+/// The exchange feeds generate both their subscriptions and the mapping of the symbols in their
+/// messages from it, so that no module has to hard-code the symbols.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Instruments(BTreeMap<Coin, Instrument>);
+
+/// This struct represents the entry of an altcoin in the registry.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Instrument {
+    /// The identifier of the altcoin at each supply provider.
+    pub supply: BTreeMap<SupplyApi, String>,
+    /// The symbol of the altcoin on each exchange, which is used to subscribe.
+    pub symbols: BTreeMap<Exchange, String>,
+    /// The other symbols that an exchange may use for the altcoin in its messages.
+    #[serde(default)]
+    pub aliases: BTreeMap<Exchange, Vec<String>>,
+}
+
+/// This struct represents the symbols of our index's altcoins on a particular exchange.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    symbols: BTreeMap<Coin, String>,
+    coins: HashMap<String, Coin>,
+}
+
+impl Default for Instruments {
+    fn default() -> Self {
+        // Safe expect: the default registry is checked by the tests below.
+        toml::from_str(DEFAULT_INSTRUMENTS).expect("invalid default instrument registry")
+    }
+}
+
+impl Instruments {
+    /// Check that every altcoin of the index is listed with a symbol on every exchange, since the
+    /// median price of an altcoin needs the market prices on all exchanges, and with an
+    /// identifier at the supply provider in use, since the index needs all the supplies too.
+    pub fn validate(&self, provider: SupplyApi) -> Result<(), String> {
+        for coin in Coin::ALL {
+            let instrument = match self.0.get(&coin) {
+                Some(instrument) => instrument,
+                None => return Err(format!("missing instrument: {:?}", coin)),
+            };
+            for exchange in Exchange::ALL {
+                if !instrument.symbols.contains_key(&exchange) {
+                    return Err(format!("missing symbol: {:?}: {:?}", coin, exchange));
+                }
+            }
+            if !instrument.supply.contains_key(&provider) {
+                return Err(format!("missing supply id: {:?}: {:?}", coin, provider));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the symbols of all the altcoins on a particular exchange, including their aliases.
+    pub fn symbols(&self, exchange: Exchange) -> Symbols {
+        let mut symbols = Symbols::default();
+        for (coin, instrument) in &self.0 {
+            if let Some(symbol) = instrument.symbols.get(&exchange) {
+                symbols.symbols.insert(*coin, symbol.clone());
+                symbols.coins.insert(symbol.clone(), *coin);
+            }
+            for alias in instrument.aliases.get(&exchange).into_iter().flatten() {
+                symbols.coins.insert(alias.clone(), *coin);
+            }
+        }
+        symbols
+    }
+
+    /// Get the identifier of each altcoin at a supply provider.
+    pub fn supply_ids(&self, provider: SupplyApi) -> Vec<(Coin, String)> {
+        let instruments = self.0.iter();
+        instruments
+            .filter_map(|(coin, instrument)| {
+                Some((*coin, instrument.supply.get(&provider)?.clone()))
+            })
+            .collect()
+    }
+}
+
+impl Symbols {
+    /// Iterate over the altcoins and their symbols, in the order of `Coin::ALL`.
+    pub fn iter(&self) -> impl Iterator<Item = (Coin, &str)> {
+        self.symbols
+            .iter()
+            .map(|(coin, symbol)| (*coin, symbol.as_str()))
+    }
+
+    /// Get the symbol of an altcoin, which is used to subscribe.
+    pub fn symbol(&self, coin: Coin) -> Option<&str> {
+        self.symbols.get(&coin).map(String::as_str)
+    }
+
+    /// Map a symbol or an alias from a message to an altcoin.
+    pub fn coin(&self, symbol: &str) -> Option<Coin> {
+        match self.coins.get(symbol) {
+            Some(coin) => Some(*coin),
+            None => {
+                tracing::error!("unexpected message coin: {}", symbol);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_instruments() {
+        let instruments = Instruments::default();
+        for provider in SupplyApi::ALL {
+            assert_eq!(instruments.validate(provider), Ok(()));
+        }
+        let supply_ids = instruments.supply_ids(SupplyApi::Coingecko);
+        assert_eq!(supply_ids[0], (Coin::ADA, "cardano".to_string()));
+
+        let symbols = instruments.symbols(Exchange::Kraken);
+        assert_eq!(symbols.symbol(Coin::DOGE), Some("DOGE/USD"));
+        assert_eq!(symbols.coin("XDG/USD"), Some(Coin::DOGE));
+        assert_eq!(symbols.coin("BTC/USD"), None);
+        let coins: Vec<Coin> = symbols.iter().map(|(coin, _)| coin).collect();
+        assert_eq!(coins, Coin::ALL);
+    }
+
+    #[test]
+    fn incomplete_instruments() {
+        let instruments = r#"
+            [ETH]
+            supply = { coinbase = "ETH" }
+            symbols = { binance = "ETHUSDT" }
+        "#;
+        let instruments: Instruments = toml::from_str(instruments).unwrap();
+        assert_eq!(
+            instruments.validate(SupplyApi::Coinbase),
+            Err("missing instrument: ADA".to_string())
+        );
+        let symbols = instruments.symbols(Exchange::Binance);
+        assert_eq!(symbols.coin("ETHUSDT"), Some(Coin::ETH));
+        assert!(instruments.supply_ids(SupplyApi::Coingecko).is_empty());
+    }
+}
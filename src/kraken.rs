@@ -1,83 +1,502 @@
+mod v2;
+
+use crate::admin::DisabledExchanges;
+use crate::book::{OrderBook, Side};
+use crate::config::{FeedConfig, KrakenApi};
 use crate::engine::Input;
-use crate::price::WebSocketPriceFeed;
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Session, Trade, WebSocketPriceFeed};
 use altusd::{Coin, Exchange};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The number of levels on each side of the order books.
+const BOOK_DEPTH: usize = 100;
+
+/// The number of levels on each side of an order book that are covered by its checksum.
+const CHECKSUM_DEPTH: usize = 10;
 
-/// This function is responsible to subscribe to the Kraken websocket price feed.
+/// The REST endpoint that serves the tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://api.kraken.com/0/public/Ticker?pair=";
+
+/// This function is responsible to subscribe to the Kraken websocket price feed, using the API
+/// version selected in the config. This module implements the legacy v1 API, and `v2.rs` the v2
+/// one.
 ///
 /// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
-pub async fn run(mpsc_tx: Sender<Input>) {
-    let websocket_price_feed = WebSocketPriceFeed {
-        endpoint: "wss://ws.kraken.com",
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(&config, symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the Kraken websocket price feed of the API version selected in the config,
+/// which is also used to replay its captures.
+pub fn feed(config: &FeedConfig, symbols: Symbols) -> WebSocketPriceFeed {
+    if config.kraken_api == KrakenApi::V2 {
+        return v2::feed(symbols);
+    }
+    let pairs: Vec<&str> = symbols.iter().map(|(_, pair)| pair).collect();
+    WebSocketPriceFeed {
         exchange: Exchange::Kraken,
-        subscribe: json!({
-            "event": "subscribe",
-            "pair": [
-                "ADA/USD",
-                "DOGE/USD",
-                "DOT/USD",
-                "ETH/USD",
-                "SOL/USD",
+        connections: vec![Connection::all_coins(
+            "wss://ws.kraken.com",
+            vec![
+                json!({
+                    "event": "subscribe",
+                    "pair": pairs,
+                    "subscription": {
+                        "name": "book",
+                        "depth": BOOK_DEPTH,
+                    },
+                }),
+                json!({
+                    "event": "subscribe",
+                    "pair": pairs,
+                    "subscription": {
+                        "name": "trade",
+                    },
+                }),
             ],
-            "subscription": {
-                "name": "ticker",
-            },
+        )],
+        symbols,
+        // Kraken sends a `heartbeat` event every second when no other message is sent.
+        idle_timeout: Duration::from_secs(10),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
         }),
+        mirrors: Vec::new(),
         message_handler,
-    };
-    websocket_price_feed.run(mpsc_tx).await;
+    }
+}
+
+/// These structs represent a message from the `book` channel, which contains either a snapshot
+/// or the changed levels of an order book. The changes of both sides are sent in two separate
+/// objects, and the checksum is only sent in the last one. See this link for reference:
+/// https://docs.kraken.com/websockets/#message-book
+///
+/// A message from the `trade` channel contains a list of trades, each made of a price, a
+/// volume, a timestamp, a side, an order type, and miscellaneous info. See this link:
+/// https://docs.kraken.com/websockets/#message-trade
+///
+/// The channel ID is ignored, and the messages carry no sequence number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message<'a> {
+    OneSide(IgnoredAny, #[serde(borrow)] BookData<'a>, &'a str, &'a str),
+    BothSides(
+        IgnoredAny,
+        #[serde(borrow)] BookData<'a>,
+        #[serde(borrow)] BookData<'a>,
+        &'a str,
+        &'a str,
+    ),
+    Trades(
+        IgnoredAny,
+        #[serde(borrow)] Vec<Vec<&'a str>>,
+        &'a str,
+        &'a str,
+    ),
 }
 
-/// These structs represent a message from the `ticker` channel. See this link for reference:
-/// https://docs.kraken.com/websockets/#message-ticker
+/// Each level is made of a price, a volume, a timestamp, and an optional update type.
 #[derive(Deserialize)]
-struct Message<'a>(u64, MessageDetail<'a>, &'a str, &'a str);
+struct BookData<'a> {
+    #[serde(borrow, default, rename = "as")]
+    snapshot_asks: Vec<Vec<&'a str>>,
+    #[serde(borrow, default, rename = "bs")]
+    snapshot_bids: Vec<Vec<&'a str>>,
+    #[serde(borrow, default)]
+    a: Vec<Vec<&'a str>>,
+    #[serde(borrow, default)]
+    b: Vec<Vec<&'a str>>,
+    c: Option<&'a str>,
+}
 
+/// This struct represents a general message, e.g. `heartbeat` or `subscriptionStatus`.
+/// Only the latter has a status, a pair, and an error message. See this link for reference:
+/// https://docs.kraken.com/websockets/#message-subscriptionStatus
 #[derive(Deserialize)]
-struct MessageDetail<'a> {
-    #[serde(borrow)]
-    a: (&'a str, u64, &'a str),
-    b: (&'a str, u64, &'a str),
-    c: (&'a str, &'a str),
+#[serde(rename_all = "camelCase")]
+struct GeneralMessage<'a> {
+    event: &'a str,
+    status: Option<&'a str>,
+    pair: Option<&'a str>,
+    error_message: Option<String>,
 }
 
-/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+/// This message handler tries to maintain the order book of an altcoin, or to parse its trades.
 ///
 /// Effectively, this is a callback used for every websocket received from the exchange.
-fn message_handler(message: String) -> Option<(Coin, [f64; 3])> {
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
     // Deserialize.
-    let message = match serde_json::from_str::<Message>(&message) {
-        Ok(message) => message,
+    let (data, name, pair) = match serde_json::from_str::<Message>(&message) {
+        Ok(Message::OneSide(_, data, name, pair)) => (vec![data], name, pair),
+        Ok(Message::BothSides(_, asks, bids, name, pair)) => (vec![asks, bids], name, pair),
+        Ok(Message::Trades(_, trades, "trade", pair)) => {
+            return match session.symbols.coin(pair) {
+                Some(coin) => trade_handler(coin, &trades),
+                None => Vec::new(),
+            };
+        }
+        Ok(Message::Trades(_, _, name, _)) => {
+            tracing::error!("unexpected message type: {}", name);
+            return Vec::new();
+        }
         Err(_) => {
-            tracing::warn!("discarded message: {}", message);
-            return None;
+            return general_message_handler(session, &message)
+                .into_iter()
+                .collect()
         }
     };
 
     // Validate message type.
-    if message.2 != "ticker" {
-        tracing::error!("unexpected message type: {}", message.2);
-        return None;
+    if !name.starts_with("book") {
+        tracing::error!("unexpected message type: {}", name);
+        return Vec::new();
     }
 
     // Extract coin.
-    let coin = match message.3 {
-        "ADA/USD" => Coin::ADA,
-        "XDG/USD" => Coin::DOGE,
-        "DOT/USD" => Coin::DOT,
-        "ETH/USD" => Coin::ETH,
-        "SOL/USD" => Coin::SOL,
-        _ => {
-            tracing::error!("unexpected message coin: {}", message.3);
+    match session.symbols.coin(pair) {
+        Some(coin) => book_handler(session, coin, pair, &data),
+        None => Vec::new(),
+    }
+}
+
+/// This message handler tries to apply a snapshot or the changed levels to the order book of an
+/// altcoin, and to verify its checksum. Kraken doesn't send the removal of the levels that fall
+/// out of the subscribed depth, so the book is truncated after every update.
+///
+/// A checksum mismatch means that the book is out of sync, so we subscribe again to the book of
+/// this pair only, which sends a new snapshot. The other books and the trades are unaffected.
+fn book_handler(session: &mut Session, coin: Coin, pair: &str, data: &[BookData]) -> Vec<Event> {
+    // A snapshot replaces the book.
+    let snapshot = data
+        .iter()
+        .any(|data| !data.snapshot_asks.is_empty() || !data.snapshot_bids.is_empty());
+    if snapshot {
+        session.books.insert(coin, OrderBook::default());
+    }
+    let book = match session.books.get_mut(&coin) {
+        Some(book) => book,
+        None => {
+            tracing::error!("unexpected update before snapshot: {:?}", coin);
+            return Vec::new();
+        }
+    };
+
+    // Apply the levels.
+    for data in data {
+        apply_levels(book, Side::Ask, &data.snapshot_asks);
+        apply_levels(book, Side::Bid, &data.snapshot_bids);
+        apply_levels(book, Side::Ask, &data.a);
+        apply_levels(book, Side::Bid, &data.b);
+    }
+    book.truncate(BOOK_DEPTH);
+
+    // Verify the checksum.
+    if let Some(expected) = data.iter().find_map(|data| data.c) {
+        let checksum = checksum(book).to_string();
+        if checksum != expected {
+            tracing::warn!(
+                "checksum mismatch: {:?}: {} != {}",
+                coin,
+                checksum,
+                expected
+            );
+            session.books.remove(&coin);
+            return resubscribe_book(pair);
+        }
+    }
+    vec![Event::Book(coin, None)]
+}
+
+/// This function is a helper to build the requests that unsubscribe from the book of a pair, and
+/// then subscribe to it again. Kraken handles them in order, and then sends a new snapshot.
+fn resubscribe_book(pair: &str) -> Vec<Event> {
+    ["unsubscribe", "subscribe"]
+        .into_iter()
+        .map(|event| {
+            let request = json!({
+                "event": event,
+                "pair": [pair],
+                "subscription": {
+                    "name": "book",
+                    "depth": BOOK_DEPTH,
+                },
+            });
+            Event::Reply(request.to_string())
+        })
+        .collect()
+}
+
+/// This message handler tries to parse the price and volume of the trades of an altcoin.
+fn trade_handler(coin: Coin, trades: &[Vec<&str>]) -> Vec<Event> {
+    let mut events = Vec::new();
+    for trade in trades {
+        let (price, volume, time) = match trade.as_slice() {
+            [price, volume, time, ..] => (price, volume, time),
+            _ => {
+                tracing::error!("unexpected trade: {:?}", trade);
+                continue;
+            }
+        };
+        if let (Some(price), Some(volume)) = (
+            crate::price::str_to_f64(price),
+            crate::price::str_to_f64(volume),
+        ) {
+            events.push(Event::Trade(Trade {
+                coin,
+                price,
+                quantity: Some(volume),
+                sequence: None,
+                time: crate::price::str_to_f64(time).map(|time| (time * 1000.0) as u64),
+            }));
+        }
+    }
+    events
+}
+
+/// This message handler tries to parse a general message that isn't from a channel.
+fn general_message_handler(session: &Session, message: &str) -> Option<Event> {
+    let general_message = match serde_json::from_str::<GeneralMessage>(message) {
+        Ok(general_message) => general_message,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
             return None;
         }
     };
 
-    // Extract last price, best bid, and best ask.
-    let last_price = crate::price::str_to_f64(message.1.c.0)?;
-    let best_bid = crate::price::str_to_f64(message.1.b.0)?;
-    let best_ask = crate::price::str_to_f64(message.1.a.0)?;
-    Some((coin, [last_price, best_bid, best_ask]))
+    match (general_message.event, general_message.status) {
+        ("heartbeat", _) => Some(Event::Heartbeat),
+        ("systemStatus", status) => {
+            tracing::info!("kraken system status: {:?}", status);
+            None
+        }
+        ("subscriptionStatus", Some("subscribed")) => {
+            let coin = session.symbols.coin(general_message.pair?)?;
+            Some(Event::Subscribed(vec![coin]))
+        }
+        ("subscriptionStatus", Some("unsubscribed")) => {
+            tracing::info!("unsubscribed: {:?}", general_message.pair);
+            None
+        }
+        ("subscriptionStatus", Some("error")) => Some(Event::Rejected(format!(
+            "{}: {}",
+            general_message.pair.unwrap_or_default(),
+            general_message.error_message.unwrap_or_default()
+        ))),
+        ("error", _) => Some(Event::Error(
+            general_message.error_message.unwrap_or_default(),
+        )),
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This function is a helper to apply the levels of one side to an order book.
+fn apply_levels(book: &mut OrderBook, side: Side, levels: &[Vec<&str>]) {
+    for level in levels {
+        match level.as_slice() {
+            [price, volume, ..] => book.update(side, price, volume),
+            _ => {
+                tracing::error!("unexpected book level: {:?}", level);
+                None
+            }
+        };
+    }
+}
+
+/// This function is a helper to compute the CRC32 checksum of the top levels of an order book,
+/// which are formatted without decimal point and leading zeros. See this link for reference:
+/// https://docs.kraken.com/websockets/#book-checksum
+fn checksum(book: &OrderBook) -> u32 {
+    let asks = book.asks().take(CHECKSUM_DEPTH);
+    let bids = book.bids().take(CHECKSUM_DEPTH);
+    let mut hasher = crc32fast::Hasher::new();
+    for level in asks.chain(bids) {
+        for number in [&level.price, &level.quantity] {
+            let digits = number.replace('.', "");
+            hasher.update(digits.trim_start_matches('0').as_bytes());
+        }
+    }
+    hasher.finalize()
+}
+
+/// These structs represent the response of the REST ticker, whose result is keyed by the name of
+/// the pair. The best ask, best bid, and last trade are arrays that start with their price.
+/// See this link for reference: https://docs.kraken.com/rest/#operation/getTickerInformation
+#[derive(Deserialize)]
+struct TickerResponse<'a> {
+    #[serde(borrow, default)]
+    result: HashMap<&'a str, TickerResult<'a>>,
+}
+
+#[derive(Deserialize)]
+struct TickerResult<'a> {
+    #[serde(borrow)]
+    a: Vec<&'a str>,
+    #[serde(borrow)]
+    b: Vec<&'a str>,
+    #[serde(borrow)]
+    c: Vec<&'a str>,
+}
+
+/// This function is a helper to build the path of the REST ticker of a pair, which is written
+/// without slash.
+fn poll_path(pair: &str) -> String {
+    pair.replace('/', "")
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker.
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let response = serde_json::from_str::<TickerResponse>(response).ok()?;
+    let ticker = response.result.values().next()?;
+    Some([
+        crate::price::str_to_f64(ticker.c.first()?)?,
+        crate::price::str_to_f64(ticker.b.first()?)?,
+        crate::price::str_to_f64(ticker.a.first()?)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Kraken)
+    }
+
+    const SNAPSHOT: &str = r#"[560,{"as":[["3000.30000","1.50000000","1673853746.123456"],["3000.40000","0.25000000","1673853746.123456"],["3000.50000","4.00000000","1673853746.123456"]],"bs":[["3000.10000","2.00000000","1673853746.123456"],["3000.00000","0.00100000","1673853746.123456"],["2999.90000","10.00000000","1673853746.123456"]]},"book-100","ETH/USD"]"#;
+
+    #[test]
+    fn book_messages() {
+        let mut session = session();
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(
+            message_handler(&mut session, SNAPSHOT.to_string()),
+            expected
+        );
+        assert_eq!(checksum(&session.books[&Coin::ETH]), 2942728);
+
+        let message = r#"[560,{"a":[["3000.30000","0.00000000","1673853747.234567"],["3000.35000","0.50000000","1673853747.234567"]],"c":"3449557130"},"book-100","ETH/USD"]"#;
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.asks().next().unwrap().price, "3000.35000");
+    }
+
+    #[test]
+    fn both_sides_message() {
+        let mut session = session();
+        message_handler(&mut session, SNAPSHOT.to_string());
+
+        let message = r#"[560,{"a":[["3000.30000","0.00000000","1673853747.234567"]]},{"b":[["3000.20000","1.00000000","1673853747.234567"]],"c":"1376330732"},"book-100","ETH/USD"]"#;
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.bids().next().unwrap().price, "3000.20000");
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut session = session();
+        message_handler(&mut session, SNAPSHOT.to_string());
+
+        // Only the book of this pair is subscribed again.
+        let message = r#"[560,{"b":[["3000.20000","1.00000000","1673853747.234567"]],"c":"123"},"book-100","ETH/USD"]"#;
+        let expected = vec![
+            Event::Reply(
+                r#"{"event":"unsubscribe","pair":["ETH/USD"],"subscription":{"depth":100,"name":"book"}}"#
+                    .to_string(),
+            ),
+            Event::Reply(
+                r#"{"event":"subscribe","pair":["ETH/USD"],"subscription":{"depth":100,"name":"book"}}"#
+                    .to_string(),
+            ),
+        ];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        assert!(session.books.is_empty());
+
+        // The updates are discarded until the new snapshot is received.
+        let message =
+            r#"[560,{"a":[["3000.40000","0.50000000","1673853747.345678"]]},"book-100","ETH/USD"]"#;
+        assert_eq!(message_handler(&mut session, message.to_string()), vec![]);
+        let message = r#"{"channelID":560,"channelName":"book-100","event":"subscriptionStatus","pair":"ETH/USD","status":"unsubscribed","subscription":{"depth":100,"name":"book"}}"#;
+        assert_eq!(message_handler(&mut session, message.to_string()), vec![]);
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(
+            message_handler(&mut session, SNAPSHOT.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn trade_message() {
+        let message = r#"[337,[["3000.10000","0.50000000","1673853746.123456","b","l",""],["3000.20000","1.25000000","1673853746.234567","s","m",""]],"trade","ETH/USD"]"#;
+        let expected = vec![
+            Event::Trade(Trade {
+                coin: Coin::ETH,
+                price: 3000.1,
+                quantity: Some(0.5),
+                sequence: None,
+                time: Some(1673853746123),
+            }),
+            Event::Trade(Trade {
+                coin: Coin::ETH,
+                price: 3000.2,
+                quantity: Some(1.25),
+                sequence: None,
+                time: Some(1673853746234),
+            }),
+        ];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn subscription_status_message() {
+        let message = r#"{"channelID":560,"channelName":"book-100","event":"subscriptionStatus","pair":"XDG/USD","status":"subscribed","subscription":{"depth":100,"name":"book"}}"#;
+        let expected = vec![Event::Subscribed(vec![Coin::DOGE])];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+
+        let message = r#"{"errorMessage":"Currency pair not supported FOO/USD","event":"subscriptionStatus","pair":"FOO/USD","status":"error","subscription":{"depth":100,"name":"book"}}"#;
+        let expected = vec![Event::Rejected(
+            "FOO/USD: Currency pair not supported FOO/USD".to_string(),
+        )];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"error":[],"result":{"XETHZUSD":{"a":["3000.30000","1","1.000"],"b":["3000.10000","2","2.000"],"c":["3000.20000","0.50000000"],"v":["1520.1","30211.5"],"p":["2990.1","2985.2"],"t":[1520,30211],"l":["2950.0","2950.0"],"h":["3050.0","3050.0"],"o":"2960.0"}}}"#;
+        assert_eq!(poll_handler(response), Some([3000.2, 3000.1, 3000.3]));
+
+        let response = r#"{"error":["EQuery:Unknown asset pair"]}"#;
+        assert_eq!(poll_handler(response), None);
+    }
 }
@@ -0,0 +1,499 @@
+use super::{checksum, poll_handler, poll_path, BOOK_DEPTH, POLL_ENDPOINT};
+use crate::book::{OrderBook, Side};
+use crate::instruments::Symbols;
+use crate::price::{Connection, Event, Poll, Sequence, Session, Trade, WebSocketPriceFeed};
+use altusd::Exchange;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+/// This function builds the Kraken websocket price feed of the v2 API. Unlike v1, its messages
+/// have named fields, and its symbols are the same in subscriptions and messages, e.g. `DOGE/USD`
+/// instead of `XDG/USD`.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    let pairs: Vec<&str> = symbols.iter().map(|(_, pair)| pair).collect();
+    WebSocketPriceFeed {
+        exchange: Exchange::Kraken,
+        // The levels of the books are sent as numbers, so the precisions of the pairs are needed
+        // to format them for the checksum. They are sent in the `instrument` snapshot, which is
+        // subscribed first.
+        connections: vec![Connection::all_coins(
+            "wss://ws.kraken.com/v2",
+            vec![
+                json!({
+                    "method": "subscribe",
+                    "params": {
+                        "channel": "instrument",
+                    },
+                }),
+                json!({
+                    "method": "subscribe",
+                    "params": {
+                        "channel": "book",
+                        "symbol": pairs,
+                        "depth": BOOK_DEPTH,
+                    },
+                }),
+                json!({
+                    "method": "subscribe",
+                    "params": {
+                        "channel": "trade",
+                        "symbol": pairs,
+                    },
+                }),
+            ],
+        )],
+        symbols,
+        // Kraken sends a `heartbeat` message every second when no other message is sent.
+        idle_timeout: Duration::from_secs(10),
+        keepalive: None,
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        mirrors: Vec::new(),
+        message_handler,
+    }
+}
+
+/// This struct represents the envelope of any message, which is used to dispatch it.
+/// Channel messages have a `channel`, whereas responses to our requests have a `method`.
+/// See this link for reference: https://docs.kraken.com/api/docs/websocket-v2/
+#[derive(Deserialize)]
+struct Header<'a> {
+    channel: Option<&'a str>,
+    method: Option<&'a str>,
+}
+
+/// These structs represent a message from the `book` channel, which contains either a snapshot
+/// or the changed levels of the order books. See this link for reference:
+/// https://docs.kraken.com/api/docs/websocket-v2/book
+#[derive(Deserialize)]
+struct BookMessage<'a> {
+    r#type: &'a str,
+    #[serde(borrow)]
+    data: Vec<BookData<'a>>,
+}
+
+#[derive(Deserialize)]
+struct BookData<'a> {
+    symbol: &'a str,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    checksum: u32,
+    timestamp: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct BookLevel {
+    price: f64,
+    qty: f64,
+}
+
+/// These structs represent a message from the `trade` channel. The trade IDs of a pair increase
+/// by one for every trade. See this link for reference:
+/// https://docs.kraken.com/api/docs/websocket-v2/trade
+#[derive(Deserialize)]
+struct TradeMessage<'a> {
+    #[serde(borrow)]
+    data: Vec<TradeData<'a>>,
+}
+
+#[derive(Deserialize)]
+struct TradeData<'a> {
+    symbol: &'a str,
+    price: f64,
+    qty: f64,
+    trade_id: u64,
+    timestamp: &'a str,
+}
+
+/// These structs represent the snapshot of the `instrument` channel, which lists all the pairs
+/// of the exchange with their precisions. See this link for reference:
+/// https://docs.kraken.com/api/docs/websocket-v2/instrument
+#[derive(Deserialize)]
+struct InstrumentMessage<'a> {
+    #[serde(borrow)]
+    data: InstrumentData<'a>,
+}
+
+#[derive(Deserialize)]
+struct InstrumentData<'a> {
+    #[serde(borrow)]
+    pairs: Vec<PairData<'a>>,
+}
+
+#[derive(Deserialize)]
+struct PairData<'a> {
+    symbol: &'a str,
+    price_precision: usize,
+    qty_precision: usize,
+}
+
+/// This struct represents the response to a request, e.g. `subscribe`. A successful response
+/// has the subscribed channel and symbol in its result, whereas an error has the symbol at the
+/// top level. See this link for reference:
+/// https://docs.kraken.com/api/docs/websocket-v2/book
+#[derive(Deserialize)]
+struct Response<'a> {
+    success: bool,
+    #[serde(borrow)]
+    result: Option<ResponseResult<'a>>,
+    symbol: Option<&'a str>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseResult<'a> {
+    symbol: Option<&'a str>,
+}
+
+/// This message handler dispatches a message on its channel or method.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    // Deserialize the header.
+    let header = match serde_json::from_str::<Header>(&message) {
+        Ok(header) => header,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
+        }
+    };
+
+    // Dispatch on the channel or method.
+    match (header.channel, header.method) {
+        (Some("book"), _) => book_handler(session, &message),
+        (Some("trade"), _) => trade_handler(session, &message),
+        (Some("instrument"), _) => {
+            instrument_handler(session, &message);
+            Vec::new()
+        }
+        (Some("heartbeat"), _) => vec![Event::Heartbeat],
+        (Some("status"), _) => {
+            tracing::info!("kraken status: {}", message);
+            Vec::new()
+        }
+        (None, Some("subscribe")) => response_handler(session, &message).into_iter().collect(),
+        (None, Some("unsubscribe")) => {
+            tracing::info!("kraken unsubscribed: {}", message);
+            Vec::new()
+        }
+        (None, Some("pong")) => vec![Event::Heartbeat],
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            Vec::new()
+        }
+    }
+}
+
+/// This message handler tries to apply a snapshot or the changed levels to the order books of
+/// the altcoins, and to verify their checksums. The levels are formatted with the precisions of
+/// their pair, so that they can be checksummed like the v1 ones. Kraken doesn't send the removal
+/// of the levels that fall out of the subscribed depth, so the books are truncated after every
+/// update.
+///
+/// A checksum mismatch means that a book is out of sync, so we subscribe again to the book of
+/// this pair only, which sends a new snapshot. The other books and the trades are unaffected.
+/// However, a book received before the precisions of its pair means that the `instrument`
+/// snapshot was missed, in which case we reconnect in order to subscribe to it again first.
+fn book_handler(session: &mut Session, message: &str) -> Vec<Event> {
+    // Deserialize.
+    let parsed = match serde_json::from_str::<BookMessage>(message) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
+        }
+    };
+
+    let mut events = Vec::new();
+    for data in &parsed.data {
+        // Extract coin and precisions.
+        let coin = match session.symbols.coin(data.symbol) {
+            Some(coin) => coin,
+            None => continue,
+        };
+        let (price_precision, qty_precision) = match session.precisions.get(&coin) {
+            Some(precisions) => *precisions,
+            None => {
+                events.push(Event::Reconnect(format!("unknown precisions: {:?}", coin)));
+                continue;
+            }
+        };
+
+        // A snapshot replaces the book.
+        if parsed.r#type == "snapshot" {
+            session.books.insert(coin, OrderBook::default());
+        }
+        let book = match session.books.get_mut(&coin) {
+            Some(book) => book,
+            None => {
+                tracing::error!("unexpected update before snapshot: {:?}", coin);
+                continue;
+            }
+        };
+
+        // Apply the levels.
+        for (side, levels) in [(Side::Ask, &data.asks), (Side::Bid, &data.bids)] {
+            for level in levels {
+                let price = format!("{:.*}", price_precision, level.price);
+                let qty = format!("{:.*}", qty_precision, level.qty);
+                book.update(side, &price, &qty);
+            }
+        }
+        book.truncate(BOOK_DEPTH);
+
+        // Verify the checksum.
+        let checksum = checksum(book);
+        if checksum != data.checksum {
+            tracing::warn!(
+                "checksum mismatch: {:?}: {} != {}",
+                coin,
+                checksum,
+                data.checksum
+            );
+            session.books.remove(&coin);
+            events.extend(resubscribe_book(data.symbol));
+            continue;
+        }
+        let time = data.timestamp.and_then(crate::price::rfc3339_to_millis);
+        events.push(Event::Book(coin, time));
+    }
+    events
+}
+
+/// This function is a helper to build the requests that unsubscribe from the book of a symbol,
+/// and then subscribe to it again. Kraken handles them in order, and then sends a new snapshot.
+fn resubscribe_book(symbol: &str) -> Vec<Event> {
+    ["unsubscribe", "subscribe"]
+        .into_iter()
+        .map(|method| {
+            let request = json!({
+                "method": method,
+                "params": {
+                    "channel": "book",
+                    "symbol": [symbol],
+                    "depth": BOOK_DEPTH,
+                },
+            });
+            Event::Reply(request.to_string())
+        })
+        .collect()
+}
+
+/// This message handler tries to parse the price and quantity of the trades of the altcoins.
+fn trade_handler(session: &Session, message: &str) -> Vec<Event> {
+    // Deserialize.
+    let parsed = match serde_json::from_str::<TradeMessage>(message) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return Vec::new();
+        }
+    };
+
+    // Extract coin, price, quantity, and sequence.
+    let trades = parsed.data.iter();
+    trades
+        .filter_map(|trade| {
+            Some(Event::Trade(Trade {
+                coin: session.symbols.coin(trade.symbol)?,
+                price: trade.price,
+                quantity: Some(trade.qty),
+                sequence: Some(Sequence::Number(trade.trade_id)),
+                time: crate::price::rfc3339_to_millis(trade.timestamp),
+            }))
+        })
+        .collect()
+}
+
+/// This message handler tries to record the precisions of the pairs of our index's altcoins.
+/// The other pairs of the exchange are ignored.
+fn instrument_handler(session: &mut Session, message: &str) {
+    let parsed = match serde_json::from_str::<InstrumentMessage>(message) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return;
+        }
+    };
+
+    for pair in parsed.data.pairs {
+        let mut symbols = session.symbols.iter();
+        if let Some((coin, _)) = symbols.find(|(_, symbol)| *symbol == pair.symbol) {
+            let precisions = (pair.price_precision, pair.qty_precision);
+            session.precisions.insert(coin, precisions);
+        }
+    }
+}
+
+/// This message handler tries to parse the response to a subscribe request. Kraken confirms
+/// each symbol of each channel separately, and the `instrument` channel has no symbol.
+fn response_handler(session: &Session, message: &str) -> Option<Event> {
+    let response = match serde_json::from_str::<Response>(message) {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    if !response.success {
+        return Some(Event::Rejected(format!(
+            "{}: {}",
+            response.symbol.unwrap_or_default(),
+            response.error.unwrap_or_default()
+        )));
+    }
+    let symbol = response.result?.symbol?;
+    let coin = session.symbols.coin(symbol)?;
+    Some(Event::Subscribed(vec![coin]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use altusd::Coin;
+
+    /// These fixtures are based on messages captured from the v2 API, trimmed to a few levels and
+    /// pairs.
+    const INSTRUMENT: &str = r#"{"channel":"instrument","type":"snapshot","data":{"assets":[{"id":"ETH","status":"enabled","precision":10,"precision_display":5,"borrowable":true,"collateral_value":1.0,"margin_rate":0.02}],"pairs":[{"symbol":"ETH/USD","base":"ETH","quote":"USD","status":"online","qty_precision":8,"qty_increment":0.00000001,"price_precision":2,"cost_precision":5,"marginable":true,"has_index":true,"cost_min":0.5,"margin_initial":0.2,"position_limit_long":1000,"position_limit_short":600,"tick_size":0.01,"price_increment":0.01,"qty_min":0.002},{"symbol":"BTC/USD","base":"BTC","quote":"USD","status":"online","qty_precision":8,"qty_increment":0.00000001,"price_precision":1,"cost_precision":5,"marginable":true,"has_index":true,"cost_min":0.5,"margin_initial":0.2,"position_limit_long":250,"position_limit_short":200,"tick_size":0.1,"price_increment":0.1,"qty_min":0.0001}]}}"#;
+    const SNAPSHOT: &str = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"ETH/USD","bids":[{"price":3000.1,"qty":2.0},{"price":3000.0,"qty":0.001},{"price":2999.9,"qty":10.0}],"asks":[{"price":3000.3,"qty":1.5},{"price":3000.4,"qty":0.25},{"price":3000.5,"qty":4.0}],"checksum":3503320757}]}"#;
+
+    /// This function is a helper to build a session with the default symbols and the precisions
+    /// of the captured instrument snapshot.
+    fn session() -> Session {
+        let mut session = Session::with_default_symbols(Exchange::Kraken);
+        assert_eq!(
+            message_handler(&mut session, INSTRUMENT.to_string()),
+            vec![]
+        );
+        session
+    }
+
+    #[test]
+    fn instrument_message() {
+        let session = session();
+        assert_eq!(session.precisions.get(&Coin::ETH), Some(&(2, 8)));
+        assert_eq!(session.precisions.len(), 1);
+    }
+
+    #[test]
+    fn book_messages() {
+        let mut session = session();
+        let snapshot = SNAPSHOT.to_string();
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(message_handler(&mut session, snapshot), expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.bids().next().unwrap().price, "3000.10");
+
+        let message = r#"{"channel":"book","type":"update","data":[{"symbol":"ETH/USD","bids":[],"asks":[{"price":3000.3,"qty":0.0},{"price":3000.35,"qty":0.5}],"checksum":885318617,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let expected = vec![Event::Book(Coin::ETH, Some(1696613755440))];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        let book = &session.books[&Coin::ETH];
+        assert_eq!(book.asks().next().unwrap().price, "3000.35");
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut session = session();
+        message_handler(&mut session, SNAPSHOT.to_string());
+
+        // Only the book of this pair is subscribed again.
+        let message = r#"{"channel":"book","type":"update","data":[{"symbol":"ETH/USD","bids":[{"price":3000.2,"qty":1.0}],"asks":[],"checksum":123,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let expected = vec![
+            Event::Reply(
+                r#"{"method":"unsubscribe","params":{"channel":"book","depth":100,"symbol":["ETH/USD"]}}"#
+                    .to_string(),
+            ),
+            Event::Reply(
+                r#"{"method":"subscribe","params":{"channel":"book","depth":100,"symbol":["ETH/USD"]}}"#
+                    .to_string(),
+            ),
+        ];
+        assert_eq!(message_handler(&mut session, message.to_string()), expected);
+        assert!(session.books.is_empty());
+
+        // The book is synchronized again by the new snapshot.
+        let message = r#"{"method":"unsubscribe","result":{"channel":"book","depth":100,"symbol":"ETH/USD"},"success":true,"time_in":"2023-10-06T17:35:55.441209Z","time_out":"2023-10-06T17:35:55.441252Z"}"#;
+        assert_eq!(message_handler(&mut session, message.to_string()), vec![]);
+        let expected = vec![Event::Book(Coin::ETH, None)];
+        assert_eq!(
+            message_handler(&mut session, SNAPSHOT.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn book_before_instrument() {
+        let mut session = Session::with_default_symbols(Exchange::Kraken);
+        let expected = vec![Event::Reconnect("unknown precisions: ETH".to_string())];
+        assert_eq!(
+            message_handler(&mut session, SNAPSHOT.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn trade_message() {
+        let message = r#"{"channel":"trade","type":"update","data":[{"symbol":"DOGE/USD","side":"buy","price":0.0817,"qty":1500.0,"ord_type":"limit","trade_id":4665906,"timestamp":"2023-10-06T17:35:55.440295Z"},{"symbol":"DOGE/USD","side":"sell","price":0.08169,"qty":250.5,"ord_type":"market","trade_id":4665907,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let expected = vec![
+            Event::Trade(Trade {
+                coin: Coin::DOGE,
+                price: 0.0817,
+                quantity: Some(1500.0),
+                sequence: Some(Sequence::Number(4665906)),
+                time: Some(1696613755440),
+            }),
+            Event::Trade(Trade {
+                coin: Coin::DOGE,
+                price: 0.08169,
+                quantity: Some(250.5),
+                sequence: Some(Sequence::Number(4665907)),
+                time: Some(1696613755440),
+            }),
+        ];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn subscribe_messages() {
+        let message = r#"{"method":"subscribe","result":{"channel":"book","depth":100,"snapshot":true,"symbol":"DOGE/USD"},"success":true,"time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440329Z"}"#;
+        let expected = vec![Event::Subscribed(vec![Coin::DOGE])];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+
+        let message = r#"{"method":"subscribe","result":{"channel":"instrument","snapshot":true},"success":true,"time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440329Z"}"#;
+        assert_eq!(message_handler(&mut session(), message.to_string()), vec![]);
+
+        let message = r#"{"error":"Currency pair not supported FOO/USD","method":"subscribe","success":false,"symbol":"FOO/USD","time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440329Z"}"#;
+        let expected = vec![Event::Rejected(
+            "FOO/USD: Currency pair not supported FOO/USD".to_string(),
+        )];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+
+    #[test]
+    fn heartbeat_message() {
+        let message = r#"{"channel":"heartbeat"}"#;
+        let expected = vec![Event::Heartbeat];
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            expected
+        );
+    }
+}
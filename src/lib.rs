@@ -1,5 +1,11 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
 /// This enum contains the 5 altcoins needed to compute the index.
-#[derive(Clone, Copy, Debug)]
+///
+/// It is (de)serialized from its name, since the TOML map keys can't be deserialized as enums.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum Coin {
     ADA,
     DOGE,
@@ -8,13 +14,87 @@ pub enum Coin {
     SOL,
 }
 
-/// This enum contains the 3 exchanges needed to compute the index.
+impl Coin {
+    /// All the altcoins in the index.
+    pub const ALL: [Coin; 5] = [Coin::ADA, Coin::DOGE, Coin::DOT, Coin::ETH, Coin::SOL];
+}
+
+impl TryFrom<String> for Coin {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let mut coins = Coin::ALL.into_iter();
+        match coins.find(|coin| format!("{:?}", coin) == name) {
+            Some(coin) => Ok(coin),
+            None => Err(format!("unknown coin: {}", name)),
+        }
+    }
+}
+
+impl From<Coin> for String {
+    fn from(coin: Coin) -> Self {
+        format!("{:?}", coin)
+    }
+}
+
+/// This enum contains the 9 exchanges needed to compute the index.
 /// The median of the market prices on these exchanges is taken.
-#[derive(Clone, Copy, Debug)]
+///
+/// It is deserialized from its lowercase name, like `Coin`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
 pub enum Exchange {
     Binance,
+    Bitfinex,
+    Bitstamp,
+    Bybit,
     Coinbase,
+    Gemini,
+    Htx,
     Kraken,
+    Okx,
+}
+
+impl Exchange {
+    /// All the exchanges of the index.
+    pub const ALL: [Exchange; 9] = [
+        Exchange::Binance,
+        Exchange::Bitfinex,
+        Exchange::Bitstamp,
+        Exchange::Bybit,
+        Exchange::Coinbase,
+        Exchange::Gemini,
+        Exchange::Htx,
+        Exchange::Kraken,
+        Exchange::Okx,
+    ];
+
+    /// Get the lowercase name of this exchange, e.g. to label metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Exchange::Binance => "binance",
+            Exchange::Bitfinex => "bitfinex",
+            Exchange::Bitstamp => "bitstamp",
+            Exchange::Bybit => "bybit",
+            Exchange::Coinbase => "coinbase",
+            Exchange::Gemini => "gemini",
+            Exchange::Htx => "htx",
+            Exchange::Kraken => "kraken",
+            Exchange::Okx => "okx",
+        }
+    }
+}
+
+impl TryFrom<String> for Exchange {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let mut exchanges = Exchange::ALL.into_iter();
+        match exchanges.find(|exchange| exchange.name() == name) {
+            Some(exchange) => Ok(exchange),
+            None => Err(format!("unknown exchange: {}", name)),
+        }
+    }
 }
 
 /// This struct represents the "core engine" of the altcoin index and encapsulates all the
@@ -25,17 +105,20 @@ pub struct Engine {
     dot: Cache,
     eth: Cache,
     sol: Cache,
+    /// Whether each exchange is part of the median, indexed by its position in `Exchange::ALL`.
+    enabled: [bool; Exchange::ALL.len()],
 }
 
 /// This struct is an internal data structure of the `Engine`, and thus a private implementation
 /// detail. It caches the values needed by the index for a particular altcoin.
+/// The market price and VWAP on each exchange are indexed by the position of the exchange in
+/// `Exchange::ALL`.
 struct Cache {
     circulating_supply: f64,
     market_cap: f64,
     median_price: f64,
-    price_binance: f64,
-    price_coinbase: f64,
-    price_kraken: f64,
+    prices: [f64; Exchange::ALL.len()],
+    vwaps: [f64; Exchange::ALL.len()],
 }
 
 impl Cache {
@@ -45,9 +128,8 @@ impl Cache {
             circulating_supply: f64::NAN,
             market_cap: f64::NAN,
             median_price: f64::NAN,
-            price_binance: f64::NAN,
-            price_coinbase: f64::NAN,
-            price_kraken: f64::NAN,
+            prices: [f64::NAN; Exchange::ALL.len()],
+            vwaps: [f64::NAN; Exchange::ALL.len()],
         }
     }
 
@@ -58,15 +140,27 @@ impl Cache {
 
     /// Update the current median price of this altcoin.
     ///
-    /// The market price on all exchanges must be set.
-    /// If an exchange price hasn't been set, the current median price stays NaN.
-    fn update_median_price(&mut self) {
-        let mut prices = [self.price_binance, self.price_coinbase, self.price_kraken];
-        if prices.iter().all(|price| price.is_finite()) {
+    /// The VWAP on an exchange replaces its market price once it's set. Either of them must be
+    /// set on all enabled exchanges. If not, the current median price stays NaN, or unchanged.
+    /// With an even number of exchanges, the median is the mean of the 2 middle prices.
+    fn update_median_price(&mut self, enabled: &[bool]) {
+        let mut prices: Vec<f64> = self
+            .prices
+            .iter()
+            .zip(self.vwaps)
+            .zip(enabled)
+            .filter(|(_, enabled)| **enabled)
+            .map(|((price, vwap), _)| if vwap.is_finite() { vwap } else { *price })
+            .collect();
+        if prices.is_empty() {
+            self.median_price = f64::NAN;
+        } else if prices.iter().all(|price| price.is_finite()) {
             // Safe unwrap: our slice doesn't contain a NaN. See this link for reference.
             // https://doc.rust-lang.org/std/primitive.slice.html#method.sort_unstable_by
             prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-            self.median_price = prices[1];
+            // Both indices point to the middle price if the number of exchanges is odd.
+            let (low, high) = ((prices.len() - 1) / 2, prices.len() / 2);
+            self.median_price = (prices[low] + prices[high]) / 2.0;
         }
     }
 }
@@ -84,6 +178,7 @@ impl Engine {
             dot: Cache::init(),
             eth: Cache::init(),
             sol: Cache::init(),
+            enabled: [true; Exchange::ALL.len()],
         }
     }
 
@@ -98,14 +193,30 @@ impl Engine {
     }
 
     /// Update the current price of an altcoin in the index for a particular exchange.
+    /// It's ignored if the exchange is disabled.
     pub fn update_price(&mut self, coin: Coin, exchange: Exchange, price: f64) -> f64 {
+        if !self.enabled[exchange as usize] {
+            return self.get_index();
+        }
+        let enabled = self.enabled;
         let cache = self.get_mut_cache(coin);
-        match exchange {
-            Exchange::Binance => cache.price_binance = price,
-            Exchange::Coinbase => cache.price_coinbase = price,
-            Exchange::Kraken => cache.price_kraken = price,
-        };
-        cache.update_median_price();
+        cache.prices[exchange as usize] = price;
+        cache.update_median_price(&enabled);
+        cache.update_market_cap();
+        self.get_index()
+    }
+
+    /// Update the current VWAP of an altcoin in the index for a particular exchange.
+    /// It's used instead of the market price on this exchange, unless it's reset to NaN, e.g.
+    /// once no trade is left in its window.
+    pub fn update_vwap(&mut self, coin: Coin, exchange: Exchange, vwap: f64) -> f64 {
+        if !self.enabled[exchange as usize] {
+            return self.get_index();
+        }
+        let enabled = self.enabled;
+        let cache = self.get_mut_cache(coin);
+        cache.vwaps[exchange as usize] = vwap;
+        cache.update_median_price(&enabled);
         cache.update_market_cap();
         self.get_index()
     }
@@ -118,6 +229,35 @@ impl Engine {
         self.get_index()
     }
 
+    /// Enable or disable an exchange. A disabled exchange is excluded from the median right away,
+    /// and its prices are forgotten, so that the median waits for fresh ones once it's enabled
+    /// again.
+    pub fn set_enabled(&mut self, exchange: Exchange, enabled: bool) -> f64 {
+        self.enabled[exchange as usize] = enabled;
+        let enabled = self.enabled;
+        for coin in Coin::ALL {
+            let cache = self.get_mut_cache(coin);
+            cache.prices[exchange as usize] = f64::NAN;
+            cache.vwaps[exchange as usize] = f64::NAN;
+            cache.update_median_price(&enabled);
+            cache.update_market_cap();
+        }
+        self.get_index()
+    }
+
+    /// Check whether the market price of an altcoin on a particular exchange is used in the
+    /// median, i.e. the exchange is enabled and its VWAP doesn't replace the market price.
+    pub fn is_price_used(&self, coin: Coin, exchange: Exchange) -> bool {
+        let cache = match coin {
+            Coin::ADA => &self.ada,
+            Coin::DOGE => &self.doge,
+            Coin::DOT => &self.dot,
+            Coin::ETH => &self.eth,
+            Coin::SOL => &self.sol,
+        };
+        self.enabled[exchange as usize] && !cache.vwaps[exchange as usize].is_finite()
+    }
+
     /// Get a mutable reference to the cache for a given altcoin.
     fn get_mut_cache(&mut self, coin: Coin) -> &mut Cache {
         match coin {
@@ -129,3 +269,81 @@ impl Engine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_price_waits_for_all_exchanges() {
+        let mut engine = Engine::init();
+        engine.update_supply(Coin::ETH, 1_000_000_000.0);
+        for (exchange, price) in [
+            (Exchange::Binance, 3000.0),
+            (Exchange::Bitfinex, 3012.0),
+            (Exchange::Bitstamp, 3010.0),
+            (Exchange::Bybit, 3005.0),
+            (Exchange::Coinbase, 3020.0),
+            (Exchange::Gemini, 3015.0),
+            (Exchange::Htx, 3008.0),
+            (Exchange::Kraken, 3100.0),
+        ] {
+            assert!(engine.update_price(Coin::ETH, exchange, price).is_nan());
+        }
+        assert!(engine.eth.median_price.is_nan());
+
+        engine.update_price(Coin::ETH, Exchange::Okx, 2990.0);
+        assert_eq!(engine.eth.median_price, 3010.0);
+        assert_eq!(engine.eth.market_cap, 3_010_000_000_000.0);
+    }
+
+    #[test]
+    fn vwap_replaces_market_price() {
+        let mut engine = Engine::init();
+        for exchange in Exchange::ALL {
+            engine.update_price(Coin::SOL, exchange, 20.0);
+        }
+        engine.update_vwap(Coin::SOL, Exchange::Binance, 21.0);
+        engine.update_vwap(Coin::SOL, Exchange::Coinbase, 21.0);
+        assert_eq!(engine.sol.median_price, 20.0);
+
+        // The market price of an exchange with a VWAP is ignored.
+        for exchange in [Exchange::Bitstamp, Exchange::Gemini, Exchange::Kraken] {
+            engine.update_vwap(Coin::SOL, exchange, 21.0);
+        }
+        engine.update_price(Coin::SOL, Exchange::Kraken, 19.0);
+        assert_eq!(engine.sol.median_price, 21.0);
+        assert!(!engine.is_price_used(Coin::SOL, Exchange::Kraken));
+        assert!(engine.is_price_used(Coin::SOL, Exchange::Okx));
+
+        // The market price is used again once the VWAP is reset.
+        for exchange in [Exchange::Binance, Exchange::Bitstamp, Exchange::Coinbase] {
+            engine.update_vwap(Coin::SOL, exchange, f64::NAN);
+        }
+        assert_eq!(engine.sol.median_price, 20.0);
+    }
+
+    #[test]
+    fn disabled_exchange_is_excluded() {
+        let mut engine = Engine::init();
+        engine.update_supply(Coin::DOT, 1_000_000_000.0);
+        for (exchange, price) in Exchange::ALL.into_iter().zip(1..) {
+            engine.update_price(Coin::DOT, exchange, price as f64);
+        }
+        assert_eq!(engine.dot.median_price, 5.0);
+
+        // The median is taken over the 8 other exchanges right away, and the prices of the
+        // disabled one are ignored.
+        engine.set_enabled(Exchange::Okx, false);
+        assert_eq!(engine.dot.median_price, 4.5);
+        assert_eq!(engine.dot.market_cap, 4_500_000_000.0);
+        engine.update_price(Coin::DOT, Exchange::Okx, 1.0);
+        assert_eq!(engine.dot.median_price, 4.5);
+
+        // Once enabled again, the median waits for a fresh price of the exchange.
+        engine.set_enabled(Exchange::Okx, true);
+        assert_eq!(engine.dot.median_price, 4.5);
+        engine.update_price(Coin::DOT, Exchange::Okx, 1.0);
+        assert_eq!(engine.dot.median_price, 4.0);
+    }
+}
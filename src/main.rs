@@ -1,37 +1,357 @@
+mod admin;
 mod binance;
+mod bitfinex;
+mod bitstamp;
+mod book;
+mod bybit;
+mod capture;
 mod coinbase;
+mod config;
 mod engine;
+mod gemini;
+mod htx;
+mod instruments;
 mod kraken;
+mod metrics;
+mod okx;
 mod price;
+mod proxy;
+mod replay;
 mod server;
 mod supply;
+mod vwap;
 
-use engine::Output;
+/// The mock exchange is built into the tests, so that the frames it sends are checked against the
+/// message handlers of the feeds.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "bin/mock-exchange/main.rs"]
+mod mock_exchange;
+
+use admin::DisabledExchanges;
+use altusd::Exchange;
+use config::Config;
+use engine::{Input, Output};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
 
 #[tokio::main]
 async fn main() {
     // Initialize the tracing subscriber with default settings.
     tracing_subscriber::fmt::init();
 
+    // Load the config from the file pointed to by the `ALTUSD_CONFIG` environment variable.
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            tracing::error!("failed to load config: {}", error);
+            return;
+        }
+    };
+
     // This mpsc channel is used to send altcoin prices and supplies to the core index engine.
     let (mpsc_tx, mpsc_rx) = tokio::sync::mpsc::channel(100_000);
 
     // This watch channel is used to notify changes in the index to the connected websocket clients.
     let (watch_tx, watch_rx) = tokio::sync::watch::channel(Output::init());
 
-    // These tasks are responsible for feeding the current price of our index's altcoins.
-    // Each task is responsible for one particular exchange: Binance, Coinbase, or Kraken.
-    tokio::spawn(binance::run(mpsc_tx.clone()));
-    tokio::spawn(coinbase::run(mpsc_tx.clone()));
-    tokio::spawn(kraken::run(mpsc_tx.clone()));
+    // This watch channel is used to notify the feeds of the exchanges disabled by an operator.
+    let (disabled_tx, disabled_rx) = tokio::sync::watch::channel(DisabledExchanges::new());
 
-    // This task is responsible for feeding the current circulating supply of our index's altcoins.
-    tokio::spawn(supply::run(mpsc_tx));
+    // This task is responsible for serving the admin endpoint, through which an operator enables
+    // or disables the exchanges at runtime.
+    tokio::spawn(admin::run(
+        config.admin.clone(),
+        disabled_tx,
+        mpsc_tx.clone(),
+    ));
+
+    // In replay mode, the captured frames and supply responses are fed to the engine in place
+    // of the live feeds.
+    if config.replay.directory.is_some() {
+        tokio::spawn(replay::run(config.clone(), mpsc_tx));
+    } else {
+        spawn_feeds(&config, mpsc_tx, disabled_rx);
+    }
 
     // This task is responsible for running the core index engine.
-    tokio::spawn(engine::run(mpsc_rx, watch_tx));
+    tokio::spawn(engine::run(config.engine, mpsc_rx, watch_tx));
+
+    // This task is responsible for serving the metrics of the feeds over HTTP.
+    tokio::spawn(metrics::run());
 
     // The current task is responsible for serving our index's price stream over websockets.
     // Internally, it spawns a new tokio task for each connected websocket client.
     server::run(watch_rx).await;
 }
+
+/// This function is responsible for spawning the live feeds of the market prices and of the
+/// circulating supplies. The feeds of the exchanges disconnect while they are disabled.
+fn spawn_feeds(config: &Config, mpsc_tx: Sender<Input>, disabled_rx: Receiver<DisabledExchanges>) {
+    // These tasks are responsible for feeding the current price of our index's altcoins.
+    // Each task is responsible for one particular exchange: Binance, Bitfinex, Bitstamp, Bybit,
+    // Coinbase, Gemini, HTX, Kraken, or OKX. The symbols come from the instrument registry.
+    tokio::spawn(binance::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Binance),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(bitfinex::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Bitfinex),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(bitstamp::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Bitstamp),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(bybit::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Bybit),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(coinbase::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Coinbase),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(gemini::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Gemini),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(htx::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Htx),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(kraken::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Kraken),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+    tokio::spawn(okx::run(
+        config.feeds.clone(),
+        config.instruments.symbols(Exchange::Okx),
+        mpsc_tx.clone(),
+        disabled_rx.clone(),
+    ));
+
+    // This task is responsible for feeding the current circulating supply of our index's altcoins.
+    tokio::spawn(supply::run(
+        config.supply.clone(),
+        config.feeds.capture.clone(),
+        config.feeds.proxy.clone(),
+        config.instruments.supply_ids(config.supply.provider),
+        mpsc_tx,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BinanceTopOfBook, FeedConfig, KrakenApi};
+    use crate::instruments::Instruments;
+    use crate::price::{Event, Session, WebSocketPriceFeed};
+    use altusd::Coin;
+    use mock_exchange::{Registry, Tick};
+    use serde_json::Value;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Arc;
+
+    /// The number of ticks streamed by the mock exchange in each test.
+    const TICKS: u64 = 3;
+
+    /// The maximum number of requests and snapshots of a connection, over which the feed and the
+    /// mock exchange are considered to disagree, e.g. on the checksum of an order book.
+    const MAX_ROUND_TRIPS: usize = 1000;
+
+    /// This function is a helper to build a tick of the mock exchange, whose prices move up at
+    /// every tick so that the order books change.
+    fn mock_tick(number: u64) -> Tick {
+        let markets = Coin::ALL.iter().map(|coin| {
+            let market = mock_exchange::market(3000.0 + number as f64, 3000.0);
+            (*coin, market)
+        });
+        Tick {
+            number,
+            time: 1673853746151 + number * 1000,
+            markets: markets.collect(),
+        }
+    }
+
+    /// This function is a helper to get the path and query of a URL, which is how the mock
+    /// exchange tells its endpoints apart.
+    fn path(url: &str) -> &str {
+        let (_, rest) = url.split_once("://").unwrap_or(("", url));
+        rest.find('/').map_or("/", |index| &rest[index..])
+    }
+
+    /// This function is a helper to run each connection of a feed against the mock exchange, and
+    /// to collect the events parsed by the message handler of the feed. The subscribe messages
+    /// and the replies of the handler are sent to the mock exchange, and the snapshots of the
+    /// order books are fetched from its REST endpoints, in the same order as the live feed.
+    fn mock_events(feed: &WebSocketPriceFeed) -> Vec<Event> {
+        let registry = Arc::new(Registry::load().unwrap());
+        let mut events = Vec::new();
+        for connection in &feed.connections {
+            let protocol = mock_exchange::protocol(feed.exchange, path(&connection.endpoint));
+            let mut mock_session = mock_exchange::Session {
+                exchange: feed.exchange,
+                path: path(&connection.endpoint).to_string(),
+                registry: registry.clone(),
+                subscriptions: Vec::new(),
+                books: HashMap::new(),
+                sequence: 0,
+            };
+            let mut session = Session {
+                coins: connection.coins.clone(),
+                symbols: feed.symbols.clone(),
+                ..Session::default()
+            };
+            let mut requests: VecDeque<String> =
+                connection.subscribe.iter().map(Value::to_string).collect();
+            let mut tick = mock_tick(1);
+            let mut messages = (protocol.connect)(&mut mock_session);
+            let mut round_trips = 0;
+            loop {
+                for message in messages.drain(..) {
+                    let text = mock_exchange::text(message);
+                    let mut handled = VecDeque::from((feed.message_handler)(&mut session, text));
+                    while let Some(event) = handled.pop_front() {
+                        if matches!(event, Event::SnapshotRequired(_) | Event::Reply(_)) {
+                            round_trips += 1;
+                            assert!(round_trips <= MAX_ROUND_TRIPS, "too many round trips");
+                        }
+                        match &event {
+                            Event::SnapshotRequired(coin) => {
+                                let snapshot = feed.snapshot.as_ref().unwrap();
+                                let symbol = session.symbols.symbol(*coin).unwrap();
+                                let url =
+                                    format!("{}{}", snapshot.endpoint, (snapshot.path)(symbol));
+                                let response = (protocol.rest)(&registry, &tick, path(&url));
+                                let response = response.unwrap().to_string();
+                                handled.extend((snapshot.handler)(&mut session, *coin, response));
+                            }
+                            Event::Reply(reply) => requests.push_back(reply.clone()),
+                            _ => {}
+                        }
+                        events.push(event);
+                    }
+                }
+                if let Some(request) = requests.pop_front() {
+                    messages = (protocol.request)(&mut mock_session, &tick, &request);
+                } else if tick.number < TICKS {
+                    tick = mock_tick(tick.number + 1);
+                    messages = (protocol.tick)(&mut mock_session, &tick);
+                } else {
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    /// This function is a helper to check that the messages of the mock exchange were accepted,
+    /// i.e. that every altcoin was updated and that no error was raised.
+    fn assert_streamed(events: &[Event]) {
+        for event in events {
+            assert!(
+                !matches!(
+                    event,
+                    Event::Rejected(_) | Event::Error(_) | Event::Reconnect(_)
+                ),
+                "unexpected event: {:?}",
+                event
+            );
+        }
+        for coin in Coin::ALL {
+            let updated = events.iter().any(|event| match event {
+                Event::Ticker(ticker) => ticker.coin == coin,
+                Event::Trade(trade) => trade.coin == coin,
+                Event::Quote(quote) => quote.coin == coin,
+                Event::Book(book_coin, _) => *book_coin == coin,
+                _ => false,
+            });
+            assert!(updated, "no update of {:?}", coin);
+        }
+    }
+
+    /// This function is a helper to build the feed of an exchange with the default symbols.
+    fn feed(exchange: Exchange, config: &FeedConfig) -> WebSocketPriceFeed {
+        let symbols = Instruments::default().symbols(exchange);
+        match exchange {
+            Exchange::Binance => binance::feed(config, symbols),
+            Exchange::Bitfinex => bitfinex::feed(symbols),
+            Exchange::Bitstamp => bitstamp::feed(symbols),
+            Exchange::Bybit => bybit::feed(symbols),
+            Exchange::Coinbase => coinbase::feed(symbols),
+            Exchange::Gemini => gemini::feed(symbols),
+            Exchange::Htx => htx::feed(symbols),
+            Exchange::Kraken => kraken::feed(config, symbols),
+            Exchange::Okx => okx::feed(symbols),
+        }
+    }
+
+    #[test]
+    fn mock_binance_depth() {
+        let config = FeedConfig::default();
+        let events = mock_events(&feed(Exchange::Binance, &config));
+        assert_streamed(&events);
+        let snapshots = events
+            .iter()
+            .filter(|event| matches!(event, Event::Book(_, None)));
+        assert_eq!(snapshots.count(), Coin::ALL.len());
+    }
+
+    #[test]
+    fn mock_binance_book_ticker() {
+        let config = FeedConfig {
+            binance_top_of_book: BinanceTopOfBook::BookTicker,
+            ..FeedConfig::default()
+        };
+        assert_streamed(&mock_events(&feed(Exchange::Binance, &config)));
+    }
+
+    #[test]
+    fn mock_exchanges() {
+        let config = FeedConfig::default();
+        for exchange in [
+            Exchange::Bitfinex,
+            Exchange::Bitstamp,
+            Exchange::Bybit,
+            Exchange::Coinbase,
+            Exchange::Gemini,
+            Exchange::Htx,
+            Exchange::Okx,
+        ] {
+            assert_streamed(&mock_events(&feed(exchange, &config)));
+        }
+    }
+
+    #[test]
+    fn mock_kraken() {
+        // A checksum mismatch would resubscribe the book, so no reply is expected.
+        for kraken_api in [KrakenApi::V1, KrakenApi::V2] {
+            let config = FeedConfig {
+                kraken_api,
+                ..FeedConfig::default()
+            };
+            let events = mock_events(&feed(Exchange::Kraken, &config));
+            assert_streamed(&events);
+            let replies = events
+                .iter()
+                .filter(|event| matches!(event, Event::Reply(_)));
+            assert_eq!(replies.count(), 0);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use altusd::Exchange;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The address of the metrics server.
+const ADDR: &str = "0.0.0.0:9090";
+
+/// The prefix of every metric name, to avoid clashes with other apps scraped by Prometheus.
+const PREFIX: &str = "altusd";
+
+/// The global registry of metrics, shared by all the tasks of the app.
+pub static METRICS: Metrics = Metrics::new();
+
+/// This struct represents a registry of metrics labeled by exchange.
+/// Counters only go up, whereas gauges are set to their current value. Since an exchange can
+/// have several connections, gauges are also labeled by connection.
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(&'static str, Exchange), u64>>,
+    gauges: Mutex<BTreeMap<(&'static str, Exchange, usize), u64>>,
+}
+
+impl Metrics {
+    /// Default constructor. The registry is empty until a metric gets recorded.
+    const fn new() -> Self {
+        Self {
+            counters: Mutex::new(BTreeMap::new()),
+            gauges: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Increment the counter with the given name for a particular exchange.
+    pub fn increment(&self, name: &'static str, exchange: Exchange) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry((name, exchange)).or_insert(0) += 1;
+    }
+
+    /// Set the gauge with the given name for a particular connection to an exchange.
+    pub fn set(&self, name: &'static str, exchange: Exchange, connection_id: usize, value: u64) {
+        let mut gauges = self.gauges.lock().unwrap();
+        gauges.insert((name, exchange, connection_id), value);
+    }
+
+    /// Get the counter with the given name for a particular exchange, which is zero until it's
+    /// incremented.
+    #[cfg(test)]
+    pub fn counter(&self, name: &'static str, exchange: Exchange) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters.get(&(name, exchange)).copied().unwrap_or_default()
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format. See this link for reference:
+    /// https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let counters = self.counters.lock().unwrap();
+        let counters = counters.iter().map(|((name, exchange), value)| {
+            let labels = format!("exchange=\"{}\"", exchange.name());
+            (*name, labels, *value)
+        });
+        render_family(&mut output, "counter", counters);
+
+        let gauges = self.gauges.lock().unwrap();
+        let gauges = gauges
+            .iter()
+            .map(|((name, exchange, connection_id), value)| {
+                let labels = format!(
+                    "exchange=\"{}\",connection=\"{}\"",
+                    exchange.name(),
+                    connection_id
+                );
+                (*name, labels, *value)
+            });
+        render_family(&mut output, "gauge", gauges);
+        output
+    }
+}
+
+/// This function is a helper to render metrics of the same type. Since the samples are sorted by
+/// name, the samples of a given metric are contiguous and are preceded by a single `# TYPE` line.
+fn render_family(
+    output: &mut String,
+    kind: &str,
+    samples: impl Iterator<Item = (&'static str, String, u64)>,
+) {
+    let mut previous_name = None;
+    for (name, labels, value) in samples {
+        // Safe unwraps: writing to a `String` never fails.
+        if previous_name != Some(name) {
+            writeln!(output, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+            previous_name = Some(name);
+        }
+        writeln!(output, "{}_{}{{{}}} {}", PREFIX, name, labels, value).unwrap();
+    }
+}
+
+/// This function is responsible for running the metrics server.
+/// It's a minimal HTTP server that answers every request with the current metrics.
+pub async fn run() {
+    let listener = TcpListener::bind(ADDR).await.unwrap();
+    tracing::info!("metrics server started: {}", ADDR);
+
+    // Accept tcp connections in a loop. Each connection is handled in its owned spawned task.
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::error!("failed to accept tcp connection: {}", error);
+                continue;
+            }
+        };
+        tokio::spawn(handle_connection(stream));
+    }
+}
+
+/// This function is responsible for handling a single HTTP connection.
+async fn handle_connection(mut stream: TcpStream) {
+    // Read the request. Its content is irrelevant since we only serve one resource.
+    let mut buffer = [0; 1024];
+    if let Err(error) = stream.read(&mut buffer).await {
+        tracing::warn!("failed to read http request: {}", error);
+        return;
+    }
+
+    // Write the response and close the connection.
+    let body = METRICS.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    if let Err(error) = stream.write_all(response.as_bytes()).await {
+        tracing::warn!("failed to write http response: {}", error);
+    }
+}
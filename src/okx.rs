@@ -0,0 +1,257 @@
+use crate::admin::DisabledExchanges;
+use crate::config::FeedConfig;
+use crate::engine::Input;
+use crate::instruments::Symbols;
+use crate::price::{
+    Connection, Event, Keepalive, Poll, Sequence, Session, Ticker, WebSocketPriceFeed,
+};
+use altusd::Exchange;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch::Receiver;
+
+/// The REST endpoint that serves the tickers, which are polled while the websocket is down.
+const POLL_ENDPOINT: &str = "https://www.okx.com/api/v5/market/ticker?instId=";
+
+/// This function is responsible to subscribe to the OKX websocket price feed.
+///
+/// See `price.rs` for the details on the implementation of `WebSocketPriceFeed`.
+pub async fn run(
+    config: FeedConfig,
+    symbols: Symbols,
+    mpsc_tx: Sender<Input>,
+    disabled: Receiver<DisabledExchanges>,
+) {
+    feed(symbols).run(config, mpsc_tx, disabled).await;
+}
+
+/// This function builds the OKX websocket price feed, which is also used to replay its captures.
+pub fn feed(symbols: Symbols) -> WebSocketPriceFeed {
+    let args: Vec<_> = symbols
+        .iter()
+        .map(|(_, inst_id)| json!({ "channel": "tickers", "instId": inst_id }))
+        .collect();
+    WebSocketPriceFeed {
+        exchange: Exchange::Okx,
+        connections: vec![Connection::all_coins(
+            "wss://ws.okx.com:8443/ws/v5/public",
+            vec![json!({
+                "op": "subscribe",
+                "args": args,
+            })],
+        )],
+        symbols,
+        // OKX closes connections that are quiet for 30 seconds, unless the client sends a `ping`.
+        idle_timeout: Duration::from_secs(30),
+        keepalive: Some(Keepalive {
+            interval: Duration::from_secs(20),
+            message: "ping",
+        }),
+        scheduled: Vec::new(),
+        snapshot: None,
+        poll: Some(Poll {
+            endpoint: POLL_ENDPOINT.to_string(),
+            path: poll_path,
+            handler: poll_handler,
+        }),
+        // OKX serves the same API on AWS, which is closer to some regions.
+        mirrors: vec!["wss://wsaws.okx.com:8443"],
+        message_handler,
+    }
+}
+
+/// These structs represent a message from the `tickers` channel. See this link for reference:
+/// https://www.okx.com/docs-v5/en/#websocket-api-public-channel-tickers-channel
+#[derive(Deserialize)]
+struct Message<'a> {
+    #[serde(borrow)]
+    data: Vec<MessageData<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageData<'a> {
+    inst_id: &'a str,
+    last: &'a str,
+    bid_px: &'a str,
+    ask_px: &'a str,
+    ts: &'a str,
+}
+
+/// These structs represent an event, i.e. the response to a subscribe request or an error.
+/// See this link for reference: https://www.okx.com/docs-v5/en/#websocket-api-subscribe
+#[derive(Deserialize)]
+struct EventMessage<'a> {
+    event: &'a str,
+    #[serde(borrow)]
+    arg: Option<EventArg<'a>>,
+    code: Option<&'a str>,
+    msg: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventArg<'a> {
+    inst_id: &'a str,
+}
+
+/// This message handler tries to parse the last price, best bid, and best ask for an altcoin.
+///
+/// Effectively, this is a callback used for every websocket received from the exchange.
+fn message_handler(session: &mut Session, message: String) -> Vec<Event> {
+    parse_message(session, &message).into_iter().collect()
+}
+
+/// This function is a helper to parse a message, which carries at most one event.
+fn parse_message(session: &Session, message: &str) -> Option<Event> {
+    // The reply to our keepalive is a plain text `pong`, not a JSON object.
+    if message == "pong" {
+        return Some(Event::Heartbeat);
+    }
+
+    // Deserialize.
+    let message = match serde_json::from_str::<Message>(message) {
+        Ok(message) => message,
+        Err(_) => return event_handler(session, message),
+    };
+
+    // Extract the first and only ticker of the message.
+    let data = match message.data.first() {
+        Some(data) => data,
+        None => {
+            tracing::error!("unexpected empty message");
+            return None;
+        }
+    };
+
+    // Extract coin.
+    let coin = session.symbols.coin(data.inst_id)?;
+
+    // Extract last price, best bid, best ask, and sequence.
+    let last_price = crate::price::str_to_f64(data.last)?;
+    let best_bid = crate::price::str_to_f64(data.bid_px)?;
+    let best_ask = crate::price::str_to_f64(data.ask_px)?;
+    let time = match data.ts.parse() {
+        Ok(time) => time,
+        Err(error) => {
+            tracing::error!("failed to parse timestamp: {}", error);
+            return None;
+        }
+    };
+    Some(Event::Ticker(Ticker {
+        coin,
+        prices: [last_price, best_bid, best_ask],
+        sequence: Some(Sequence::Time(time)),
+        time: Some(time),
+    }))
+}
+
+/// This message handler tries to parse an event. OKX confirms each instrument separately.
+fn event_handler(session: &Session, message: &str) -> Option<Event> {
+    let event = match serde_json::from_str::<EventMessage>(message) {
+        Ok(event) => event,
+        Err(_) => {
+            tracing::warn!("discarded message: {}", message);
+            return None;
+        }
+    };
+
+    match event.event {
+        "subscribe" => {
+            let coin = session.symbols.coin(event.arg?.inst_id)?;
+            Some(Event::Subscribed(vec![coin]))
+        }
+        "error" => Some(Event::Rejected(format!(
+            "{}: {}",
+            event.code.unwrap_or_default(),
+            event.msg.unwrap_or_default()
+        ))),
+        _ => {
+            tracing::warn!("discarded message: {}", message);
+            None
+        }
+    }
+}
+
+/// This function is a helper to build the path of the REST ticker of an instrument, which is the
+/// bare instrument ID, e.g. `ETH-USDT`, appended to the `instId` query of the endpoint.
+fn poll_path(inst_id: &str) -> String {
+    inst_id.to_string()
+}
+
+/// This poll handler tries to parse the last price, best bid, and best ask from a REST ticker,
+/// which has the same data as the ticker messages. See this link for reference:
+/// https://www.okx.com/docs-v5/en/#order-book-trading-market-data-get-ticker
+fn poll_handler(response: &str) -> Option<[f64; 3]> {
+    let response = serde_json::from_str::<Message>(response).ok()?;
+    let ticker = response.data.first()?;
+    Some([
+        crate::price::str_to_f64(ticker.last)?,
+        crate::price::str_to_f64(ticker.bid_px)?,
+        crate::price::str_to_f64(ticker.ask_px)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use altusd::Coin;
+
+    /// This function is a helper to build a session with the default symbols.
+    fn session() -> Session {
+        Session::with_default_symbols(Exchange::Okx)
+    }
+
+    #[test]
+    fn ticker_message() {
+        let message = r#"{"arg":{"channel":"tickers","instId":"DOT-USDT"},"data":[{"instType":"SPOT","instId":"DOT-USDT","last":"18.734","lastSz":"12.5","askPx":"18.736","askSz":"410.2","bidPx":"18.733","bidSz":"95.1","open24h":"19.01","high24h":"19.25","low24h":"18.51","sodUtc0":"18.9","sodUtc8":"18.77","volCcy24h":"24803321.51","vol24h":"1320471.43","ts":"1645113600512"}]}"#;
+        let expected = Event::Ticker(Ticker {
+            coin: Coin::DOT,
+            prices: [18.734, 18.733, 18.736],
+            sequence: Some(Sequence::Time(1645113600512)),
+            time: Some(1645113600512),
+        });
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn pong_message() {
+        assert_eq!(
+            message_handler(&mut session(), "pong".to_string()),
+            vec![Event::Heartbeat]
+        );
+    }
+
+    #[test]
+    fn subscribe_message() {
+        let message = r#"{"event":"subscribe","arg":{"channel":"tickers","instId":"SOL-USDT"},"connId":"a4d3ae55"}"#;
+        let expected = Event::Subscribed(vec![Coin::SOL]);
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn error_message() {
+        let message = r#"{"event":"error","code":"60018","msg":"Wrong URL or channel:tickers,instId:FOO-USDT doesn't exist.","connId":"a4d3ae55"}"#;
+        let expected = Event::Rejected(
+            "60018: Wrong URL or channel:tickers,instId:FOO-USDT doesn't exist.".to_string(),
+        );
+        assert_eq!(
+            message_handler(&mut session(), message.to_string()),
+            vec![expected]
+        );
+    }
+
+    #[test]
+    fn poll_response() {
+        let response = r#"{"code":"0","msg":"","data":[{"instType":"SPOT","instId":"DOT-USDT","last":"18.734","lastSz":"12.5","askPx":"18.736","askSz":"410.2","bidPx":"18.733","bidSz":"95.1","open24h":"19.01","high24h":"19.25","low24h":"18.51","volCcy24h":"24803321.51","vol24h":"1320471.43","ts":"1645113600512","sodUtc0":"18.9","sodUtc8":"18.77"}]}"#;
+        assert_eq!(poll_handler(response), Some([18.734, 18.733, 18.736]));
+    }
+}
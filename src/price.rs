@@ -1,75 +1,1399 @@
-use crate::engine::Input;
+use crate::admin::DisabledExchanges;
+use crate::book::OrderBook;
+use crate::capture::{hex, unhex, Capture, Frame, Record};
+use crate::config::{FeedConfig, SubscriptionFailure};
+use crate::engine::{Input, Source, Timestamps};
+use crate::instruments::Symbols;
+use crate::metrics::METRICS;
+use crate::vwap::RollingVwap;
 use altusd::{Coin, Exchange};
+use flate2::read::{DeflateDecoder, GzDecoder};
 use futures::{SinkExt, StreamExt};
+use rand::Rng;
 use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
 use std::time::Duration;
 use tokio::sync::mpsc::Sender;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::{watch, Mutex};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::{Error as WebSocketError, Message};
 
-/// The interval at which we reconnect to the websocket server if an error occurs.
-const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+/// The first bytes of a gzip stream. See this link for reference:
+/// https://www.rfc-editor.org/rfc/rfc1952#page-6
+const GZIP_MAGIC_NUMBER: [u8; 2] = [0x1f, 0x8b];
 
-/// This struct represent a generic websocket price feed connection to an exchange.
+/// The delay before the first reconnection to the websocket server if an error occurs.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The maximum delay between two reconnections to the websocket server.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The user agent sent to the REST endpoints, some of which reject requests without one.
+const USER_AGENT: &str = "altusd";
+
+/// This struct represent a generic websocket price feed to an exchange.
 /// It should be implemented by all supported exchanges.
+///
+/// Most exchanges stream all our altcoins on a single connection, but some of them require one
+/// connection per altcoin. All the connections of an exchange are managed by the same feed.
+/// If no message is received within `idle_timeout`, the connection is considered stalled.
+/// Exchanges that offer a heartbeat channel should subscribe to it so that quiet markets
+/// don't trigger a reconnection, whereas exchanges that expect the client to keep the
+/// connection alive should set a `keepalive` or `scheduled` messages.
+/// Exchanges whose order book must be synchronized with a REST snapshot should set a `snapshot`.
+/// Exchanges that serve a REST ticker should set a `poll`, which is used while a connection is
+/// down. The `symbols` of the exchange come from the instrument registry.
+/// The websocket and REST endpoints are the public ones, unless the config overrides them.
+/// The `mirrors` are the origins of alternate hosts of the websocket server, over which the
+/// redundant replicas of each connection are spread, if the config asks for more than one.
 pub struct WebSocketPriceFeed {
-    pub endpoint: &'static str,
     pub exchange: Exchange,
-    pub subscribe: Value,
-    pub message_handler: fn(String) -> Option<(Coin, [f64; 3])>,
+    pub symbols: Symbols,
+    pub connections: Vec<Connection>,
+    pub idle_timeout: Duration,
+    pub keepalive: Option<Keepalive>,
+    pub scheduled: Vec<ScheduledMessage>,
+    pub snapshot: Option<Snapshot>,
+    pub poll: Option<Poll>,
+    pub mirrors: Vec<&'static str>,
+    pub message_handler: fn(&mut Session, String) -> Vec<Event>,
+}
+
+/// This struct represents a websocket connection to an exchange. The `subscribe` messages are
+/// sent in order right after connecting, and the subscription of each of the `coins` must then
+/// be confirmed by the exchange.
+pub struct Connection {
+    pub endpoint: String,
+    pub subscribe: Vec<Value>,
+    pub coins: Vec<Coin>,
+}
+
+impl Connection {
+    /// Constructor for a connection that streams all our altcoins.
+    pub fn all_coins(endpoint: &str, subscribe: Vec<Value>) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            subscribe,
+            coins: Coin::ALL.to_vec(),
+        }
+    }
+}
+
+/// This struct represents the context of a connection that is passed to the message handler.
+/// It's created anew for every reconnection.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The altcoins streamed on this connection.
+    pub coins: Vec<Coin>,
+    /// The symbols of the altcoins on the exchange, which map the messages to the altcoins.
+    pub symbols: Symbols,
+    /// The altcoin of each channel, for exchanges that assign channel IDs on subscription.
+    pub channels: HashMap<u64, Coin>,
+    /// The local order book of each altcoin, for exchanges that stream their order books.
+    pub books: HashMap<Coin, OrderBook>,
+    /// The messages received for each altcoin while waiting for a snapshot of its order book.
+    pub buffered: HashMap<Coin, Vec<String>>,
+    /// The price and quantity precisions of each altcoin, for exchanges that send the levels of
+    /// their order books as numbers.
+    pub precisions: HashMap<Coin, (usize, usize)>,
+    /// The altcoins whose subscription was confirmed, for exchanges that don't acknowledge it.
+    pub confirmed: HashSet<Coin>,
+}
+
+#[cfg(test)]
+impl Session {
+    /// Constructor for the tests of a message handler, with the default symbols of an exchange.
+    pub fn with_default_symbols(exchange: Exchange) -> Self {
+        Self {
+            symbols: crate::instruments::Instruments::default().symbols(exchange),
+            ..Session::default()
+        }
+    }
+}
+
+/// This struct represents an application-level keepalive, i.e. a text message sent to the
+/// exchange when no message has been received for `interval`. The exchange's reply should be
+/// parsed as a heartbeat by the message handler.
+pub struct Keepalive {
+    pub interval: Duration,
+    pub message: &'static str,
+}
+
+/// This struct represents a message sent to the exchange every `interval` after subscribing,
+/// regardless of the traffic on the connection, e.g. a client heartbeat.
+pub struct ScheduledMessage {
+    pub interval: Duration,
+    pub message: Value,
+}
+
+/// This struct represents the REST endpoint that serves a snapshot of the order book of an
+/// altcoin, and the handler that applies the response to the session.
+/// The URL of the snapshot is the `endpoint` followed by the `path` of the altcoin's symbol.
+pub struct Snapshot {
+    pub endpoint: String,
+    pub path: fn(&str) -> String,
+    pub handler: fn(&mut Session, Coin, String) -> Vec<Event>,
+}
+
+/// This struct represents the REST endpoint that serves the ticker of an altcoin, and the handler
+/// that parses the last price, best bid, and best ask from the response, in this order.
+/// The URL of the ticker is the `endpoint` followed by the `path` of the altcoin's symbol.
+pub struct Poll {
+    pub endpoint: String,
+    pub path: fn(&str) -> String,
+    pub handler: fn(&str) -> Option<[f64; 3]>,
+}
+
+/// This enum represents a message parsed by an exchange's message handler.
+///
+/// Exchanges without a ticker channel can send the last price and the best bid and ask in
+/// separate messages. In that case, the feed combines them into the market price.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Ticker(Ticker),
+    Trade(Trade),
+    Quote(Quote),
+    /// The order book of this altcoin was updated in the session, at this exchange event time.
+    Book(Coin, Option<u64>),
+    /// The order book of this altcoin must be synchronized with a snapshot.
+    SnapshotRequired(Coin),
+    Heartbeat,
+    /// The exchange confirmed the subscription of these altcoins.
+    Subscribed(Vec<Coin>),
+    /// The exchange rejected the subscription of a product, for the given reason.
+    Rejected(String),
+    /// The exchange reported an error that isn't related to a subscription.
+    Error(String),
+    /// The exchange asked to reconnect, for the given reason, e.g. before a restart.
+    Reconnect(String),
+    /// The exchange expects this message in reply, e.g. a pong to its own ping.
+    Reply(String),
+}
+
+/// This struct represents a ticker message parsed by an exchange's message handler.
+/// The prices are the last price, best bid, and best ask, in this order.
+///
+/// Like the other market data messages, it carries the exchange event time in milliseconds since
+/// the Unix epoch, if the exchange sends one.
+#[derive(Debug, PartialEq)]
+pub struct Ticker {
+    pub coin: Coin,
+    pub prices: [f64; 3],
+    pub sequence: Option<Sequence>,
+    pub time: Option<u64>,
+}
+
+/// This struct represents a trade message, which carries the last price.
+/// The traded quantity weighs the trade in the VWAP, and is `None` if the exchange only sends
+/// the last price, e.g. in a ticker.
+#[derive(Debug, PartialEq)]
+pub struct Trade {
+    pub coin: Coin,
+    pub price: f64,
+    pub quantity: Option<f64>,
+    pub sequence: Option<Sequence>,
+    pub time: Option<u64>,
+}
+
+/// This struct represents a top-of-book message, which carries the best bid and ask.
+/// A side of the book that didn't change may be omitted.
+#[derive(Debug, PartialEq)]
+pub struct Quote {
+    pub coin: Coin,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub sequence: Option<Sequence>,
+    pub time: Option<u64>,
+}
+
+/// This enum represents the kind of message that updated a market. Each kind of message is
+/// usually sent on its own channel, with its own sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Channel {
+    Ticker,
+    Trade,
+    Quote,
+    Book,
+}
+
+/// This struct represents the market data update carried by an event, before it's fed to the
+/// core engine. The prices are the last price, best bid, and best ask, and the traded price and
+/// quantity feed the VWAP, if any.
+struct Update {
+    coin: Coin,
+    channel: Channel,
+    prices: [Option<f64>; 3],
+    sequence: Option<Sequence>,
+    time: Option<u64>,
+    traded: Option<(f64, f64)>,
+    /// The replica of the connection that delivered the update.
+    replica: usize,
+}
+
+/// This enum represents the value used by an exchange to order the messages of a product.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sequence {
+    /// A sequence number, which increases by exactly one for every message of a product.
+    Number(u64),
+    /// An event time in milliseconds since the Unix epoch, which never decreases.
+    Time(u64),
+    /// An update ID, which increases for every message of a product, but skips the updates
+    /// that aren't pushed, e.g. the order book updates that don't change the top of the book.
+    /// It only orders the messages, so its gaps aren't counted.
+    UpdateId(u64),
+}
+
+/// This struct keeps track of the highest sequence received for each product and channel of an
+/// exchange, in order to discard messages that were delivered out of order.
+///
+/// The redundant connections of a stream share the same tracker, so that the messages delivered by
+/// more than one of them are only handled once, whichever connection delivers them first.
+struct SequenceTracker {
+    exchange: Exchange,
+    /// The highest sequence of each product and channel, and the replica that delivered it.
+    highest: HashMap<(Coin, Channel), (Sequence, usize)>,
+}
+
+impl SequenceTracker {
+    /// Default constructor. No sequence has been received yet.
+    fn init(exchange: Exchange) -> Self {
+        Self {
+            exchange,
+            highest: HashMap::new(),
+        }
+    }
+
+    /// Check whether a message with the given sequence, delivered by the given replica of the
+    /// stream, is newer than the previous messages. If so, it becomes the highest sequence for
+    /// this product. Otherwise, the message is stale, or a duplicate if the stream is redundant.
+    /// Since several messages may share the same event time, they are only duplicates if another
+    /// replica delivered that time first.
+    ///
+    /// Gaps, reorderings, and duplicates are counted in the metrics.
+    fn is_newer(
+        &mut self,
+        coin: Coin,
+        channel: Channel,
+        sequence: Sequence,
+        replica: usize,
+        redundant: bool,
+    ) -> bool {
+        let is_newer = match (self.highest.get(&(coin, channel)), sequence) {
+            (Some((Sequence::Number(highest), _)), Sequence::Number(number)) => {
+                if number > highest + 1 {
+                    tracing::debug!(
+                        "sequence gap: {:?} {:?}: {} -> {}",
+                        self.exchange,
+                        coin,
+                        highest,
+                        number
+                    );
+                    METRICS.increment("sequence_gaps_total", self.exchange);
+                }
+                number > *highest
+            }
+            (Some((Sequence::UpdateId(highest), _)), Sequence::UpdateId(id)) => id > *highest,
+            (Some((Sequence::Time(highest), from)), Sequence::Time(time)) => {
+                time > *highest || (time == *highest && (*from == replica || !redundant))
+            }
+            _ => true,
+        };
+
+        if is_newer {
+            self.highest.insert((coin, channel), (sequence, replica));
+        } else if redundant {
+            tracing::debug!(
+                "duplicate message: {:?} {:?}: {:?}",
+                self.exchange,
+                coin,
+                sequence
+            );
+            METRICS.increment("duplicates_total", self.exchange);
+        } else {
+            tracing::warn!(
+                "out-of-order message: {:?} {:?}: {:?}",
+                self.exchange,
+                coin,
+                sequence
+            );
+            METRICS.increment("sequence_reorders_total", self.exchange);
+        }
+        is_newer
+    }
+}
+
+/// This enum represents the state of a websocket connection to an exchange.
+/// The numeric value of each state is exposed in the `connection_state` gauge.
+#[derive(Clone, Copy, Debug)]
+pub enum ConnectionState {
+    Connecting = 0,
+    Subscribed = 1,
+    Streaming = 2,
+    BackingOff = 3,
+    Disabled = 4,
+}
+
+impl ConnectionState {
+    /// Make this state the current state of a connection to a particular exchange.
+    fn enter(self, exchange: Exchange, connection_id: usize) {
+        tracing::info!(
+            "connection state changed: {:?}#{}: {:?}",
+            exchange,
+            connection_id,
+            self
+        );
+        METRICS.set("connection_state", exchange, connection_id, self as u64);
+    }
+}
+
+/// This struct computes the delay before reconnecting to the websocket server.
+/// The delay doubles after every failed attempt up to `MAX_BACKOFF`, and a random jitter of
+/// up to half the delay is subtracted so that many clients don't reconnect in lockstep.
+struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Default constructor. The next delay is `INITIAL_BACKOFF`.
+    fn init() -> Self {
+        Self { attempts: 0 }
+    }
+
+    /// Reset the delay to `INITIAL_BACKOFF`, e.g. once a connection is healthy again.
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Get the delay before the next attempt, and increase it for the following attempt.
+    fn next_delay(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempts);
+        let delay = INITIAL_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF);
+        self.attempts = self.attempts.saturating_add(1);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+/// This struct holds the state of a connection that is kept across reconnections. It's shared
+/// by the redundant replicas of the connection, which feed the engine one consistent stream.
+struct FeedState {
+    /// Whether any replica is streaming, which is watched by the REST ticker poller.
+    streaming: watch::Sender<bool>,
+    /// Whether each replica is streaming.
+    replicas: Vec<bool>,
+    sequence_tracker: SequenceTracker,
+    /// The last price, best bid, and best ask of each altcoin, in this order.
+    markets: HashMap<Coin, [f64; 3]>,
+    /// The rolling VWAP of the trades of each altcoin.
+    vwaps: HashMap<Coin, RollingVwap>,
+}
+
+impl FeedState {
+    /// Default constructor. All prices are unknown until they get updated, and no replica is
+    /// streaming yet.
+    fn init(exchange: Exchange, streaming: watch::Sender<bool>, replicas: usize) -> Self {
+        Self {
+            streaming,
+            replicas: vec![false; replicas],
+            sequence_tracker: SequenceTracker::init(exchange),
+            markets: HashMap::new(),
+            vwaps: HashMap::new(),
+        }
+    }
+
+    /// Record whether a replica is streaming, and notify the poller whether any replica is.
+    fn set_streaming(&mut self, replica: usize, streaming: bool) {
+        if let Some(replica) = self.replicas.get_mut(replica) {
+            *replica = streaming;
+        }
+        // The poller can't receive anything if the exchange has no REST ticker, which is fine.
+        let _ = self.streaming.send(self.replicas.contains(&true));
+    }
+
+    /// Get the leading replica, i.e. the first one that streams, which is the only one whose
+    /// messages are handled if they have neither a sequence nor an event time.
+    fn leader(&self) -> Option<usize> {
+        self.replicas.iter().position(|streaming| *streaming)
+    }
+}
+
+/// This struct represents a replica of a connection, i.e. one of its redundant websocket
+/// connections. Its ID labels the connection in the logs, the metrics, and the captures.
+struct Replica {
+    id: usize,
+    index: usize,
+    endpoint: String,
+}
+
+/// This struct represents a connection replayed from a capture. The session of a replica is
+/// created anew whenever the capture records a reconnection, whereas the feed state is kept
+/// across them and shared by the replicas.
+pub struct ReplayedConnection {
+    sessions: HashMap<usize, Session>,
+    state: FeedState,
 }
 
 impl WebSocketPriceFeed {
     /// This function is responsible for feeding the current market price of our index's
-    /// altcoins to the core engine for a particular exchange. It does that by subscribing to the
-    /// exchange's websocket server. If an error occurs, it tries to reconnect after 10 seconds.
-    pub async fn run(mut self, mpsc_tx: Sender<Input>) {
+    /// altcoins to the core engine for a particular exchange. It does that by running all the
+    /// connections to the exchange's websocket server concurrently, each one along with the
+    /// poller that takes over while it's down.
+    ///
+    /// The connections are closed while the exchange is disabled by an operator, and opened
+    /// again once it's enabled.
+    pub async fn run(
+        mut self,
+        config: FeedConfig,
+        mpsc_tx: Sender<Input>,
+        mut disabled: watch::Receiver<DisabledExchanges>,
+    ) {
+        // Point the endpoints at their overridden origins, if any.
+        let endpoints = config.endpoints(self.exchange);
+        for connection in &mut self.connections {
+            connection.endpoint = endpoints.websocket(&connection.endpoint);
+        }
+        if let Some(snapshot) = &mut self.snapshot {
+            snapshot.endpoint = endpoints.rest(&snapshot.endpoint);
+        }
+        if let Some(poll) = &mut self.poll {
+            poll.endpoint = endpoints.rest(&poll.endpoint);
+        }
+
+        // The redundant connections are spread over the mirrors of the websocket server, unless
+        // its endpoint is overridden.
+        if endpoints.websocket.is_some() {
+            self.mirrors.clear();
+        }
+
+        let capture = Capture::new(self.exchange, &config.capture);
         loop {
-            self.subscribe_websocket_endpoint(&mpsc_tx).await;
-            tokio::time::sleep(RECONNECT_INTERVAL).await;
+            if !wait_for_enabled(&mut disabled, self.exchange, true).await {
+                return;
+            }
+            tokio::select! {
+                _ = self.run_connections(&config, &mpsc_tx, capture.as_ref()) => return,
+                true = wait_for_enabled(&mut disabled, self.exchange, false) => {}
+            }
+
+            // The connections and their pollers were dropped, which closed the connections.
+            tracing::warn!("exchange disabled, disconnected: {:?}", self.exchange);
+            let replicas = self.connections.len() * config.redundancy.max(1);
+            for connection_id in 0..replicas {
+                ConnectionState::Disabled.enter(self.exchange, connection_id);
+                METRICS.set("polling", self.exchange, connection_id, 0);
+            }
+        }
+    }
+
+    /// This function is responsible for running all the connections concurrently, along with
+    /// their pollers. Every connection is replicated, and the IDs of its replicas are contiguous.
+    async fn run_connections(
+        &self,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        capture: Option<&Capture>,
+    ) {
+        let redundancy = config.redundancy.max(1);
+        let connections = self.connections.iter().enumerate();
+        let connections = connections.map(|(index, connection)| async move {
+            let (streaming_tx, streaming_rx) = watch::channel(false);
+            let state = Mutex::new(FeedState::init(self.exchange, streaming_tx, redundancy));
+            let replicas = (0..redundancy).map(|replica| {
+                let replica = Replica {
+                    id: index * redundancy + replica,
+                    index: replica,
+                    endpoint: self.replica_endpoint(connection, replica),
+                };
+                self.run_connection(replica, connection, config, mpsc_tx, &state, capture)
+            });
+            futures::future::join(
+                futures::future::join_all(replicas),
+                self.run_poller(
+                    index * redundancy,
+                    connection,
+                    config,
+                    mpsc_tx,
+                    streaming_rx,
+                ),
+            )
+            .await;
+        });
+        futures::future::join_all(connections).await;
+    }
+
+    /// This function gets the websocket endpoint of a replica of a connection. The first replica
+    /// connects to the endpoint itself, and the others to its mirrors in turn, if any.
+    fn replica_endpoint(&self, connection: &Connection, replica: usize) -> String {
+        match replica.checked_sub(1) {
+            Some(mirror) if !self.mirrors.is_empty() => {
+                let mirror = self.mirrors[mirror % self.mirrors.len()];
+                crate::config::rebase(&connection.endpoint, Some(mirror))
+            }
+            _ => connection.endpoint.clone(),
+        }
+    }
+
+    /// This function is responsible for keeping a connection to the exchange's websocket server.
+    /// If an error occurs, it tries to reconnect with an exponential backoff, which is reset once
+    /// the connection streams prices again.
+    ///
+    /// The highest sequence, the prices, and the trades of each product are kept across
+    /// reconnections, and shared with the other replicas of the connection.
+    async fn run_connection(
+        &self,
+        replica: Replica,
+        connection: &Connection,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        state: &Mutex<FeedState>,
+        capture: Option<&Capture>,
+    ) {
+        let connection_id = replica.id;
+        let mut backoff = Backoff::init();
+        loop {
+            ConnectionState::Connecting.enter(self.exchange, connection_id);
+            let streamed = self
+                .subscribe_websocket_endpoint(&replica, connection, config, mpsc_tx, state, capture)
+                .await;
+            state.lock().await.set_streaming(replica.index, false);
+            if streamed {
+                backoff.reset();
+            }
+
+            let delay = backoff.next_delay();
+            ConnectionState::BackingOff.enter(self.exchange, connection_id);
+            METRICS.increment("reconnects_total", self.exchange);
+            tracing::info!(
+                "reconnecting in {:?}: {:?}#{}",
+                delay,
+                self.exchange,
+                connection_id
+            );
+            tokio::time::sleep(delay).await;
         }
     }
 
     /// This function is responsible for subscribing to the exchange's websocket server, consuming
     /// and parsing the ticker messages, and feeding the updated market prices to the core engine.
-    pub async fn subscribe_websocket_endpoint(&mut self, mpsc_tx: &Sender<Input>) {
+    /// Messages that are older than the last message received for the same product are discarded.
+    /// The subscription of every altcoin must be confirmed by the exchange within a timeout.
+    ///
+    /// It returns whether the connection reached the streaming state before being disconnected.
+    async fn subscribe_websocket_endpoint(
+        &self,
+        replica: &Replica,
+        connection: &Connection,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        state: &Mutex<FeedState>,
+        capture: Option<&Capture>,
+    ) -> bool {
         // Connect.
-        let mut websocket_stream = match connect_async(self.endpoint).await {
+        let connection_id = replica.id;
+        let endpoint = replica.endpoint.as_str();
+        let websocket_stream = crate::proxy::connect_websocket(endpoint, config.proxy.as_ref());
+        let mut websocket_stream = match websocket_stream.await {
             Ok((websocket_stream, _)) => websocket_stream,
             Err(error) => {
                 tracing::error!("failed to connect to websocket server: {}", error);
-                return;
+                return false;
             }
         };
         tracing::info!("connected to websocket server: {:?}", self.exchange);
+        if let Some(capture) = capture {
+            let connected = crate::engine::unix_millis().unwrap_or_default();
+            capture.record(connection_id, connected, Frame::Connected);
+        }
 
         // Subscribe.
-        let subscribe_message = Message::Text(self.subscribe.to_string());
-        if let Err(error) = websocket_stream.send(subscribe_message).await {
-            tracing::error!("failed to send subscribe request: {}", error);
+        for subscribe in &connection.subscribe {
+            let subscribe_message = Message::Text(subscribe.to_string());
+            if let Err(error) = websocket_stream.send(subscribe_message).await {
+                tracing::error!("failed to send subscribe request: {}", error);
+                return false;
+            }
         }
+        ConnectionState::Subscribed.enter(self.exchange, connection_id);
+        let mut session = self.session(connection);
+        let mut streaming = false;
+        let mut closed_by_server = false;
+        let mut pending_coins = connection.coins.clone();
+        let subscription_deadline = Instant::now() + config.subscription_timeout();
+        let mut last_received = Instant::now();
+        let mut keepalive_sent = false;
+        let mut scheduled_at: Vec<Instant> = self
+            .scheduled
+            .iter()
+            .map(|scheduled| Instant::now() + scheduled.interval)
+            .collect();
+
+        // Consume and parse websocket messages in a loop, until the connection is closed or
+        // stays idle for too long. In that case, it might be half-open, so we tear it down.
+        // If the exchange expects a keepalive, it's sent whenever the connection is quiet.
+        // The wait for the next message is also cut short when a scheduled message is due, or when
+        // the subscription is due to be confirmed.
+        loop {
+            let idle_remaining = self.idle_timeout.saturating_sub(last_received.elapsed());
+            let mut wait = match &self.keepalive {
+                Some(keepalive) => keepalive.interval.min(idle_remaining),
+                None => idle_remaining,
+            };
+            if let Some(next_scheduled_at) = scheduled_at.iter().min() {
+                wait = wait.min(next_scheduled_at.saturating_duration_since(Instant::now()));
+            }
+            if !pending_coins.is_empty() {
+                wait = wait.min(subscription_deadline.saturating_duration_since(Instant::now()));
+            }
+            let next = tokio::time::timeout(wait, websocket_stream.next()).await;
+
+            // Send the scheduled messages that are due.
+            let mut scheduled_error = None;
+            for (scheduled, at) in self.scheduled.iter().zip(scheduled_at.iter_mut()) {
+                if *at <= Instant::now() {
+                    *at += scheduled.interval;
+                    let scheduled_message = Message::Text(scheduled.message.to_string());
+                    if let Err(error) = websocket_stream.send(scheduled_message).await {
+                        scheduled_error = Some(error);
+                        break;
+                    }
+                }
+            }
+            if let Some(error) = scheduled_error {
+                tracing::error!(
+                    "failed to send scheduled message: {:?}: {}",
+                    self.exchange,
+                    error
+                );
+                break;
+            }
+
+            let message = match next {
+                Ok(Some(Ok(message))) => {
+                    last_received = Instant::now();
+                    keepalive_sent = false;
+                    message
+                }
+                Ok(Some(Err(error))) if closed_by_server => {
+                    // The server closed the connection, so it may end abruptly, which is fine.
+                    tracing::info!("websocket closed: {:?}: {}", self.exchange, error);
+                    break;
+                }
+                Ok(Some(Err(error))) => {
+                    METRICS.increment("websocket_errors_total", self.exchange);
+                    if is_recoverable(&error) {
+                        tracing::warn!("discarded websocket frame: {:?}: {}", self.exchange, error);
+                        continue;
+                    }
+                    tracing::error!("websocket error: {:?}: {}", self.exchange, error);
+                    break;
+                }
+                Ok(None) => break,
+                Err(_) if last_received.elapsed() < self.idle_timeout => {
+                    // The subscription may expire while the connection is quiet.
+                    if self.subscription_expired(&mut pending_coins, subscription_deadline, config)
+                    {
+                        break;
+                    }
+
+                    // The connection is quiet, so send a keepalive if the exchange expects one.
+                    let keepalive = match &self.keepalive {
+                        Some(keepalive) if !keepalive_sent => keepalive,
+                        _ => continue,
+                    };
+                    if last_received.elapsed() >= keepalive.interval {
+                        let keepalive_message = Message::Text(keepalive.message.to_string());
+                        if let Err(error) = websocket_stream.send(keepalive_message).await {
+                            tracing::error!(
+                                "failed to send keepalive: {:?}: {}",
+                                self.exchange,
+                                error
+                            );
+                            break;
+                        }
+                        keepalive_sent = true;
+                    }
+                    continue;
+                }
+                Err(_) => {
+                    tracing::warn!("idle websocket connection: {:?}", self.exchange);
+                    METRICS.increment("idle_timeouts_total", self.exchange);
+                    break;
+                }
+            };
+
+            // Handle control frames. Only text frames and compressed binary frames carry
+            // messages from the exchange, which are timestamped as soon as they are received.
+            let received = crate::engine::unix_millis().unwrap_or_default();
+            if let Some(capture) = capture {
+                match &message {
+                    Message::Text(json) => {
+                        capture.record(connection_id, received, Frame::Text(json.clone()))
+                    }
+                    Message::Binary(frame) => {
+                        capture.record(connection_id, received, Frame::Binary(hex(frame)))
+                    }
+                    _ => {}
+                }
+            }
+            let json = match message {
+                Message::Text(json) => json,
+                Message::Binary(frame) => match decompress(&frame) {
+                    Ok(json) => json,
+                    Err(error) => {
+                        tracing::warn!(
+                            "failed to decompress frame: {:?}: {}",
+                            self.exchange,
+                            error
+                        );
+                        METRICS.increment("decompression_errors_total", self.exchange);
+                        continue;
+                    }
+                },
+                Message::Ping(_) => {
+                    // The pong reply is queued by tungstenite, so flush it right away.
+                    if let Err(error) = websocket_stream.flush().await {
+                        tracing::error!("failed to send pong: {:?}: {}", self.exchange, error);
+                        break;
+                    }
+                    continue;
+                }
+                Message::Close(close_frame) => {
+                    // The close reply is also queued, and the stream ends after it's sent.
+                    tracing::info!(
+                        "received close frame: {:?}: {:?}",
+                        self.exchange,
+                        close_frame
+                    );
+                    closed_by_server = true;
+                    continue;
+                }
+                Message::Pong(_) => {
+                    tracing::debug!(
+                        "ignored websocket frame: {:?}: {:?}",
+                        self.exchange,
+                        message
+                    );
+                    continue;
+                }
+            };
+
+            // Handle the events parsed from the message. Some of them may require to tear down
+            // the connection, which is done once they have all been handled.
+            let mut teardown = false;
+            let mut events = VecDeque::from((self.message_handler)(&mut session, json));
+            while let Some(event) = events.pop_front() {
+                let update = match event {
+                    Event::Ticker(_) | Event::Trade(_) | Event::Quote(_) | Event::Book(..) => {
+                        self.market_update(event, replica.index, &session, config)
+                    }
+                    Event::SnapshotRequired(coin) => {
+                        let capture = capture.map(|capture| (capture, connection_id));
+                        let snapshot = self.fetch_snapshot(coin, &mut session, config, capture);
+                        match snapshot.await {
+                            Some(snapshot_events) => events.extend(snapshot_events),
+                            None => teardown = true,
+                        }
+                        None
+                    }
+                    Event::Heartbeat => {
+                        tracing::debug!("received heartbeat: {:?}", self.exchange);
+                        None
+                    }
+                    Event::Subscribed(coins) => {
+                        pending_coins.retain(|coin| !coins.contains(coin));
+                        if pending_coins.is_empty() {
+                            tracing::info!("subscription confirmed: {:?}", self.exchange);
+                        }
+                        None
+                    }
+                    Event::Rejected(reason) => {
+                        tracing::error!("subscription rejected: {:?}: {}", self.exchange, reason);
+                        teardown |= self.subscription_failed(config);
+                        None
+                    }
+                    Event::Error(error) => {
+                        tracing::error!("exchange error: {:?}: {}", self.exchange, error);
+                        METRICS.increment("exchange_errors_total", self.exchange);
+                        None
+                    }
+                    Event::Reconnect(reason) => {
+                        tracing::info!("reconnection requested: {:?}: {}", self.exchange, reason);
+                        teardown = true;
+                        None
+                    }
+                    Event::Reply(reply) => {
+                        if let Err(error) = websocket_stream.send(Message::Text(reply)).await {
+                            tracing::error!("failed to send reply: {:?}: {}", self.exchange, error);
+                            teardown = true;
+                        }
+                        None
+                    }
+                };
 
-        // Consume and parse websocket messages in a loop.
-        while let Some(Result::Ok(Message::Text(json))) = websocket_stream.next().await {
-            if let Some((coin, mut prices)) = (self.message_handler)(json) {
-                // Find the median and send it to the engine.
-                if prices.iter().all(|price| price.is_finite()) {
-                    // Safe unwrap: our slice doesn't contain a NaN. See this link for reference.
-                    // https://doc.rust-lang.org/std/primitive.slice.html#method.sort_unstable_by
-                    prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-                    let market_price = prices[1];
-                    let input = Input::price(coin, self.exchange, market_price);
-                    if let Err(error) = mpsc_tx.send(input).await {
-                        tracing::error!("failed to send message in mpsc channel: {}", error);
+                // Process the market data update, if any.
+                if let Some(update) = update {
+                    // The first market data message confirms that the connection is healthy.
+                    let mut state = state.lock().await;
+                    if !streaming {
+                        ConnectionState::Streaming.enter(self.exchange, connection_id);
+                        state.set_streaming(replica.index, true);
+                        streaming = true;
                     }
-                } else {
-                    tracing::error!("found non-finite market price: {:?}", prices);
+                    let now = Instant::now();
+                    self.process_market_update(update, received, now, config, mpsc_tx, &mut state)
+                        .await;
                 }
             }
+            if teardown {
+                break;
+            }
+
+            // Check that the subscription was confirmed in time.
+            if self.subscription_expired(&mut pending_coins, subscription_deadline, config) {
+                break;
+            }
         }
 
         tracing::info!("disconnected from websocket server: {:?}", self.exchange);
+        streaming
+    }
+
+    /// This function is responsible for replaying a captured frame through the message handler,
+    /// and for feeding the resulting market prices to the core engine as if they had just been
+    /// received. The VWAP windows follow the given time, which is on the capture's clock.
+    ///
+    /// The captured snapshots are applied to the session when they are replayed, whereas the
+    /// requests, replies, and subscriptions only mattered to the live connection.
+    pub async fn replay(
+        &self,
+        record: Record,
+        time: Instant,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        connections: &mut HashMap<usize, ReplayedConnection>,
+    ) {
+        // The replicas of a connection have contiguous IDs, like on the live feed.
+        let redundancy = config.redundancy.max(1);
+        let (index, replica) = (
+            record.connection / redundancy,
+            record.connection % redundancy,
+        );
+        let connection = match self.connections.get(index) {
+            Some(connection) => connection,
+            None => {
+                tracing::warn!(
+                    "unexpected replayed connection: {:?}#{}",
+                    self.exchange,
+                    record.connection
+                );
+                return;
+            }
+        };
+        let replayed = connections
+            .entry(index)
+            .or_insert_with(|| ReplayedConnection {
+                sessions: HashMap::new(),
+                state: FeedState::init(self.exchange, watch::channel(false).0, redundancy),
+            });
+        let session = replayed
+            .sessions
+            .entry(replica)
+            .or_insert_with(|| self.session(connection));
+
+        // A reconnection means that the replica was down in the meantime.
+        let events = match record.frame {
+            Frame::Connected => {
+                *session = self.session(connection);
+                replayed.state.set_streaming(replica, false);
+                return;
+            }
+            Frame::Text(json) => (self.message_handler)(session, json),
+            Frame::Binary(frame) => match unhex(&frame).map(|frame| decompress(&frame)) {
+                Some(Ok(json)) => (self.message_handler)(session, json),
+                _ => {
+                    tracing::warn!("failed to decode replayed frame: {:?}", self.exchange);
+                    return;
+                }
+            },
+            Frame::Snapshot(coin, response) => match &self.snapshot {
+                Some(snapshot) => (snapshot.handler)(session, coin, response),
+                None => {
+                    tracing::warn!("unexpected replayed snapshot: {:?}", self.exchange);
+                    return;
+                }
+            },
+            Frame::Supply(_) => {
+                tracing::warn!("unexpected replayed supply: {:?}", self.exchange);
+                return;
+            }
+        };
+        for event in events {
+            let update = match event {
+                Event::Ticker(_) | Event::Trade(_) | Event::Quote(_) | Event::Book(..) => {
+                    self.market_update(event, replica, session, config)
+                }
+                _ => None,
+            };
+            if let Some(update) = update {
+                let state = &mut replayed.state;
+                if state.replicas.get(replica) == Some(&false) {
+                    state.set_streaming(replica, true);
+                }
+                self.process_market_update(update, record.received, time, config, mpsc_tx, state)
+                    .await;
+            }
+        }
+    }
+
+    /// This function creates the session of a new connection.
+    fn session(&self, connection: &Connection) -> Session {
+        Session {
+            coins: connection.coins.clone(),
+            symbols: self.symbols.clone(),
+            ..Session::default()
+        }
+    }
+
+    /// This function extracts the market data update carried by an event that was delivered by
+    /// the given replica of a connection, if any.
+    fn market_update(
+        &self,
+        event: Event,
+        replica: usize,
+        session: &Session,
+        config: &FeedConfig,
+    ) -> Option<Update> {
+        match event {
+            Event::Ticker(ticker) => {
+                let [last_price, best_bid, best_ask] = ticker.prices;
+                Some(Update {
+                    coin: ticker.coin,
+                    channel: Channel::Ticker,
+                    prices: [Some(last_price), Some(best_bid), Some(best_ask)],
+                    sequence: ticker.sequence,
+                    time: ticker.time,
+                    traded: None,
+                    replica,
+                })
+            }
+            Event::Trade(trade) => Some(Update {
+                coin: trade.coin,
+                channel: Channel::Trade,
+                prices: [Some(trade.price), None, None],
+                sequence: trade.sequence,
+                time: trade.time,
+                traded: trade.quantity.map(|quantity| (trade.price, quantity)),
+                replica,
+            }),
+            Event::Quote(quote) => Some(Update {
+                coin: quote.coin,
+                channel: Channel::Quote,
+                prices: [None, quote.best_bid, quote.best_ask],
+                sequence: quote.sequence,
+                time: quote.time,
+                traded: None,
+                replica,
+            }),
+            Event::Book(coin, time) => {
+                // The depth-weighted mid is the market price of an exchange whose order book is
+                // maintained, so it stands for the last price, best bid, and best ask. The book
+                // is already ordered by the message handler.
+                let book = session.books.get(&coin);
+                match book.and_then(|book| book.depth_weighted_mid(config.book_notional)) {
+                    Some(mid) => Some(Update {
+                        coin,
+                        channel: Channel::Book,
+                        prices: [Some(mid); 3],
+                        sequence: None,
+                        time,
+                        traded: None,
+                        replica,
+                    }),
+                    None => {
+                        tracing::debug!("not enough depth: {:?}: {:?}", self.exchange, coin);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// This function is responsible for feeding a market data update received at the given
+    /// time to the core engine, unless it was delivered out of order, or already delivered by
+    /// another replica of the connection. The updates without a sequence are ordered by their
+    /// event time if the connection is redundant, and are otherwise only taken from the leading
+    /// replica.
+    async fn process_market_update(
+        &self,
+        update: Update,
+        received: u64,
+        time: Instant,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        state: &mut FeedState,
+    ) {
+        // Discard the message if it was delivered out of order, or is a duplicate.
+        let redundant = state.replicas.len() > 1;
+        let sequence = match update.sequence {
+            None if redundant => update.time.map(Sequence::Time),
+            sequence => sequence,
+        };
+        let is_newer = match sequence {
+            Some(sequence) => state.sequence_tracker.is_newer(
+                update.coin,
+                update.channel,
+                sequence,
+                update.replica,
+                redundant,
+            ),
+            None => state.leader() == Some(update.replica),
+        };
+        if !is_newer {
+            return;
+        }
+        let timestamps = Timestamps {
+            event: update.time,
+            received,
+        };
+        self.process_update(update.coin, update.prices, timestamps, mpsc_tx, state)
+            .await;
+        match update.traded {
+            Some(traded) => {
+                self.process_trade(update.coin, traded, time, config, mpsc_tx, state)
+                    .await
+            }
+            None => {
+                self.expire_vwap(update.coin, time, mpsc_tx, state).await;
+            }
+        }
+    }
+
+    /// This function is responsible for fetching a snapshot of the order book of an altcoin, and
+    /// for applying it to the session. It returns the resulting events, or `None` if it failed.
+    ///
+    /// The messages received in the meantime are left in the websocket stream.
+    async fn fetch_snapshot(
+        &self,
+        coin: Coin,
+        session: &mut Session,
+        config: &FeedConfig,
+        capture: Option<(&Capture, usize)>,
+    ) -> Option<Vec<Event>> {
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => {
+                tracing::error!("unexpected snapshot request: {:?}", self.exchange);
+                return None;
+            }
+        };
+
+        let symbol = match self.symbols.symbol(coin) {
+            Some(symbol) => symbol,
+            None => {
+                tracing::error!("unexpected snapshot coin: {:?}: {:?}", self.exchange, coin);
+                return None;
+            }
+        };
+
+        // Send a GET request and download the response body.
+        let endpoint = format!("{}{}", snapshot.endpoint, (snapshot.path)(symbol));
+        tracing::info!("fetching book snapshot: {:?}: {:?}", self.exchange, coin);
+        let builder = reqwest::Client::builder();
+        let response = match crate::proxy::client(builder, config.proxy.as_ref()) {
+            Ok(client) => match client.get(&endpoint).send().await {
+                Ok(response) => response.text().await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+        match response {
+            Ok(response) => {
+                if let Some((capture, connection_id)) = capture {
+                    let received = crate::engine::unix_millis().unwrap_or_default();
+                    let frame = Frame::Snapshot(coin, response.clone());
+                    capture.record(connection_id, received, frame);
+                }
+                Some((snapshot.handler)(session, coin, response))
+            }
+            Err(error) => {
+                tracing::error!(
+                    "failed to fetch book snapshot: {:?}: {}",
+                    self.exchange,
+                    error
+                );
+                METRICS.increment("snapshot_failures_total", self.exchange);
+                None
+            }
+        }
+    }
+
+    /// This function is responsible for polling the REST tickers of the altcoins of a connection
+    /// while it's down, i.e. once it hasn't been streaming for `poll_fallback`. The polling stops
+    /// as soon as the connection streams again.
+    async fn run_poller(
+        &self,
+        connection_id: usize,
+        connection: &Connection,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        mut streaming: watch::Receiver<bool>,
+    ) {
+        let poll = match &self.poll {
+            Some(poll) => poll,
+            None => return,
+        };
+        loop {
+            // Wait until the connection has been down for long enough.
+            if !wait_for_streaming(&mut streaming, false).await {
+                return;
+            }
+            let resumed = wait_for_streaming(&mut streaming, true);
+            if tokio::time::timeout(config.poll_fallback(), resumed)
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+
+            // Poll until the connection streams again.
+            tracing::warn!(
+                "websocket down, polling rest tickers: {:?}#{}",
+                self.exchange,
+                connection_id
+            );
+            METRICS.set("polling", self.exchange, connection_id, 1);
+            while !*streaming.borrow() {
+                for coin in &connection.coins {
+                    self.poll_ticker(poll, *coin, config, mpsc_tx).await;
+                }
+                let _ = tokio::time::timeout(config.poll_interval(), streaming.changed()).await;
+            }
+            tracing::info!(
+                "websocket streaming, stopped polling: {:?}#{}",
+                self.exchange,
+                connection_id
+            );
+            METRICS.set("polling", self.exchange, connection_id, 0);
+        }
+    }
+
+    /// This function is responsible for polling the REST ticker of an altcoin once, and for
+    /// feeding the market price to the core engine.
+    async fn poll_ticker(
+        &self,
+        poll: &Poll,
+        coin: Coin,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+    ) {
+        let symbol = match self.symbols.symbol(coin) {
+            Some(symbol) => symbol,
+            None => {
+                tracing::error!("unexpected poll coin: {:?}: {:?}", self.exchange, coin);
+                return;
+            }
+        };
+
+        // Send a GET request and download the response body.
+        let url = format!("{}{}", poll.endpoint, (poll.path)(symbol));
+        let builder = reqwest::Client::builder().user_agent(USER_AGENT);
+        let response = match crate::proxy::client(builder, config.proxy.as_ref()) {
+            Ok(client) => match client.get(&url).send().await {
+                Ok(response) => response.text().await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                tracing::error!("failed to poll rest ticker: {:?}: {}", self.exchange, error);
+                METRICS.increment("poll_failures_total", self.exchange);
+                return;
+            }
+        };
+
+        // Find the median and send it to the engine.
+        let mut prices = match (poll.handler)(&response) {
+            Some(prices) if prices.iter().all(|price| price.is_finite()) => prices,
+            _ => {
+                tracing::warn!("discarded rest ticker: {:?}: {}", self.exchange, response);
+                METRICS.increment("poll_failures_total", self.exchange);
+                return;
+            }
+        };
+        // Safe unwrap: our slice doesn't contain a NaN.
+        prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let timestamps = Timestamps::received_now(None);
+        let input = Input::price(coin, self.exchange, prices[1], Source::Poll, timestamps);
+        if let Err(error) = mpsc_tx.send(input).await {
+            tracing::error!("failed to send message in mpsc channel: {}", error);
+        }
+    }
+
+    /// This function is responsible for recording a failed subscription, and returns whether the
+    /// connection should be torn down in order to subscribe again.
+    fn subscription_failed(&self, config: &FeedConfig) -> bool {
+        METRICS.increment("subscription_failures_total", self.exchange);
+        match config.subscription_failure {
+            SubscriptionFailure::Retry => true,
+            SubscriptionFailure::Alert => false,
+        }
+    }
+
+    /// This function is responsible for checking that the subscription of the pending altcoins
+    /// was confirmed before the deadline. If it wasn't, they are no longer pending, and it
+    /// returns whether the connection should be torn down in order to subscribe again.
+    fn subscription_expired(
+        &self,
+        pending_coins: &mut Vec<Coin>,
+        deadline: Instant,
+        config: &FeedConfig,
+    ) -> bool {
+        if pending_coins.is_empty() || Instant::now() < deadline {
+            return false;
+        }
+        tracing::error!(
+            "subscription not confirmed: {:?}: {:?}",
+            self.exchange,
+            pending_coins
+        );
+        pending_coins.clear();
+        self.subscription_failed(config)
+    }
+
+    /// This function is responsible for feeding the updated market price to the core engine.
+    ///
+    /// The prices that aren't carried by the update keep their last known value. The market
+    /// price is only known once the last price, best bid, and best ask have all been received.
+    async fn process_update(
+        &self,
+        coin: Coin,
+        prices: [Option<f64>; 3],
+        timestamps: Timestamps,
+        mpsc_tx: &Sender<Input>,
+        state: &mut FeedState,
+    ) {
+        // Update the known prices of this market.
+        let market = state.markets.entry(coin).or_insert([f64::NAN; 3]);
+        for (known_price, price) in market.iter_mut().zip(prices) {
+            if let Some(price) = price {
+                *known_price = price;
+            }
+        }
+
+        // Find the median and send it to the engine.
+        let mut prices = *market;
+        if prices.iter().all(|price| price.is_finite()) {
+            // Safe unwrap: our slice doesn't contain a NaN. See this link for reference.
+            // https://doc.rust-lang.org/std/primitive.slice.html#method.sort_unstable_by
+            prices.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            let market_price = prices[1];
+            let input = Input::price(
+                coin,
+                self.exchange,
+                market_price,
+                Source::Stream,
+                timestamps,
+            );
+            if let Err(error) = mpsc_tx.send(input).await {
+                tracing::error!("failed to send message in mpsc channel: {}", error);
+            }
+        }
+    }
+
+    /// This function is responsible for recording a trade received at the given time in the
+    /// rolling VWAP of its altcoin, and for feeding the updated VWAP to the core engine. The engine
+    /// decides whether it replaces the market price.
+    async fn process_trade(
+        &self,
+        coin: Coin,
+        (price, quantity): (f64, f64),
+        time: Instant,
+        config: &FeedConfig,
+        mpsc_tx: &Sender<Input>,
+        state: &mut FeedState,
+    ) {
+        let vwap = state
+            .vwaps
+            .entry(coin)
+            .or_insert_with(|| RollingVwap::new(config.vwap_window()));
+        vwap.record(time, price, quantity);
+        if let Some(vwap) = self.expire_vwap(coin, time, mpsc_tx, state).await {
+            let input = Input::vwap(coin, self.exchange, vwap);
+            if let Err(error) = mpsc_tx.send(input).await {
+                tracing::error!("failed to send message in mpsc channel: {}", error);
+            }
+        }
+    }
+
+    /// This function is responsible for expiring the trades of an altcoin that fell out of the
+    /// VWAP window at the given time, and returns the VWAP of the remaining ones. Once the window
+    /// is empty, the VWAP is reset to NaN in the core engine, which falls back to the market price
+    /// until the next trade.
+    async fn expire_vwap(
+        &self,
+        coin: Coin,
+        time: Instant,
+        mpsc_tx: &Sender<Input>,
+        state: &mut FeedState,
+    ) -> Option<f64> {
+        let vwap = state.vwaps.get_mut(&coin)?.vwap(time);
+        if vwap.is_none() {
+            state.vwaps.remove(&coin);
+            let input = Input::vwap(coin, self.exchange, f64::NAN);
+            if let Err(error) = mpsc_tx.send(input).await {
+                tracing::error!("failed to send message in mpsc channel: {}", error);
+            }
+        }
+        vwap
+    }
+}
+
+/// This function is a helper to wait until a connection is streaming or not, as given.
+/// It returns `false` if the connection is gone.
+async fn wait_for_streaming(streaming: &mut watch::Receiver<bool>, expected: bool) -> bool {
+    while *streaming.borrow() != expected {
+        if streaming.changed().await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// This function is a helper to wait until an exchange is enabled or not, as given.
+/// It returns `false` if the exchanges can't be enabled or disabled anymore.
+async fn wait_for_enabled(
+    disabled: &mut watch::Receiver<DisabledExchanges>,
+    exchange: Exchange,
+    expected: bool,
+) -> bool {
+    while disabled.borrow().contains(&exchange) == expected {
+        if disabled.changed().await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// This function is a helper to decide whether the connection can still be used after an error.
+/// An invalid UTF-8 text frame is discarded, but any other error means the connection is broken,
+/// either because it was closed, because of a network failure, or because of a protocol violation.
+fn is_recoverable(error: &WebSocketError) -> bool {
+    matches!(error, WebSocketError::Utf8)
+}
+
+/// This function is a helper to decompress a binary frame into a text message. Gzip frames are
+/// recognized by their magic number, and any other frame is assumed to be raw deflate.
+fn decompress(frame: &[u8]) -> std::io::Result<String> {
+    let mut message = String::new();
+    if frame.starts_with(&GZIP_MAGIC_NUMBER) {
+        GzDecoder::new(frame).read_to_string(&mut message)?;
+    } else {
+        DeflateDecoder::new(frame).read_to_string(&mut message)?;
     }
+    Ok(message)
 }
 
 /// This function is a helper to parse an f64 from a string slice.
@@ -82,3 +1406,517 @@ pub fn str_to_f64(string: &str) -> Option<f64> {
         }
     }
 }
+
+/// This function is a helper to parse an RFC 3339 time in UTC, e.g. `2023-01-16T07:22:26.123456Z`,
+/// as Unix time in milliseconds. The digits past the milliseconds are truncated.
+pub fn rfc3339_to_millis(time: &str) -> Option<u64> {
+    let parsed = time.strip_suffix('Z').and_then(|time| {
+        let (date, time) = time.split_once('T')?;
+        let mut date = date.splitn(3, '-').map(str::parse::<u64>);
+        let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+        let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+        let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+        let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+        if !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+        let millis = fraction
+            .bytes()
+            .chain(std::iter::repeat(b'0'))
+            .take(3)
+            .fold(0, |millis, digit| millis * 10 + u64::from(digit - b'0'));
+        if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day)
+        {
+            return None;
+        }
+        // A leap second is allowed, and counted as the next second.
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+
+        // Count the days since the epoch, with the years starting in March so that the leap day
+        // comes last. See this link for reference:
+        // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+        let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+        Some(seconds * 1000 + millis)
+    });
+    if parsed.is_none() {
+        tracing::error!("failed to parse time: {}", time);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::Instruments;
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+    /// This function is a helper to serve the same response body to every HTTP request on a
+    /// local port, as a stand-in for the REST endpoint of an exchange. It returns its URL.
+    async fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0; 1024];
+                let _ = stream.read(&mut buffer).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    /// This function is a helper to build a feed of ETH whose websocket server is unreachable,
+    /// and whose REST ticker is served at the given endpoint as a list of 3 prices.
+    fn feed(endpoint: String) -> WebSocketPriceFeed {
+        WebSocketPriceFeed {
+            exchange: Exchange::Kraken,
+            symbols: Instruments::default().symbols(Exchange::Kraken),
+            connections: vec![Connection {
+                endpoint: "ws://127.0.0.1:1".to_string(),
+                subscribe: Vec::new(),
+                coins: vec![Coin::ETH],
+            }],
+            idle_timeout: Duration::from_secs(10),
+            keepalive: None,
+            scheduled: Vec::new(),
+            snapshot: None,
+            poll: Some(Poll {
+                endpoint,
+                path: |symbol| symbol.to_string(),
+                handler: |response| serde_json::from_str(response).ok(),
+            }),
+            mirrors: Vec::new(),
+            message_handler: |_, _| Vec::new(),
+        }
+    }
+
+    /// This function is a helper to check that an input is the polled market price of ETH.
+    fn is_polled_price(input: Option<Input>) -> bool {
+        matches!(
+            input,
+            Some(Input::Price(Coin::ETH, Exchange::Kraken, price, Source::Poll, _)) if price == 3000.2
+        )
+    }
+
+    #[tokio::test]
+    async fn poll_while_disconnected() {
+        let feed = feed(serve("[3000.2,3000.1,3000.3]").await);
+        let config = FeedConfig {
+            poll_fallback_secs: 0,
+            ..FeedConfig::default()
+        };
+        let (mpsc_tx, mut mpsc_rx) = mpsc::channel(10);
+        let (_, disabled_rx) = watch::channel(DisabledExchanges::new());
+        tokio::spawn(feed.run(config, mpsc_tx, disabled_rx));
+        let input = timeout(Duration::from_secs(5), mpsc_rx.recv()).await;
+        assert!(is_polled_price(input.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn disconnect_while_disabled() {
+        // This websocket server reports when a client connects and disconnects.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let events_tx = events_tx.clone();
+                tokio::spawn(async move {
+                    let mut websocket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let _ = events_tx.send("connected");
+                    while let Some(Ok(_)) = websocket.next().await {}
+                    let _ = events_tx.send("disconnected");
+                });
+            }
+        });
+
+        let mut feed = feed("http://127.0.0.1:1".to_string());
+        feed.connections[0].endpoint = format!("ws://{}", addr);
+        feed.poll = None;
+        let (mpsc_tx, _mpsc_rx) = mpsc::channel(10);
+        let (disabled_tx, disabled_rx) = watch::channel(DisabledExchanges::new());
+        tokio::spawn(feed.run(FeedConfig::default(), mpsc_tx, disabled_rx));
+        let event = timeout(Duration::from_secs(5), events_rx.recv()).await;
+        assert_eq!(event.unwrap(), Some("connected"));
+
+        // The connection is closed once the exchange is disabled, and isn't opened again until
+        // it's enabled.
+        disabled_tx
+            .send(DisabledExchanges::from([Exchange::Kraken]))
+            .unwrap();
+        let event = timeout(Duration::from_secs(5), events_rx.recv()).await;
+        assert_eq!(event.unwrap(), Some("disconnected"));
+        assert!(timeout(Duration::from_millis(200), events_rx.recv())
+            .await
+            .is_err());
+        disabled_tx.send(DisabledExchanges::new()).unwrap();
+        let event = timeout(Duration::from_secs(5), events_rx.recv()).await;
+        assert_eq!(event.unwrap(), Some("connected"));
+    }
+
+    #[tokio::test]
+    async fn ping_and_close_frames() {
+        // This websocket server pings the client, and then closes the connection. It reports
+        // the frames sent back by the client.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (frames_tx, mut frames_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut websocket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            websocket.send(Message::Ping(b"1".to_vec())).await.unwrap();
+            let _ = frames_tx.send(websocket.next().await);
+            let close_frame = CloseFrame {
+                code: CloseCode::Away,
+                reason: "maintenance".into(),
+            };
+            websocket.close(Some(close_frame)).await.unwrap();
+            let _ = frames_tx.send(websocket.next().await);
+        });
+
+        let feed = feed(String::new());
+        let replica = Replica {
+            id: 0,
+            index: 0,
+            endpoint: format!("ws://{}", addr),
+        };
+        let config = FeedConfig::default();
+        let (mpsc_tx, _mpsc_rx) = mpsc::channel(10);
+        let state = Mutex::new(FeedState::init(
+            Exchange::Kraken,
+            watch::channel(false).0,
+            1,
+        ));
+        let connection = &feed.connections[0];
+        let subscribe = feed
+            .subscribe_websocket_endpoint(&replica, connection, &config, &mpsc_tx, &state, None);
+
+        // The ping is answered and the connection is kept, until the server closes it.
+        let streaming = timeout(Duration::from_secs(5), subscribe).await;
+        assert!(!streaming.unwrap());
+        let pong = frames_rx.recv().await.unwrap();
+        assert!(matches!(pong, Some(Ok(Message::Pong(payload))) if payload == b"1"));
+        let close = frames_rx.recv().await.unwrap();
+        assert!(matches!(close, Some(Ok(Message::Close(_)))));
+    }
+
+    #[test]
+    fn recoverable_errors() {
+        assert!(is_recoverable(&WebSocketError::Utf8));
+        assert!(!is_recoverable(&WebSocketError::ConnectionClosed));
+        assert!(!is_recoverable(&WebSocketError::AlreadyClosed));
+        let error = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert!(!is_recoverable(&WebSocketError::Io(error)));
+    }
+
+    #[tokio::test]
+    async fn subscription_not_confirmed() {
+        // This websocket server never confirms the subscription, but reports when a client
+        // disconnects. The connection only stays up with keepalives.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut websocket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(_)) = websocket.next().await {}
+            let _ = events_tx.send("disconnected");
+        });
+
+        let mut feed = feed("http://127.0.0.1:1".to_string());
+        feed.connections[0].endpoint = format!("ws://{}", addr);
+        feed.keepalive = Some(Keepalive {
+            interval: Duration::from_millis(200),
+            message: "ping",
+        });
+        feed.poll = None;
+        let config = FeedConfig {
+            subscription_timeout_secs: 1,
+            ..FeedConfig::default()
+        };
+        let (mpsc_tx, _mpsc_rx) = mpsc::channel(10);
+        let (_disabled_tx, disabled_rx) = watch::channel(DisabledExchanges::new());
+        tokio::spawn(feed.run(config, mpsc_tx, disabled_rx));
+
+        // The connection is torn down once the subscription is due, well before it's idle.
+        let event = timeout(Duration::from_secs(5), events_rx.recv()).await;
+        assert_eq!(event.unwrap(), Some("disconnected"));
+    }
+
+    #[tokio::test]
+    async fn stop_polling_once_streaming() {
+        let feed = feed(serve("[3000.2,3000.1,3000.3]").await);
+        let config = FeedConfig {
+            poll_fallback_secs: 0,
+            poll_interval_secs: 1,
+            ..FeedConfig::default()
+        };
+        let (mpsc_tx, mut mpsc_rx) = mpsc::channel(10);
+        let (streaming_tx, streaming_rx) = watch::channel(true);
+        let poller = feed.run_poller(0, &feed.connections[0], &config, &mpsc_tx, streaming_rx);
+        let test = async {
+            // Nothing is polled while streaming.
+            let input = timeout(Duration::from_millis(200), mpsc_rx.recv()).await;
+            assert!(input.is_err());
+
+            // The ticker is polled once the connection is down.
+            streaming_tx.send(false).unwrap();
+            let input = timeout(Duration::from_secs(5), mpsc_rx.recv()).await;
+            assert!(is_polled_price(input.unwrap()));
+
+            // The polling stops as soon as the connection streams again.
+            streaming_tx.send(true).unwrap();
+            let input = timeout(Duration::from_millis(1500), mpsc_rx.recv()).await;
+            assert!(input.is_err());
+        };
+        tokio::select! {
+            _ = poller => panic!("unexpected end of the poller"),
+            _ = test => {}
+        }
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let mut backoff = Backoff::init();
+
+        // The delay doubles after every attempt until it's capped, minus up to half of it.
+        let mut expected = INITIAL_BACKOFF;
+        for _ in 0..12 {
+            let delay = backoff.next_delay();
+            assert!(delay >= expected / 2 && delay <= expected);
+            expected = (expected * 2).min(MAX_BACKOFF);
+        }
+        assert_eq!(expected, MAX_BACKOFF);
+        let delay = backoff.next_delay();
+        assert!(delay >= MAX_BACKOFF / 2 && delay <= MAX_BACKOFF);
+
+        // The delay starts over once a connection is healthy again.
+        backoff.reset();
+        let delay = backoff.next_delay();
+        assert!(delay >= INITIAL_BACKOFF / 2 && delay <= INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn discard_stale_messages() {
+        // Gemini's messages are ordered by event time, so its counters are only used here.
+        let exchange = Exchange::Gemini;
+        let mut tracker = SequenceTracker::init(exchange);
+        let eth = Coin::ETH;
+        let gaps = METRICS.counter("sequence_gaps_total", exchange);
+        let reorders = METRICS.counter("sequence_reorders_total", exchange);
+
+        // The first message of a channel is always newer, and so is the next number.
+        assert!(tracker.is_newer(eth, Channel::Trade, Sequence::Number(10), 0, false));
+        assert!(tracker.is_newer(eth, Channel::Trade, Sequence::Number(11), 0, false));
+        assert_eq!(METRICS.counter("sequence_gaps_total", exchange), gaps);
+
+        // A gap is counted, but the message is still handled.
+        assert!(tracker.is_newer(eth, Channel::Trade, Sequence::Number(14), 0, false));
+        assert_eq!(METRICS.counter("sequence_gaps_total", exchange), gaps + 1);
+
+        // A stale message and a duplicate are discarded, and counted as reorderings.
+        assert!(!tracker.is_newer(eth, Channel::Trade, Sequence::Number(12), 0, false));
+        assert!(!tracker.is_newer(eth, Channel::Trade, Sequence::Number(14), 0, false));
+        assert_eq!(
+            METRICS.counter("sequence_reorders_total", exchange),
+            reorders + 2
+        );
+
+        // The messages that share an event time are all handled, but not the older ones.
+        assert!(tracker.is_newer(eth, Channel::Quote, Sequence::Time(100), 0, false));
+        assert!(tracker.is_newer(eth, Channel::Quote, Sequence::Time(100), 0, false));
+        assert!(!tracker.is_newer(eth, Channel::Quote, Sequence::Time(99), 0, false));
+        assert_eq!(
+            METRICS.counter("sequence_reorders_total", exchange),
+            reorders + 3
+        );
+
+        // Each channel has its own sequence.
+        assert!(tracker.is_newer(eth, Channel::Ticker, Sequence::Number(1), 0, false));
+        assert_eq!(METRICS.counter("sequence_gaps_total", exchange), gaps + 1);
+
+        // The update IDs skip the updates that weren't pushed, which aren't gaps.
+        let sol = Coin::SOL;
+        assert!(tracker.is_newer(sol, Channel::Quote, Sequence::UpdateId(400900217), 0, false));
+        assert!(tracker.is_newer(sol, Channel::Quote, Sequence::UpdateId(400900230), 0, false));
+        assert!(!tracker.is_newer(sol, Channel::Quote, Sequence::UpdateId(400900220), 0, false));
+        assert_eq!(METRICS.counter("sequence_gaps_total", exchange), gaps + 1);
+        assert_eq!(
+            METRICS.counter("sequence_reorders_total", exchange),
+            reorders + 4
+        );
+    }
+
+    #[test]
+    fn deduplicate_replicas() {
+        let mut tracker = SequenceTracker::init(Exchange::Binance);
+        let (eth, sol) = (Coin::ETH, Coin::SOL);
+
+        // A numbered message is handled once, whichever replica delivers it first.
+        assert!(tracker.is_newer(eth, Channel::Trade, Sequence::Number(1), 0, true));
+        assert!(!tracker.is_newer(eth, Channel::Trade, Sequence::Number(1), 1, true));
+        assert!(tracker.is_newer(eth, Channel::Trade, Sequence::Number(2), 1, true));
+        assert!(!tracker.is_newer(eth, Channel::Trade, Sequence::Number(2), 0, true));
+
+        // The messages that share an event time are taken from the replica that delivered it
+        // first, and all of them are taken without redundancy.
+        assert!(tracker.is_newer(eth, Channel::Quote, Sequence::Time(100), 1, true));
+        assert!(tracker.is_newer(eth, Channel::Quote, Sequence::Time(100), 1, true));
+        assert!(!tracker.is_newer(eth, Channel::Quote, Sequence::Time(100), 0, true));
+        assert!(tracker.is_newer(eth, Channel::Quote, Sequence::Time(101), 0, true));
+        assert!(tracker.is_newer(sol, Channel::Quote, Sequence::Time(100), 0, false));
+        assert!(tracker.is_newer(sol, Channel::Quote, Sequence::Time(100), 1, false));
+    }
+
+    #[tokio::test]
+    async fn failover_between_replicas() {
+        let feed = feed(String::new());
+        let config = FeedConfig::default();
+        let (mpsc_tx, mut mpsc_rx) = mpsc::channel(10);
+        let mut state = FeedState::init(Exchange::Kraken, watch::channel(false).0, 2);
+        state.set_streaming(1, true);
+        state.set_streaming(0, true);
+
+        // The updates without a sequence or an event time are only taken from the leader, i.e.
+        // the first replica that streams, until it's down.
+        let update = |price, replica| Update {
+            coin: Coin::ETH,
+            channel: Channel::Ticker,
+            prices: [Some(price); 3],
+            sequence: None,
+            time: None,
+            traded: None,
+            replica,
+        };
+        for (price, replica) in [(3000.1, 1), (3000.2, 0)] {
+            let update = update(price, replica);
+            feed.process_market_update(update, 0, Instant::now(), &config, &mpsc_tx, &mut state)
+                .await;
+        }
+        state.set_streaming(0, false);
+        for (price, replica) in [(3000.3, 0), (3000.4, 1)] {
+            let update = update(price, replica);
+            feed.process_market_update(update, 0, Instant::now(), &config, &mpsc_tx, &mut state)
+                .await;
+        }
+        drop(mpsc_tx);
+
+        let mut prices = Vec::new();
+        while let Some(Input::Price(_, _, price, _, _)) = mpsc_rx.recv().await {
+            prices.push(price);
+        }
+        assert_eq!(prices, vec![3000.2, 3000.4]);
+    }
+
+    #[tokio::test]
+    async fn expire_vwap_without_trades() {
+        let feed = feed(String::new());
+        let config = FeedConfig::default();
+        let (mpsc_tx, mut mpsc_rx) = mpsc::channel(10);
+        let mut state = FeedState::init(Exchange::Kraken, watch::channel(false).0, 1);
+        state.set_streaming(0, true);
+        let update = |traded| Update {
+            coin: Coin::ETH,
+            channel: Channel::Ticker,
+            prices: [Some(3000.2); 3],
+            sequence: None,
+            time: None,
+            traded,
+            replica: 0,
+        };
+
+        // A trade sets the VWAP, which is reset once its window has passed without any other
+        // trade. It's only reset once.
+        let start = Instant::now();
+        let times = [
+            start,
+            start + config.vwap_window() + Duration::from_secs(1),
+            start + config.vwap_window() + Duration::from_secs(2),
+        ];
+        let updates = [update(Some((3000.1, 0.5))), update(None), update(None)];
+        for (update, time) in updates.into_iter().zip(times) {
+            feed.process_market_update(update, 0, time, &config, &mpsc_tx, &mut state)
+                .await;
+        }
+        drop(mpsc_tx);
+
+        let mut vwaps = Vec::new();
+        while let Some(input) = mpsc_rx.recv().await {
+            if let Input::Vwap(_, _, vwap) = input {
+                vwaps.push(vwap);
+            }
+        }
+        assert_eq!(vwaps.len(), 2);
+        assert_eq!(vwaps[0], 3000.1);
+        assert!(vwaps[1].is_nan());
+    }
+
+    #[test]
+    fn decompress_frames() {
+        let message = r#"{"ping":1492420473027}"#;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(message.as_bytes()).unwrap();
+        let frame = encoder.finish().unwrap();
+        assert_eq!(decompress(&frame).unwrap(), message);
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(message.as_bytes()).unwrap();
+        let frame = encoder.finish().unwrap();
+        assert_eq!(decompress(&frame).unwrap(), message);
+
+        assert!(decompress(message.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_rfc3339() {
+        assert_eq!(rfc3339_to_millis("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            rfc3339_to_millis("2023-01-16T07:22:26.123456Z"),
+            Some(1673853746123)
+        );
+        assert_eq!(
+            rfc3339_to_millis("2024-02-29T12:00:00.5Z"),
+            Some(1709208000500)
+        );
+        assert_eq!(rfc3339_to_millis("2023-01-16T07:22:26+01:00"), None);
+        assert_eq!(rfc3339_to_millis("2023-13-16T07:22:26Z"), None);
+
+        // Remote input may not be well-formed, and must not panic or overflow.
+        assert_eq!(rfc3339_to_millis("2023-01-16T07:22:26.12éZ"), None);
+        assert_eq!(rfc3339_to_millis("2023-01-16T07:22:26.éZ"), None);
+        assert_eq!(rfc3339_to_millis("2023-01-16T24:00:00Z"), None);
+        assert_eq!(rfc3339_to_millis("2023-01-16T07:60:00Z"), None);
+        assert_eq!(rfc3339_to_millis("2023-01-16T07:22:61Z"), None);
+        assert_eq!(
+            rfc3339_to_millis("2023-01-16T99999999999999999:00:00Z"),
+            None
+        );
+        assert_eq!(
+            rfc3339_to_millis("2016-12-31T23:59:60Z"),
+            Some(1483228800000)
+        );
+    }
+}
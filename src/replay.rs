@@ -0,0 +1,289 @@
+use crate::capture::{Frame, Record, SUPPLY};
+use crate::config::{Config, ReplaySpeed};
+use crate::engine::Input;
+use crate::price::WebSocketPriceFeed;
+use crate::{binance, bitfinex, bitstamp, bybit, coinbase, gemini, htx, kraken, okx, supply};
+use altusd::Exchange;
+use flate2::read::GzDecoder;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+/// This enum represents where the records of a capture file come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Origin {
+    Exchange(Exchange),
+    Supply,
+}
+
+/// This function is responsible for feeding the captured frames and supply responses to the core
+/// engine instead of the live feeds. The frames go through the message handlers of the real
+/// feeds, which are configured by the `[feeds]` config, e.g. the Kraken API must be the one that
+/// was captured, and so must the supply provider. The records of all the capture files are merged
+/// by receive time, and replayed at the configured speed.
+///
+/// The receive times of the market prices are the captured ones, and so is the clock of the VWAP
+/// windows, whatever the speed.
+pub async fn run(config: Config, mpsc_tx: Sender<Input>) {
+    let directory = match &config.replay.directory {
+        Some(directory) => directory.clone(),
+        None => return,
+    };
+
+    // The capture files are read and decompressed on a blocking thread.
+    let (records_tx, mut records_rx) = tokio::sync::mpsc::channel(10_000);
+    tokio::task::spawn_blocking(move || read_captures(&directory, records_tx));
+
+    let feeds: HashMap<Exchange, WebSocketPriceFeed> = Exchange::ALL
+        .into_iter()
+        .map(|exchange| (exchange, feed(exchange, &config)))
+        .collect();
+    let supply_provider = supply::provider(&config.supply);
+    let supply_ids = config.instruments.supply_ids(config.supply.provider);
+    let mut connections = HashMap::new();
+    let mut started: Option<(u64, Instant)> = None;
+    let mut replayed = 0;
+    tracing::info!("replay started: {:?}", config.replay.speed);
+    while let Some((origin, record)) = records_rx.recv().await {
+        let (first_received, start) = *started.get_or_insert((record.received, Instant::now()));
+        let elapsed = Duration::from_millis(record.received.saturating_sub(first_received));
+        if let ReplaySpeed::Factor(factor) = config.replay.speed {
+            tokio::time::sleep_until(start + elapsed.div_f64(factor)).await;
+        }
+
+        match origin {
+            Origin::Exchange(exchange) => {
+                let connections = connections.entry(exchange).or_default();
+                let time = start + elapsed;
+                feeds[&exchange]
+                    .replay(record, time, &config.feeds, &mpsc_tx, connections)
+                    .await;
+            }
+            Origin::Supply => match record.frame {
+                Frame::Supply(response) => {
+                    supply_provider
+                        .handle_response(&response, &supply_ids, &mpsc_tx)
+                        .await;
+                }
+                frame => tracing::warn!("unexpected supply frame: {:?}", frame),
+            },
+        }
+        replayed += 1;
+    }
+    tracing::info!("replay finished: {} records", replayed);
+}
+
+/// This function builds the websocket price feed of an exchange, whose message handlers replay
+/// its captures.
+fn feed(exchange: Exchange, config: &Config) -> WebSocketPriceFeed {
+    let symbols = config.instruments.symbols(exchange);
+    match exchange {
+        Exchange::Binance => binance::feed(&config.feeds, symbols),
+        Exchange::Bitfinex => bitfinex::feed(symbols),
+        Exchange::Bitstamp => bitstamp::feed(symbols),
+        Exchange::Bybit => bybit::feed(symbols),
+        Exchange::Coinbase => coinbase::feed(symbols),
+        Exchange::Gemini => gemini::feed(symbols),
+        Exchange::Htx => htx::feed(symbols),
+        Exchange::Kraken => kraken::feed(&config.feeds, symbols),
+        Exchange::Okx => okx::feed(symbols),
+    }
+}
+
+/// This function is responsible for reading the capture files of a directory, and for sending
+/// their records to the replay in the order of their receive times. The records of an origin
+/// are kept in the order they were written, since they are already ordered by connection.
+fn read_captures(directory: &str, records_tx: Sender<(Origin, Record)>) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) => {
+            tracing::error!("failed to read capture directory: {}: {}", directory, error);
+            return;
+        }
+    };
+
+    // Group the capture files by origin, in the order they were created.
+    let mut files: BTreeMap<Origin, Vec<(u64, u64, PathBuf)>> = BTreeMap::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_file_name)
+        {
+            Some((origin, created, index)) => {
+                files
+                    .entry(origin)
+                    .or_default()
+                    .push((created, index, path));
+            }
+            None => tracing::debug!("ignored file: {}", path.display()),
+        }
+    }
+    let mut readers: Vec<CaptureReader> = files
+        .into_iter()
+        .map(|(origin, mut paths)| {
+            paths.sort();
+            CaptureReader {
+                origin,
+                paths: paths.into_iter().map(|(_, _, path)| path).collect(),
+                lines: None,
+            }
+        })
+        .collect();
+
+    // Merge the records of the origins, by always sending the earliest next record.
+    let mut next: Vec<Option<Record>> = readers.iter_mut().map(CaptureReader::next).collect();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = next
+        .iter()
+        .enumerate()
+        .filter_map(|(index, record)| Some(Reverse((record.as_ref()?.received, index))))
+        .collect();
+    while let Some(Reverse((_, index))) = heap.pop() {
+        let following = readers[index].next();
+        if let Some(record) = &following {
+            heap.push(Reverse((record.received, index)));
+        }
+        let record = match std::mem::replace(&mut next[index], following) {
+            Some(record) => record,
+            None => continue,
+        };
+        if records_tx
+            .blocking_send((readers[index].origin, record))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// This function is a helper to parse the name of a capture file, e.g.
+/// `binance-1673853746151-0.jsonl.gz`, into its origin, creation time, and index.
+fn parse_file_name(name: &str) -> Option<(Origin, u64, u64)> {
+    let mut parts = name.strip_suffix(".jsonl.gz")?.rsplitn(3, '-');
+    let index = parts.next()?.parse().ok()?;
+    let created = parts.next()?.parse().ok()?;
+    let origin = match parts.next()? {
+        SUPPLY => Origin::Supply,
+        name => Origin::Exchange(Exchange::try_from(name.to_string()).ok()?),
+    };
+    Some((origin, created, index))
+}
+
+/// This struct reads the records of the capture files of an origin, one file after the other.
+struct CaptureReader {
+    origin: Origin,
+    paths: VecDeque<PathBuf>,
+    lines: Option<Lines<BufReader<GzDecoder<File>>>>,
+}
+
+impl CaptureReader {
+    /// Read the next record, if any. Unreadable records are skipped, and so is the rest of a file
+    /// that can't be decompressed, e.g. the truncated last file of an interrupted capture.
+    fn next(&mut self) -> Option<Record> {
+        loop {
+            let lines = match &mut self.lines {
+                Some(lines) => lines,
+                None => {
+                    let path = self.paths.pop_front()?;
+                    match File::open(&path) {
+                        Ok(file) => {
+                            tracing::info!("replaying capture: {}", path.display());
+                            let lines = BufReader::new(GzDecoder::new(file)).lines();
+                            self.lines.insert(lines)
+                        }
+                        Err(error) => {
+                            tracing::error!(
+                                "failed to open capture: {}: {}",
+                                path.display(),
+                                error
+                            );
+                            continue;
+                        }
+                    }
+                }
+            };
+            match lines.next() {
+                Some(Ok(line)) => match serde_json::from_str(&line) {
+                    Ok(record) => return Some(record),
+                    Err(error) => {
+                        tracing::warn!("discarded capture record: {:?}: {}", self.origin, error);
+                    }
+                },
+                Some(Err(error)) => {
+                    tracing::warn!("failed to read capture: {:?}: {}", self.origin, error);
+                    self.lines = None;
+                }
+                None => self.lines = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Capture;
+    use crate::engine::{Source, Timestamps};
+    use altusd::Coin;
+
+    #[test]
+    fn capture_file_names() {
+        assert_eq!(
+            parse_file_name("binance-1673853746151-0.jsonl.gz"),
+            Some((Origin::Exchange(Exchange::Binance), 1673853746151, 0))
+        );
+        assert_eq!(
+            parse_file_name("supply-1673853746151-12.jsonl.gz"),
+            Some((Origin::Supply, 1673853746151, 12))
+        );
+        assert_eq!(parse_file_name("ftx-1673853746151-0.jsonl.gz"), None);
+        assert_eq!(parse_file_name("binance-1673853746151-0.jsonl"), None);
+    }
+
+    #[tokio::test]
+    async fn replay_captures() {
+        let directory = std::env::temp_dir().join(format!("altusd-replay-{}", std::process::id()));
+        let mut config = Config::default();
+        config.feeds.capture.directory = Some(directory.display().to_string());
+        config.replay.directory = config.feeds.capture.directory.clone();
+        config.replay.speed = ReplaySpeed::Max;
+
+        // The supply response is captured after the ticker, but received before it.
+        let okx = Capture::new(Exchange::Okx, &config.feeds.capture).unwrap();
+        let ticker = r#"{"arg":{"channel":"tickers","instId":"DOT-USDT"},"data":[{"instType":"SPOT","instId":"DOT-USDT","last":"18.734","lastSz":"12.5","askPx":"18.736","askSz":"410.2","bidPx":"18.733","bidSz":"95.1","open24h":"19.01","high24h":"19.25","low24h":"18.51","sodUtc0":"18.9","sodUtc8":"18.77","volCcy24h":"24803321.51","vol24h":"1320471.43","ts":"1645113600512"}]}"#;
+        okx.record(0, 1645113600500, Frame::Connected);
+        okx.record(0, 1645113600520, Frame::Text(ticker.to_string()));
+        drop(okx);
+        let supply = Capture::supply(&config.feeds.capture).unwrap();
+        let response = r#"{"data":[{"symbol":"DOT","circulating_supply":"1234567.5"}]}"#;
+        supply.record(0, 1645113600510, Frame::Supply(response.to_string()));
+        drop(supply);
+
+        let (mpsc_tx, mut mpsc_rx) = tokio::sync::mpsc::channel(100);
+        run(config, mpsc_tx).await;
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        match mpsc_rx.recv().await {
+            Some(Input::Supply(Coin::DOT, supply)) => assert_eq!(supply, 1234567.5),
+            input => panic!("unexpected input: {:?}", input),
+        }
+        match mpsc_rx.recv().await {
+            Some(Input::Price(Coin::DOT, Exchange::Okx, price, Source::Stream, timestamps)) => {
+                assert_eq!(price, 18.734);
+                let expected = Timestamps {
+                    event: Some(1645113600512),
+                    received: 1645113600520,
+                };
+                assert_eq!(timestamps, expected);
+            }
+            input => panic!("unexpected input: {:?}", input),
+        }
+        assert!(mpsc_rx.recv().await.is_none());
+    }
+}
@@ -1,104 +1,234 @@
+mod coinbase;
+mod coingecko;
+
+use crate::capture::{Capture, Frame};
+use crate::config::{CaptureConfig, Proxy, SupplyApi, SupplyConfig};
 use crate::engine::Input;
 use altusd::Coin;
-use serde::Deserialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::sync::mpsc::Sender;
 
-/// The API endpoint from which we retrieve the circulating supplies.
-const ENDPOINT: &str = "https://www.coinbase.com/api/v2/assets/search";
-
 /// The interval at which we poll the API endpoint.
 const POLL_INTERVAL: Duration = Duration::from_secs(60);
 
-/// This struct represents the top-level JSON object returned by the API endpoint.
-/// Irrelevant fields are omitted.
-#[derive(Deserialize)]
-struct ApiResponse {
-    data: Vec<ApiResponseData>,
+/// This struct represents a generic provider of the circulating supplies, i.e. a REST API that
+/// serves the supplies of all our index's altcoins in a single response.
+/// Each supported API builds one from its endpoint, its query, and its response handler.
+pub struct SupplyProvider {
+    pub api: SupplyApi,
+    /// The URL of the API endpoint.
+    pub endpoint: String,
+    /// This function builds the query parameters of a request from the identifiers of the
+    /// altcoins at the provider.
+    pub query: fn(&[&str]) -> Vec<(&'static str, String)>,
+    /// This function extracts the circulating supply of each identifier from a response. It
+    /// returns `None` if the response can't be parsed.
+    pub handler: fn(&str) -> Option<HashMap<String, f64>>,
 }
 
-/// This struct represents the JSON object of an element in the `data` array of the API response.
-/// Irrelevant fields are omitted.
-#[derive(Deserialize)]
-struct ApiResponseData {
-    symbol: String,
-    circulating_supply: String,
+/// This function builds the supply provider selected in the config, whose endpoint is on the
+/// overridden origin if any.
+pub fn provider(config: &SupplyConfig) -> SupplyProvider {
+    let mut provider = match config.provider {
+        SupplyApi::Coinbase => coinbase::provider(),
+        SupplyApi::Coingecko => coingecko::provider(),
+    };
+    provider.endpoint = crate::config::rebase(&provider.endpoint, config.rest.as_deref());
+    provider
 }
 
 /// This function is responsible for feeding the current circulating supply of our index's
-/// altcoins to the core engine. It does that by polling a Coinbase API endpoint every minute.
-pub async fn run(mpsc_tx: Sender<Input>) {
+/// altcoins to the core engine. It does that by polling the API of the configured provider
+/// every minute. The altcoins are found in the response by their identifiers at the provider,
+/// which come from the instrument registry.
+///
+/// The responses are captured along with the exchange frames, if the capture is enabled, and the
+/// API is polled through the proxy of the feeds, if any.
+pub async fn run(
+    config: SupplyConfig,
+    capture: CaptureConfig,
+    proxy: Option<Proxy>,
+    supply_ids: Vec<(Coin, String)>,
+    mpsc_tx: Sender<Input>,
+) {
+    let provider = provider(&config);
+    let capture = Capture::supply(&capture);
+    let client = match crate::proxy::client(reqwest::Client::builder(), proxy.as_ref()) {
+        Ok(client) => client,
+        Err(error) => {
+            tracing::error!("failed to build supply api client: {}", error);
+            return;
+        }
+    };
     loop {
-        poll_api_endpoint(&mpsc_tx).await;
+        provider
+            .poll(&client, &supply_ids, capture.as_ref(), &mpsc_tx)
+            .await;
         tokio::time::sleep(POLL_INTERVAL).await;
     }
 }
 
-/// This function is responsible for polling the API endpoint once and for trying to extract
-/// the circulating supplies of all the altcoins in the index from the response.
-async fn poll_api_endpoint(mpsc_tx: &Sender<Input>) {
-    // Send a GET request.
-    let response = match reqwest::get(ENDPOINT).await {
-        Ok(response) => response,
-        Err(error) => {
-            tracing::error!("failed to poll api endpoint: {}", error);
-            return;
+impl SupplyProvider {
+    /// This function is responsible for polling the API endpoint once and for trying to extract
+    /// the circulating supplies of all the altcoins in the index from the response.
+    async fn poll(
+        &self,
+        client: &reqwest::Client,
+        supply_ids: &[(Coin, String)],
+        capture: Option<&Capture>,
+        mpsc_tx: &Sender<Input>,
+    ) {
+        // Send a GET request and download the response body, unless the API answered with an
+        // error status, e.g. when it's rate limited, whose body isn't a response to capture.
+        let ids: Vec<&str> = supply_ids.iter().map(|(_, id)| id.as_str()).collect();
+        let request = client.get(&self.endpoint).query(&(self.query)(&ids));
+        let response = match request.send().await {
+            Ok(response) => match response.error_for_status() {
+                Ok(response) => response.text().await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                tracing::error!("failed to poll api endpoint: {:?}: {}", self.api, error);
+                return;
+            }
+        };
+        if let Some(capture) = capture {
+            let received = crate::engine::unix_millis().unwrap_or_default();
+            capture.record(0, received, Frame::Supply(response.clone()));
         }
-    };
+        self.handle_response(&response, supply_ids, mpsc_tx).await;
+    }
 
-    // Download the response body and try to parse it into an `ApiResponse`.
-    let response = match response.json::<ApiResponse>().await {
-        Ok(response) => response,
-        Err(error) => {
-            tracing::error!("failed to parse api response: {}", error);
-            return;
-        }
-    };
+    /// This function is responsible for trying to extract the circulating supplies of all the
+    /// altcoins in the index from a response of the API endpoint, which may also be replayed,
+    /// and for sending them to the core engine.
+    pub async fn handle_response(
+        &self,
+        response: &str,
+        supply_ids: &[(Coin, String)],
+        mpsc_tx: &Sender<Input>,
+    ) {
+        let supplies = match (self.handler)(response) {
+            Some(supplies) => supplies,
+            None => {
+                tracing::error!("failed to parse api response: {:?}", self.api);
+                return;
+            }
+        };
 
-    // Try to extract the circulating supply of each coin.
-    try_extract_for_coin(mpsc_tx, &response, Coin::ADA).await;
-    try_extract_for_coin(mpsc_tx, &response, Coin::DOGE).await;
-    try_extract_for_coin(mpsc_tx, &response, Coin::DOT).await;
-    try_extract_for_coin(mpsc_tx, &response, Coin::ETH).await;
-    try_extract_for_coin(mpsc_tx, &response, Coin::SOL).await;
+        for (coin, supply_id) in supply_ids {
+            let supply = match supplies.get(supply_id) {
+                Some(supply) => *supply,
+                None => {
+                    tracing::error!("failed to find circulating supply: {}", supply_id);
+                    continue;
+                }
+            };
+            let input = Input::supply(*coin, supply);
+            if let Err(error) = mpsc_tx.send(input).await {
+                tracing::error!("failed to send message in mpsc channel: {}", error);
+            }
+        }
+    }
 }
 
-/// This function is responsible for extracting the circulating supply of a given coin from the
-/// API response and sending it to the core engine.
-async fn try_extract_for_coin(mpsc_tx: &Sender<Input>, response: &ApiResponse, coin: Coin) {
-    let target_symbol = match coin {
-        Coin::ADA => "ADA",
-        Coin::DOGE => "DOGE",
-        Coin::DOT => "DOT",
-        Coin::ETH => "ETH",
-        Coin::SOL => "SOL",
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::Instruments;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
 
-    // Try to find the entry in the `data` array.
-    let entry = match response
-        .data
-        .iter()
-        .find(|entry| entry.symbol == target_symbol)
-    {
-        Some(entry) => entry,
-        None => {
-            tracing::error!("failed to find target symbol: {}", target_symbol);
-            return;
-        }
-    };
+    /// This function is a helper to serve the same response status and body to every HTTP request
+    /// on a local port, as a stand-in for a supply API. It returns its origin, and reports the
+    /// target of every request.
+    async fn serve(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (targets_tx, targets_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0; 1024];
+                let read = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                let target = request.split_whitespace().nth(1).unwrap_or_default();
+                let _ = targets_tx.send(target.to_string());
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (format!("http://{}", addr), targets_rx)
+    }
 
-    // Try to parse the circulating supply as a 64-bit floating-point value.
-    let supply = match entry.circulating_supply.parse::<f64>() {
-        Ok(supply) => supply,
-        Err(error) => {
-            tracing::error!("failed to parse circulating supply: {}", error);
-            return;
+    /// This function is a helper to poll a provider once, selected by the config and served by a
+    /// stand-in, and to collect the circulating supplies sent to the engine.
+    async fn poll(
+        provider: SupplyApi,
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, Vec<(Coin, f64)>) {
+        let (origin, mut targets_rx) = serve(status, body).await;
+        let config = SupplyConfig {
+            provider,
+            rest: Some(origin),
+        };
+        let supply_ids = Instruments::default().supply_ids(provider);
+        let (mpsc_tx, mut mpsc_rx) = mpsc::channel(10);
+        let client = reqwest::Client::new();
+        self::provider(&config)
+            .poll(&client, &supply_ids, None, &mpsc_tx)
+            .await;
+        drop(mpsc_tx);
+
+        let mut supplies = Vec::new();
+        while let Some(input) = mpsc_rx.recv().await {
+            match input {
+                Input::Supply(coin, supply) => supplies.push((coin, supply)),
+                input => panic!("unexpected input: {:?}", input),
+            }
         }
-    };
+        (targets_rx.recv().await.unwrap(), supplies)
+    }
+
+    #[tokio::test]
+    async fn poll_coinbase() {
+        let body = r#"{"pagination":{"total":2},"data":[{"id":"d85dce9b-5b73-5c3c-8978-522ce1d1c1b4","symbol":"ETH","name":"Ethereum","circulating_supply":"120262034.5"},{"id":"4f039497-3af8-5bb3-951c-6df9afa9be1c","symbol":"SOL","name":"Solana","circulating_supply":"433127418.1"}]}"#;
+        let (target, supplies) = poll(SupplyApi::Coinbase, "200 OK", body).await;
+        assert_eq!(target, "/api/v2/assets/search");
+        let expected = vec![(Coin::ETH, 120262034.5), (Coin::SOL, 433127418.1)];
+        assert_eq!(supplies, expected);
+    }
+
+    #[tokio::test]
+    async fn poll_coingecko() {
+        let body = r#"[{"id":"ethereum","symbol":"eth","name":"Ethereum","current_price":3012.5,"market_cap":362285023190,"circulating_supply":120262034.5,"total_supply":120262034.5},{"id":"cardano","symbol":"ada","name":"Cardano","current_price":0.351,"market_cap":12335082301,"circulating_supply":35143062580.0,"total_supply":45000000000.0}]"#;
+        let (target, supplies) = poll(SupplyApi::Coingecko, "200 OK", body).await;
+        let query = "vs_currency=usd&ids=cardano%2Cdogecoin%2Cpolkadot%2Cethereum%2Csolana";
+        assert_eq!(target, format!("/api/v3/coins/markets?{}", query));
+        let expected = vec![(Coin::ADA, 35143062580.0), (Coin::ETH, 120262034.5)];
+        assert_eq!(supplies, expected);
+    }
 
-    // Send the circulating supply to the core engine.
-    let input = Input::supply(coin, supply);
-    if let Err(error) = mpsc_tx.send(input).await {
-        tracing::error!("failed to send message in mpsc channel: {}", error);
+    #[tokio::test]
+    async fn poll_error_status() {
+        // The body of a rate-limited response is discarded even if it could be parsed.
+        let body = r#"{"pagination":{"total":1},"data":[{"id":"d85dce9b-5b73-5c3c-8978-522ce1d1c1b4","symbol":"ETH","name":"Ethereum","circulating_supply":"120262034.5"}]}"#;
+        let (_, supplies) = poll(SupplyApi::Coinbase, "429 Too Many Requests", body).await;
+        assert_eq!(supplies, vec![]);
     }
 }
@@ -0,0 +1,76 @@
+use super::SupplyProvider;
+use crate::config::SupplyApi;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The API endpoint from which we retrieve the circulating supplies. It isn't officially
+/// supported, so it may change without notice.
+const ENDPOINT: &str = "https://www.coinbase.com/api/v2/assets/search";
+
+/// This struct represents the top-level JSON object returned by the API endpoint.
+/// Irrelevant fields are omitted.
+#[derive(Deserialize)]
+struct ApiResponse {
+    data: Vec<ApiResponseData>,
+}
+
+/// This struct represents the JSON object of an element in the `data` array of the API response.
+/// Irrelevant fields are omitted.
+#[derive(Deserialize)]
+struct ApiResponseData {
+    symbol: String,
+    circulating_supply: String,
+}
+
+/// This function builds the Coinbase supply provider, whose asset search returns the supplies of
+/// all the assets at once. The altcoins are identified by their symbols, e.g. `ETH`.
+pub fn provider() -> SupplyProvider {
+    SupplyProvider {
+        api: SupplyApi::Coinbase,
+        endpoint: ENDPOINT.to_string(),
+        query: |_| Vec::new(),
+        handler,
+    }
+}
+
+/// This function extracts the circulating supply of each symbol from a response. The supplies
+/// that can't be parsed are skipped.
+fn handler(response: &str) -> Option<HashMap<String, f64>> {
+    let response = match serde_json::from_str::<ApiResponse>(response) {
+        Ok(response) => response,
+        Err(error) => {
+            tracing::error!("failed to parse coinbase response: {}", error);
+            return None;
+        }
+    };
+
+    let mut supplies = HashMap::new();
+    for entry in response.data {
+        match entry.circulating_supply.parse::<f64>() {
+            Ok(supply) => {
+                supplies.insert(entry.symbol, supply);
+            }
+            Err(error) => {
+                tracing::debug!(
+                    "failed to parse circulating supply: {}: {}",
+                    entry.symbol,
+                    error
+                );
+            }
+        }
+    }
+    Some(supplies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coinbase_supplies() {
+        let response = r#"{"data":[{"symbol":"DOT","circulating_supply":"1234567.5"},{"symbol":"XYZ","circulating_supply":""}]}"#;
+        let expected = HashMap::from([("DOT".to_string(), 1234567.5)]);
+        assert_eq!(handler(response), Some(expected));
+        assert_eq!(handler(r#"{"errors":[]}"#), None);
+    }
+}
@@ -0,0 +1,58 @@
+use super::SupplyProvider;
+use crate::config::SupplyApi;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The API endpoint from which we retrieve the circulating supplies. See this link for reference:
+/// https://docs.coingecko.com/reference/coins-markets
+const ENDPOINT: &str = "https://api.coingecko.com/api/v3/coins/markets";
+
+/// This struct represents the JSON object of an element in the array returned by the API
+/// endpoint. Irrelevant fields are omitted.
+#[derive(Deserialize)]
+struct Market {
+    id: String,
+    /// The circulating supply, which is null if CoinGecko doesn't know it.
+    circulating_supply: Option<f64>,
+}
+
+/// This function builds the CoinGecko supply provider, whose markets are requested for the
+/// altcoins of the index only. The altcoins are identified by their API IDs, e.g. `ethereum`.
+pub fn provider() -> SupplyProvider {
+    SupplyProvider {
+        api: SupplyApi::Coingecko,
+        endpoint: ENDPOINT.to_string(),
+        query: |ids| vec![("vs_currency", "usd".to_string()), ("ids", ids.join(","))],
+        handler,
+    }
+}
+
+/// This function extracts the circulating supply of each API ID from a response. The unknown
+/// supplies are skipped.
+fn handler(response: &str) -> Option<HashMap<String, f64>> {
+    let markets = match serde_json::from_str::<Vec<Market>>(response) {
+        Ok(markets) => markets,
+        Err(error) => {
+            tracing::error!("failed to parse coingecko response: {}", error);
+            return None;
+        }
+    };
+    let supplies = markets
+        .into_iter()
+        .filter_map(|market| Some((market.id, market.circulating_supply?)))
+        .collect();
+    Some(supplies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coingecko_supplies() {
+        let response = r#"[{"id":"polkadot","symbol":"dot","name":"Polkadot","current_price":6.02,"circulating_supply":1234567.5},{"id":"xyz","symbol":"xyz","name":"XYZ","current_price":1.0,"circulating_supply":null}]"#;
+        let expected = HashMap::from([("polkadot".to_string(), 1234567.5)]);
+        assert_eq!(handler(response), Some(expected));
+        assert_eq!(handler(r#"{"status":{"error_code":429}}"#), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// This struct represents the volume-weighted average price of the trades of a market over a
+/// rolling time window. Unlike the last price, a single small trade barely moves it.
+///
+/// The trades are timestamped when they are received, and expire once they fall out of the
+/// window. The sums are kept up to date so that the VWAP is computed in constant time.
+#[derive(Debug)]
+pub struct RollingVwap {
+    window: Duration,
+    /// The receive time, price, and quantity of each trade in the window, oldest first.
+    trades: VecDeque<(Instant, f64, f64)>,
+    notional: f64,
+    volume: f64,
+}
+
+impl RollingVwap {
+    /// Default constructor. The VWAP is unknown until a trade gets recorded.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            trades: VecDeque::new(),
+            notional: 0.0,
+            volume: 0.0,
+        }
+    }
+
+    /// Record a trade received at the given time, and expire the trades that fell out of the
+    /// window since then.
+    pub fn record(&mut self, time: Instant, price: f64, quantity: f64) {
+        self.trades.push_back((time, price, quantity));
+        self.notional += price * quantity;
+        self.volume += quantity;
+        self.expire(time);
+    }
+
+    /// Get the VWAP of the trades in the window at the given time, or `None` if they have no
+    /// volume. The trades that fell out of the window are expired first, so that the VWAP rolls
+    /// even when the market stops trading.
+    pub fn vwap(&mut self, now: Instant) -> Option<f64> {
+        self.expire(now);
+        if self.volume > 0.0 {
+            Some(self.notional / self.volume)
+        } else {
+            None
+        }
+    }
+
+    /// Remove the trades that are older than the window. The sums are reset once the window is
+    /// empty, so that rounding errors don't accumulate over time.
+    fn expire(&mut self, now: Instant) {
+        while let Some((time, price, quantity)) = self.trades.front() {
+            if now.saturating_duration_since(*time) <= self.window {
+                break;
+            }
+            self.notional -= price * quantity;
+            self.volume -= quantity;
+            self.trades.pop_front();
+        }
+        if self.trades.is_empty() {
+            self.notional = 0.0;
+            self.volume = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_window() {
+        let start = Instant::now();
+        let mut vwap = RollingVwap::new(Duration::from_secs(60));
+        assert_eq!(vwap.vwap(start), None);
+
+        vwap.record(start, 100.0, 3.0);
+        vwap.record(start + Duration::from_secs(30), 110.0, 1.0);
+        assert_eq!(vwap.vwap(start + Duration::from_secs(30)), Some(102.5));
+
+        // The first trade falls out of the window.
+        let time = start + Duration::from_secs(61);
+        vwap.record(time, 120.0, 1.0);
+        assert_eq!(vwap.vwap(time), Some(115.0));
+
+        // A trade without volume doesn't weigh in the VWAP.
+        let time = start + Duration::from_secs(200);
+        vwap.record(time, 130.0, 0.0);
+        assert_eq!(vwap.vwap(time), None);
+    }
+
+    #[test]
+    fn quiet_market() {
+        let start = Instant::now();
+        let mut vwap = RollingVwap::new(Duration::from_secs(60));
+        vwap.record(start, 100.0, 3.0);
+        assert_eq!(vwap.vwap(start + Duration::from_secs(60)), Some(100.0));
+
+        // The window rolls even if no trade is recorded anymore.
+        assert_eq!(vwap.vwap(start + Duration::from_secs(61)), None);
+    }
+}